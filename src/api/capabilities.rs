@@ -0,0 +1,134 @@
+/// What a model accepts, by the first matching model id prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// tokens of the prompt and the reply together
+    pub context_length: usize,
+    /// parameters the server rejects or ignores for this model
+    pub unsupported: &'static [&'static str],
+}
+
+impl Capabilities {
+    pub fn supports(&self, param: &str) -> bool {
+        !self.unsupported.contains(&param)
+    }
+}
+
+/// only the models released since November 2023 take a seed and a response format
+const BEFORE_SEED: &[&str] = &["seed", "json_mode"];
+
+/// More specific prefixes first, extend it as models are released.
+const MODELS: &[(&str, Capabilities)] = &[
+    (
+        "gpt-4-1106",
+        Capabilities {
+            context_length: 128000,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-4-0125",
+        Capabilities {
+            context_length: 128000,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-4-turbo-preview",
+        Capabilities {
+            context_length: 128000,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-4-turbo",
+        Capabilities {
+            context_length: 128000,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-4o",
+        Capabilities {
+            context_length: 128000,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-4-32k",
+        Capabilities {
+            context_length: 32768,
+            unsupported: BEFORE_SEED,
+        },
+    ),
+    (
+        "gpt-4",
+        Capabilities {
+            context_length: 8192,
+            unsupported: BEFORE_SEED,
+        },
+    ),
+    (
+        "gpt-3.5-turbo-1106",
+        Capabilities {
+            context_length: 16385,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-3.5-turbo-0125",
+        Capabilities {
+            context_length: 16385,
+            unsupported: &[],
+        },
+    ),
+    (
+        "gpt-3.5-turbo-16k",
+        Capabilities {
+            context_length: 16384,
+            unsupported: BEFORE_SEED,
+        },
+    ),
+    (
+        "gpt-3.5-turbo-0301",
+        Capabilities {
+            context_length: 4096,
+            unsupported: BEFORE_SEED,
+        },
+    ),
+    (
+        "gpt-3.5-turbo-0613",
+        Capabilities {
+            context_length: 4096,
+            unsupported: BEFORE_SEED,
+        },
+    ),
+];
+
+/// What `model` accepts, everything with a 4096 tokens context for unknown models.
+pub fn capabilities(model: &str) -> Capabilities {
+    MODELS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map_or(
+            Capabilities {
+                context_length: 4096,
+                unsupported: &[],
+            },
+            |(_, capabilities)| *capabilities,
+        )
+}
+
+#[test]
+fn test_capabilities() {
+    assert_eq!(capabilities("gpt-4-32k-0613").context_length, 32768);
+    assert_eq!(capabilities("gpt-4-0613").context_length, 8192);
+    assert!(capabilities("gpt-4-1106-preview").supports("seed"));
+    assert_eq!(capabilities("gpt-4-0125-preview").context_length, 128000);
+    assert!(capabilities("gpt-4-0125-preview").supports("json_mode"));
+    assert_eq!(capabilities("gpt-4-turbo-preview").context_length, 128000);
+    assert_eq!(capabilities("gpt-3.5-turbo-0125").context_length, 16385);
+    assert!(!capabilities("gpt-4").supports("seed"));
+    assert!(capabilities("gpt-3.5-turbo").supports("json_mode"));
+    assert!(!capabilities("gpt-3.5-turbo-16k").supports("json_mode"));
+    assert_eq!(capabilities("my-fine-tune").context_length, 4096);
+}
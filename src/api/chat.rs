@@ -1,21 +1,23 @@
-use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use hyper::{Body, Request, Uri};
 use serde::{Deserialize, Serialize};
 use strum::Display;
 use tokio::task;
 use tracing::instrument;
 
-use crate::client::fetch_sse;
-use crate::client::MultiClient;
+use crate::client::{
+    copy_request, BuildRequestError, ExchangeLog, MultiClient, RateLimits, RetryState,
+    IDEMPOTENCY_KEY,
+};
+use crate::client::{fetch_sse, replay_sse};
 use futures::StreamExt;
 
 use std::cell::RefCell;
-use std::collections::VecDeque;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{atomic, Arc, Mutex};
+use tokio::sync::{watch, RwLock};
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-use super::{Param, Parameter, ParameterControl};
+use super::{capabilities::capabilities, Param, Parameter, ParameterControl, ParameterValue};
 
 /// POST https://api.openai.com/v1/chat/completions
 ///
@@ -73,6 +75,41 @@ pub struct Chat {
     /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far,
     /// decreasing the model's likelihood to repeat the same line verbatim.
     pub frequency_penalty: Option<f32>,
+    /// `string` `Optional`
+    ///
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    pub user: Option<String>,
+    /// `object` `Optional`
+    ///
+    /// An object specifying the format that the model must output.
+    /// Setting to `{ "type": "json_object" }` enables JSON mode,
+    /// which guarantees the message the model generates is valid JSON.
+    pub response_format: Option<ResponseFormat>,
+    /// `integer` `Optional`
+    ///
+    /// If specified, the system will make a best effort to sample deterministically,
+    /// such that repeated requests with the same seed and parameters should return the same result.
+    pub seed: Option<u64>,
+    /// `map` `Optional` `Defaults to null`
+    ///
+    /// Modify the likelihood of specified tokens appearing in the completion.
+    /// Maps token ids to a bias from -100, which bans the token, to 100,
+    /// which makes it the only one generated. Sorted, so the same bias sends
+    /// the same request body, as it is cached.
+    pub logit_bias: Option<BTreeMap<u32, f32>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ResponseFormat {
+    pub r#type: String,
+}
+
+impl ResponseFormat {
+    pub fn json_object() -> Self {
+        Self {
+            r#type: "json_object".to_string(),
+        }
+    }
 }
 #[derive(Deserialize, Serialize, Debug, Display, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -88,7 +125,7 @@ pub struct ResponseChatMessage {
     pub content: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct ChatMessage {
     pub role: Role,
     pub content: String,
@@ -99,6 +136,7 @@ struct ChatCompletion {
     object: Option<String>,
     created: Option<u64>,
     model: Option<String>,
+    system_fingerprint: Option<String>,
     choices: Option<Vec<ChatChoice>>,
     usage: Option<ChatUsage>,
     error: Option<ChatError>,
@@ -111,13 +149,114 @@ struct ChatChoice {
     finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct ChatError {
-    message: String,
-    r#type: String,
-    param: Option<String>,
-    code: Option<String>,
+#[derive(Debug, Clone, Deserialize, Serialize, thiserror::Error)]
+#[error("{message}")]
+pub struct ChatError {
+    pub message: String,
+    pub r#type: String,
+    pub param: Option<String>,
+    pub code: Option<String>,
+}
+
+/// What the user can do about a [`ChatError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    UpdateKey,
+    CheckBilling,
+    TrimConversation,
+    Retry,
+}
+
+impl ChatError {
+    /// The key cannot be put in a header, e.g. a newline pasted with it. Not
+    /// sent to the server, which would reject it as an `invalid_api_key`.
+    fn invalid_key_format() -> Self {
+        Self {
+            message: "Invalid API key format".to_string(),
+            r#type: "invalid_request_error".to_string(),
+            param: None,
+            code: Some("invalid_api_key_format".to_string()),
+        }
+    }
+    /// A friendly explanation and the suggested fix for known errors.
+    pub fn hint(&self) -> Option<(&'static str, ErrorAction)> {
+        match (self.code.as_deref(), self.r#type.as_str()) {
+            (Some("invalid_api_key"), _) => Some((
+                "Your API key is invalid, open Tuning to update it.",
+                ErrorAction::UpdateKey,
+            )),
+            (Some("invalid_api_key_format"), _) => Some((
+                "Your API key contains characters that cannot be sent, open Tuning to update it.",
+                ErrorAction::UpdateKey,
+            )),
+            (Some("insufficient_quota"), _) | (_, "insufficient_quota") => Some((
+                "You have run out of credits, check your plan and billing details.",
+                ErrorAction::CheckBilling,
+            )),
+            (Some("context_length_exceeded"), _) => Some((
+                "The conversation is too long for this model, remove older messages or start a new one.",
+                ErrorAction::TrimConversation,
+            )),
+            (Some("rate_limit_exceeded"), _) | (_, "requests") => Some((
+                "Too many requests, wait a moment and retry.",
+                ErrorAction::Retry,
+            )),
+            _ => None,
+        }
+    }
+}
+/// Tokens the model accepts for the prompt and the reply together.
+pub fn context_limit(model: &str) -> usize {
+    capabilities(model).context_length
+}
+
+/// A rough token count, about 4 characters per token for latin scripts and one
+/// token per character otherwise.
+#[allow(clippy::manual_div_ceil)] // `usize::div_ceil` is newer than the toolchains we build with
+pub fn estimate_text_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+    (ascii + 3) / 4 + other
+}
+
+/// [`estimate_text_tokens`] of every message, plus the overhead of the message format.
+pub fn estimate_tokens(messages: &VecDeque<ChatMessage>) -> usize {
+    messages
+        .iter()
+        .map(|msg| 4 + estimate_text_tokens(&msg.content))
+        .sum::<usize>()
+        + 3
+}
+
+/// Drop the oldest messages until the estimate fits in `budget`, keeping the
+/// system message and the latest message, returns how many were dropped.
+fn trim_messages(messages: &mut VecDeque<ChatMessage>, budget: usize) -> usize {
+    let system = messages.front().is_some_and(|msg| msg.role == Role::System) as usize;
+    let mut trimmed = 0;
+    while estimate_tokens(messages) > budget && messages.len() > system + 1 {
+        messages.remove(system);
+        trimmed += 1;
+    }
+    trimmed
+}
+
+/// Clear the parameters `data.model` does not support, the server rejects them
+/// even when they were set before switching models.
+fn drop_unsupported(data: &mut Chat) {
+    let capabilities = capabilities(&data.model);
+    if !capabilities.supports("seed") {
+        data.seed = None;
+    }
+    if !capabilities.supports("json_mode") {
+        data.response_format = None;
+    }
 }
+
 #[derive(Debug, Deserialize, Serialize)]
 struct ChatUsage {
     prompt_tokens: u32,
@@ -131,16 +270,43 @@ pub struct ChatAPI {
     api_key: Arc<RwLock<String>>,
 
     pub pending_generate: Arc<RwLock<Option<Result<ResponseChatMessage, anyhow::Error>>>>,
+    /// raw requests and responses, for the developer panel
+    pub exchange_log: ExchangeLog,
+    /// sent instead of the stored temperature, not shared between clones
+    temperature_override: Option<f32>,
+    /// asks to continue the last reply and extends it, not shared between clones
+    continuing: bool,
+    last_cleared: Arc<RwLock<Option<VecDeque<ChatMessage>>>>,
+    /// why the last reply stopped, `"length"` when cut off by `max_tokens`
+    finish_reason: Arc<RwLock<Option<String>>>,
+    /// Cancels the running generation, replaced for every request
+    cancel: Arc<Mutex<CancellationToken>>,
+    /// waiting for a free place among the running generations
+    queued: Arc<atomic::AtomicBool>,
+    /// backend configuration the last reply was generated with
+    system_fingerprint: Arc<RwLock<Option<String>>>,
+    /// drop the oldest messages before generating when over the context window
+    auto_trim: Arc<atomic::AtomicBool>,
+    /// messages dropped by the last automatic trim, until taken
+    trimmed: Arc<atomic::AtomicUsize>,
+    /// the automatic retries of the running request
+    retries: Arc<watch::Sender<Option<RetryState>>>,
+    /// sent after the standard headers, e.g. for a gateway
+    headers: Arc<RwLock<Vec<(String, String)>>>,
+    /// as of the last response, `None` when it did not tell
+    rate_limits: Arc<RwLock<Option<RateLimits>>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ChatAPIBuilder {
     chat: Chat,
     api_key: String,
+    client: Arc<MultiClient>,
+    headers: Vec<(String, String)>,
 }
 
 impl ChatAPIBuilder {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, client: Arc<MultiClient>) -> Self {
         Self {
             chat: Chat {
                 model: ChatAPI::DEFAULT_MODEL.to_string(),
@@ -153,28 +319,61 @@ impl ChatAPIBuilder {
                 max_tokens: None,
                 presence_penalty: Some(0.),
                 frequency_penalty: Some(0.),
+                user: None,
+                response_format: None,
+                seed: None,
+                logit_bias: None,
             },
             api_key,
+            client,
+            headers: Vec::new(),
         }
     }
     pub fn with_data(mut self, chat: Chat) -> Self {
         self.chat = chat;
         self
     }
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+    pub fn with_model(mut self, model: String) -> Self {
+        self.chat.model = model;
+        self
+    }
 
     pub fn build(self) -> ChatAPI {
         ChatAPI {
             data: Arc::new(RwLock::new(self.chat)),
             api_key: Arc::new(RwLock::new(self.api_key)),
-            client: Arc::new(MultiClient::new()),
+            client: self.client,
             pending_generate: Arc::new(RwLock::new(None)),
+            exchange_log: ExchangeLog::default(),
+            temperature_override: None,
+            continuing: false,
+            last_cleared: Arc::new(RwLock::new(None)),
+            finish_reason: Arc::new(RwLock::new(None)),
+            cancel: Arc::new(Mutex::new(CancellationToken::new())),
+            queued: Arc::new(atomic::AtomicBool::new(false)),
+            system_fingerprint: Arc::new(RwLock::new(None)),
+            auto_trim: Arc::new(atomic::AtomicBool::new(false)),
+            trimmed: Arc::new(atomic::AtomicUsize::new(0)),
+            retries: Arc::new(watch::channel(None).0),
+            headers: Arc::new(RwLock::new(self.headers)),
+            rate_limits: Arc::new(RwLock::new(None)),
         }
     }
 }
 
 impl ChatAPI {
     const URL: &'static str = "https://api.openai.com/v1/chat/completions";
-    const DEFAULT_MODEL: &'static str = "gpt-3.5-turbo";
+    pub const DEFAULT_MODEL: &'static str = "gpt-3.5-turbo";
+    const CONTINUE_PROMPT: &'static str =
+        "Continue exactly where you stopped, without repeating anything.";
+    const TITLE_PROMPT: &'static str = "Summarize the conversation into a title of 3 to 5 words. \
+        Reply with the title only, without quotes or a final period.";
+    const SUMMARY_PROMPT: &'static str = "Summarize the conversation so far in a few short \
+        paragraphs. Keep the facts, decisions and open questions needed to continue it.";
 
     pub fn data(&self) -> Chat {
         task::block_in_place(|| self.data.blocking_read().clone())
@@ -182,8 +381,21 @@ impl ChatAPI {
     pub async fn set_model(&mut self, model: String) {
         self.data.write().await.model = model;
     }
+    /// Clear the messages, keeping them until the next clear for [`Self::undo_clear`].
     pub async fn clear_message(&mut self) {
-        self.data.write().await.messages.clear();
+        let messages = std::mem::take(&mut self.data.write().await.messages);
+        *self.last_cleared.write().await = Some(messages);
+    }
+    /// Put back the messages of the last clear, `false` when there is none.
+    pub async fn undo_clear(&mut self) -> bool {
+        let Some(messages) = self.last_cleared.write().await.take() else {
+            return false;
+        };
+        self.data.write().await.messages = messages;
+        true
+    }
+    pub async fn forget_cleared(&self) {
+        self.last_cleared.write().await.take();
     }
     pub async fn set_system_message(&self, system_message: Option<String>) {
         let mut data = self.data.write().await;
@@ -215,23 +427,58 @@ impl ChatAPI {
         }
         None
     }
+    /// An independent conversation with the messages up to and including `index`.
+    pub fn fork(&self, index: usize) -> Self {
+        let mut chat = self.data();
+        chat.messages.truncate(index + 1);
+        ChatAPIBuilder::new(self.get_api_key(), self.client.clone())
+            .with_data(chat)
+            .with_headers(self.headers())
+            .build()
+    }
+    pub fn client(&self) -> Arc<MultiClient> {
+        self.client.clone()
+    }
     pub fn get_api_key(&self) -> String {
         tokio::task::block_in_place(|| self.api_key.blocking_read()).clone()
     }
+    /// The key itself, for the other requests made for this conversation.
+    pub fn shared_api_key(&self) -> Arc<RwLock<String>> {
+        self.api_key.clone()
+    }
+    /// Stray whitespace, e.g. the newline of a pasted key, is trimmed.
     pub async fn set_api_key(&self, api_key: String) {
-        *self.api_key.write().await = api_key;
+        *self.api_key.write().await = api_key.trim().to_string();
+    }
+    /// Checked when sending, see [`crate::client::custom_headers`].
+    pub async fn set_headers(&self, headers: Vec<(String, String)>) {
+        *self.headers.write().await = headers;
+    }
+    pub fn headers(&self) -> Vec<(String, String)> {
+        tokio::task::block_in_place(|| self.headers.blocking_read()).clone()
+    }
+    /// Only checks that a key is present, not that OpenAI accepts it.
+    pub fn has_valid_key(&self) -> bool {
+        !tokio::task::block_in_place(|| self.api_key.blocking_read())
+            .trim()
+            .is_empty()
     }
 
-    async fn add_message(&mut self, message: ChatMessage) {
+    /// Append a message without generating a reply, e.g. a few-shot example.
+    pub async fn add_message(&mut self, message: ChatMessage) {
         self.data.write().await.messages.push_back(message);
     }
-    pub async fn question(&mut self, question: String) -> Result<(), anyhow::Error> {
+    pub async fn question(
+        &mut self,
+        question: String,
+        token: CancellationToken,
+    ) -> Result<(), anyhow::Error> {
         self.add_message(ChatMessage {
             role: Role::User,
             content: question,
         })
         .await;
-        match self.generate().await {
+        match self.generate(token).await {
             Ok(_) => Ok(()),
             Err(e) => {
                 tracing::error!("Error generating response: {:?}", e);
@@ -240,12 +487,66 @@ impl ChatAPI {
         }?;
         Ok(())
     }
+    /// Drop the oldest message, keeping the system message.
+    pub async fn remove_oldest(&mut self) {
+        let mut data = self.data.write().await;
+        if let Some(index) = data
+            .messages
+            .iter()
+            .position(|msg| msg.role != Role::System)
+        {
+            data.messages.remove(index);
+        }
+    }
     pub async fn remove_last(&mut self) {
         match self.data.write().await.messages.pop_back() {
             Some(v) => tracing::info!("Removed last message: {:?}", v),
             None => tracing::info!("No message to remove"),
         };
     }
+    /// Regenerate the last reply with the temperature shifted by `delta`,
+    /// the stored temperature is left untouched.
+    pub async fn regenerate_with_temperature(
+        &self,
+        delta: f32,
+        token: CancellationToken,
+    ) -> Result<(), anyhow::Error> {
+        let mut chat = self.clone();
+        let temperature = {
+            let mut data = chat.data.write().await;
+            if data
+                .messages
+                .back()
+                .is_some_and(|msg| msg.role == Role::Assistant)
+            {
+                data.messages.pop_back();
+            }
+            // the API defaults to 1 when no temperature is sent
+            data.temperature.unwrap_or(1.)
+        };
+        chat.temperature_override = Some((temperature + delta).clamp(0., 2.));
+        chat.generate(token).await
+    }
+    /// Whether the last reply was cut off by `max_tokens`.
+    pub fn is_truncated(&self) -> bool {
+        tokio::task::block_in_place(|| {
+            self.finish_reason.blocking_read().as_deref() == Some("length")
+        })
+    }
+    /// Extend the last reply, which was cut off, instead of starting a new one.
+    pub async fn continue_reply(&self, token: CancellationToken) -> Result<(), anyhow::Error> {
+        let mut chat = self.clone();
+        chat.continuing = true;
+        chat.generate(token).await
+    }
+    /// The error of the last generation, when it came from the server or the
+    /// API key could not be sent.
+    pub fn get_error(&self) -> Option<ChatError> {
+        tokio::task::block_in_place(|| match self.pending_generate.blocking_read().as_ref() {
+            Some(Err(e)) => e.downcast_ref::<ChatError>().cloned(),
+            _ => None,
+        })
+    }
     pub fn get_generate(&self) -> Option<Result<String, String>> {
         tokio::task::block_in_place(|| {
             let pending_generate = self.pending_generate.blocking_read();
@@ -256,9 +557,83 @@ impl ChatAPI {
             }
         })
     }
-    pub async fn generate(&mut self) -> Result<(), anyhow::Error> {
+    pub fn rate_limits(&self) -> Option<RateLimits> {
+        task::block_in_place(|| self.rate_limits.blocking_read().clone())
+    }
+    /// Changes when the backend serving the model changes, which can change
+    /// replies even with the same seed.
+    pub fn system_fingerprint(&self) -> Option<String> {
+        task::block_in_place(|| self.system_fingerprint.blocking_read().clone())
+    }
+    /// Whether replies are requested as JSON objects.
+    pub fn is_json_mode(&self) -> bool {
+        task::block_in_place(|| {
+            self.data.blocking_read().response_format == Some(ResponseFormat::json_object())
+        })
+    }
+    pub fn set_auto_trim(&self, enabled: bool) {
+        self.auto_trim.store(enabled, atomic::Ordering::Relaxed);
+    }
+    /// Messages dropped by the last automatic trim, once.
+    pub fn take_trimmed(&self) -> usize {
+        self.trimmed.swap(0, atomic::Ordering::Relaxed)
+    }
+    /// Drop the oldest messages until the estimated prompt and `max_tokens` fit
+    /// in `context_limit` tokens, returns how many were dropped.
+    pub async fn trim_to_fit(&self, context_limit: usize) -> usize {
+        let mut data = self.data.write().await;
+        let budget = context_limit.saturating_sub(data.max_tokens.unwrap_or(0) as usize);
+        trim_messages(&mut data.messages, budget)
+    }
+    /// The token [`Self::abort`] cancels from now on, to pass to the next
+    /// generation. Taken before spawning it, so an abort right away is not lost.
+    pub fn start(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.cancel.lock().unwrap() = token.clone();
+        token
+    }
+    /// Stop the running generation, the text received so far is kept as the reply.
+    pub fn abort(&self) {
+        self.cancel.lock().unwrap().cancel();
+    }
+    /// Follows the automatic retries of the requests, `None` unless waiting for one.
+    pub fn subscribe_retries(&self) -> watch::Receiver<Option<RetryState>> {
+        self.retries.subscribe()
+    }
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(atomic::Ordering::Relaxed)
+    }
+    pub async fn generate(&mut self, token: CancellationToken) -> Result<(), anyhow::Error> {
+        if self.auto_trim.load(atomic::Ordering::Relaxed) {
+            let model = self.data.read().await.model.clone();
+            let trimmed = self.trim_to_fit(context_limit(&model)).await;
+            if trimmed > 0 {
+                self.trimmed.store(trimmed, atomic::Ordering::Relaxed);
+            }
+        }
+        self.pending_generate.write().await.take();
+        self.queued.store(true, atomic::Ordering::Relaxed);
+        let permit = tokio::select! {
+            // aborted before it started, e.g. while trimming
+            biased;
+            _ = token.cancelled() => None,
+            permit = self.client.acquire() => Some(permit),
+        };
+        self.queued.store(false, atomic::Ordering::Relaxed);
+        let Some(_permit) = permit else {
+            return Ok(());
+        };
         *self.pending_generate.write().await = Some(Ok(ResponseChatMessage::default()));
-        let mut stream = match self.complete().await {
+        self.finish_reason.write().await.take();
+        let stream = tokio::select! {
+            stream = self.complete() => stream,
+            _ = token.cancelled() => {
+                self.pending_generate.write().await.take();
+                self.retries.send_replace(None);
+                return Ok(());
+            }
+        };
+        let mut stream = match stream {
             Ok(stream) => stream,
             Err(e) => {
                 tracing::error!("Error while generating: {:?}", e);
@@ -266,27 +641,52 @@ impl ChatAPI {
                 return Ok(());
             }
         };
-        while let Some(res) = stream.next().await {
-            let mut pending_generate = self.pending_generate.write().await;
-            let pending_generate = pending_generate.as_mut().unwrap().as_mut().unwrap();
+        loop {
+            // dropping the stream closes the response body
+            let res = tokio::select! {
+                res = stream.next() => res,
+                _ = token.cancelled() => {
+                    tracing::info!("generation aborted");
+                    break;
+                }
+            };
+            let Some(res) = res else {
+                break;
+            };
+            // errors are kept in `pending_generate` for display
             let res = match res {
                 Ok(res) => res,
                 Err(e) => {
                     tracing::error!("Error while generating: {:?}", e);
                     self.pending_generate.write().await.replace(Err(e));
-                    break;
+                    return Ok(());
                 }
             };
-            if let Some(error) = &res.error {
+            if let Some(error) = res.error {
                 tracing::error!("Error message from server: {:?}", error);
-                anyhow::bail!(error.message.clone());
+                self.pending_generate
+                    .write()
+                    .await
+                    .replace(Err(error.into()));
+                return Ok(());
             }
+            if res.system_fingerprint.is_some() {
+                *self.system_fingerprint.write().await = res.system_fingerprint.clone();
+            }
+            let mut pending_generate = self.pending_generate.write().await;
+            let pending_generate = pending_generate.as_mut().unwrap().as_mut().unwrap();
             let Some(choices) = &res.choices else {
                 continue;
             };
             let Some(first_choice) = &choices.first() else{
                 continue;
             };
+            if let Some(finish_reason) = &first_choice.finish_reason {
+                self.finish_reason
+                    .write()
+                    .await
+                    .replace(finish_reason.clone());
+            }
             let message = &first_choice.delta;
             if let Some(role) = &message.role {
                 pending_generate.role.replace(role.clone());
@@ -312,6 +712,17 @@ impl ChatAPI {
         let Some(content) = message.content else{
             anyhow::bail!("content is empty");
         };
+        if self.continuing {
+            let mut data = self.data.write().await;
+            if let Some(last) = data
+                .messages
+                .back_mut()
+                .filter(|msg| msg.role == Role::Assistant)
+            {
+                last.content.push_str(&content);
+                return Ok(());
+            }
+        }
         self.add_message(ChatMessage {
             role: Role::Assistant,
             content,
@@ -320,39 +731,225 @@ impl ChatAPI {
         Ok(())
     }
 
+    /// A short title for the conversation, from its first question and reply.
+    pub async fn suggest_title(&self) -> Result<String, anyhow::Error> {
+        let mut data = self.data.read().await.clone();
+        let first = |role: Role| data.messages.iter().find(|msg| msg.role == role).cloned();
+        let (Some(question), Some(reply)) = (first(Role::User), first(Role::Assistant)) else {
+            anyhow::bail!("no exchange to summarize");
+        };
+        data.messages = VecDeque::from([
+            ChatMessage {
+                role: Role::System,
+                content: Self::TITLE_PROMPT.to_string(),
+            },
+            question,
+            reply,
+        ]);
+        data.max_tokens = Some(16);
+        let title = self.reply_to(data).await?;
+        let title = title
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'' || c == '.')
+            .trim();
+        if title.is_empty() {
+            anyhow::bail!("no title generated");
+        }
+        Ok(title.to_string())
+    }
+
+    /// Replace all but the last `keep_last` messages with a summary of them, the
+    /// system message is kept. The original messages can be put back with
+    /// [`Self::undo_clear`].
+    pub async fn summarize_and_compress(&self, keep_last: usize) -> Result<(), anyhow::Error> {
+        let mut data = self.data.read().await.clone();
+        let original = data.messages.clone();
+        let system = data
+            .messages
+            .front()
+            .is_some_and(|msg| msg.role == Role::System) as usize;
+        let older = data.messages.len().saturating_sub(system + keep_last);
+        if older < 2 {
+            anyhow::bail!("not enough messages to summarize");
+        }
+        data.messages = data
+            .messages
+            .range(system..system + older)
+            .cloned()
+            .collect();
+        data.messages.push_back(ChatMessage {
+            role: Role::User,
+            content: Self::SUMMARY_PROMPT.to_string(),
+        });
+        data.max_tokens = None;
+        let summary = self.reply_to(data).await?;
+        if summary.trim().is_empty() {
+            anyhow::bail!("no summary generated");
+        }
+
+        let mut data = self.data.write().await;
+        // sent, regenerated, edited or deleted while summarizing
+        let summarized = system + older;
+        if data.messages.len() < summarized
+            || !data
+                .messages
+                .range(..summarized)
+                .eq(original.range(..summarized))
+        {
+            anyhow::bail!("the conversation changed while summarizing");
+        }
+        // undo puts back the messages as they are now, replies that came in included
+        *self.last_cleared.write().await = Some(data.messages.clone());
+        data.messages.drain(system..summarized);
+        data.messages.insert(
+            system,
+            ChatMessage {
+                role: Role::System,
+                content: format!("Summary of the earlier conversation:\n{}", summary.trim()),
+            },
+        );
+        Ok(())
+    }
+
+    /// The whole reply to `data`, for requests made on the side of the conversation.
+    async fn reply_to(&self, mut data: Chat) -> Result<String, anyhow::Error> {
+        data.n = Some(1);
+        data.stop = None;
+        // the side prompts ask for plain text, without the bans of the conversation
+        data.response_format = None;
+        data.logit_bias = None;
+        drop_unsupported(&mut data);
+        let _permit = self.client.acquire().await;
+        let mut stream = self.request(&data).await?;
+        let mut reply = String::new();
+        while let Some(res) = stream.next().await {
+            let res = res?;
+            if let Some(error) = res.error {
+                return Err(error.into());
+            }
+            if let Some(content) = res
+                .choices
+                .as_ref()
+                .and_then(|choices| choices.first())
+                .and_then(|choice| choice.delta.content.as_ref())
+            {
+                reply.push_str(content);
+            }
+        }
+        Ok(reply)
+    }
+
     #[instrument(skip(self))]
     async fn complete(
         &self,
     ) -> Result<impl Stream<Item = Result<ChatCompletion, anyhow::Error>>, anyhow::Error> {
-        let uri: Uri = Self::URL.parse()?;
-
-        let body = Body::from(serde_json::to_string(&self.data.write().await.clone())?);
+        let data = self.build_request_body(self.data.read().await.clone());
+        self.request(&data).await
+    }
 
-        let mut request_body = Request::new(body);
+    /// `data` as [`Self::complete`] sends it, and as a dry run shows it.
+    pub fn build_request_body(&self, mut data: Chat) -> Chat {
+        if let Some(temperature) = self.temperature_override {
+            data.temperature = Some(temperature);
+        }
+        if self.continuing {
+            data.messages.push_back(ChatMessage {
+                role: Role::User,
+                content: Self::CONTINUE_PROMPT.to_string(),
+            });
+        }
+        drop_unsupported(&mut data);
+        data
+    }
 
-        *request_body.method_mut() = hyper::Method::POST;
-        *request_body.uri_mut() = uri.clone();
+    /// The request the next reply is generated with, after `question` when
+    /// given, pretty printed, with the API key and the custom header values
+    /// redacted. Nothing is sent.
+    pub fn request_json(&self, question: Option<String>) -> Result<String, serde_json::Error> {
+        let mut data = self.data();
+        if let Some(question) = question {
+            data.messages.push_back(ChatMessage {
+                role: Role::User,
+                content: question,
+            });
+        }
+        let body = self.build_request_body(data);
+        let mut headers = serde_json::json!({
+            "Content-Type": "application/json",
+            "Authorization": "Bearer <redacted>",
+        });
+        if self.client.sends_idempotency_keys() {
+            headers[IDEMPOTENCY_KEY.as_str()] = "<new for each request>".into();
+        }
+        for (name, _) in self.headers() {
+            let name = name.trim();
+            if let Some(headers) = headers.as_object_mut() {
+                headers.retain(|standard, _| !standard.eq_ignore_ascii_case(name));
+            }
+            headers[name] = "<redacted>".into();
+        }
+        serde_json::to_string_pretty(&serde_json::json!({
+            "url": Self::URL,
+            "method": "POST",
+            "headers": headers,
+            "body": body,
+        }))
+    }
 
-        request_body
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    async fn request(
+        &self,
+        data: &Chat,
+    ) -> Result<impl Stream<Item = Result<ChatCompletion, anyhow::Error>>, anyhow::Error> {
+        let body = serde_json::to_string(data)?;
+        self.exchange_log.begin(&body);
+        let cache = self.client.cache();
+        // only a temperature of 0 gives the same reply every time
+        let cacheable = cache.is_enabled() && data.temperature == Some(0.);
+        if cacheable {
+            if let Some(events) = cache.get(&body) {
+                tracing::info!("replaying a cached response");
+                return Ok(replay_sse(events, Some(self.exchange_log.clone())));
+            }
+        }
+        let cache = cacheable.then(|| (cache, body.clone()));
+        let request = self
+            .client
+            .build_request(
+                Self::URL,
+                &self.api_key.read().await,
+                &self.headers.read().await,
+                &body,
+            )
+            .map_err(|e| match e {
+                BuildRequestError::InvalidKey => ChatError::invalid_key_format().into(),
+                e => anyhow::Error::from(e),
+            })?;
+        // the same idempotency key for every retry, so a reply is not generated and billed twice
+        let build = || copy_request(&request, &body);
 
-        request_body.headers_mut().insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", self.api_key.read().await))?,
+        let response = self
+            .client
+            .request_with_retry(build, Some(&self.retries))
+            .await?;
+        // read before the body is handed over, the headers are not streamed
+        *self.rate_limits.write().await = RateLimits::from_headers(response.headers());
+        let stream = fetch_sse(
+            response,
+            Some(self.exchange_log.clone()),
+            cache,
+            self.client.stall_timeout(),
         );
-
-        let response = self.client.request(request_body).await?;
-        let stream = fetch_sse::<ChatCompletion>(response);
         Ok(stream)
     }
 }
 impl ParameterControl for ChatAPI {
     fn params(&self) -> Vec<Box<dyn super::Parameter>> {
         let mut v = Vec::new();
+        let context_limit =
+            tokio::task::block_in_place(|| context_limit(&self.data.blocking_read().model));
         v.push(Box::new(Param {
             name: "max_tokens",
-            range: Some((1, 2048).into()),
+            range: Some((1, context_limit as u32).into()),
             store: RefCell::new(tokio::task::block_in_place(|| {
                 self.data.blocking_read().max_tokens
             })),
@@ -375,7 +972,9 @@ impl ParameterControl for ChatAPI {
             name: "temperature",
             range: Some((0., 2.).into()),
             default: (0.3).into(),
-            store: RefCell::new(0.3),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().temperature.unwrap_or(0.3)
+            })),
             getter: {
                 let data = self.data.clone();
                 Box::new(move || {
@@ -394,9 +993,11 @@ impl ParameterControl for ChatAPI {
         }));
         v.push(Box::new(Param {
             name: "top_p",
-            range: Some((0., 2.).into()),
+            range: Some((0., 1.).into()),
             default: (1.).into(),
-            store: RefCell::new(1.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().top_p.unwrap_or(1.)
+            })),
             getter: {
                 let data = self.data.clone();
                 Box::new(move || {
@@ -417,7 +1018,9 @@ impl ParameterControl for ChatAPI {
             name: "presence_penalty",
             range: Some((-2., 2.).into()),
             default: (0.).into(),
-            store: RefCell::new(0.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().presence_penalty.unwrap_or(0.)
+            })),
             getter: {
                 let data = self.data.clone();
                 Box::new(move || {
@@ -440,7 +1043,9 @@ impl ParameterControl for ChatAPI {
             name: "frequency_penalty",
             range: Some((-2., 2.).into()),
             default: (0.).into(),
-            store: RefCell::new(0.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().frequency_penalty.unwrap_or(0.)
+            })),
             getter: {
                 let data = self.data.clone();
                 Box::new(move || {
@@ -478,6 +1083,88 @@ impl ParameterControl for ChatAPI {
                 })
             },
         }));
+        v.push(Box::new(Param {
+            name: "seed",
+            range: None,
+            default: ParameterValue::Long(0),
+            store: RefCell::new(None),
+            getter: {
+                let data = self.data.clone();
+                Box::new(move || tokio::task::block_in_place(|| data.blocking_read().seed))
+            },
+            setter: {
+                let data = self.data.clone();
+                Box::new(move |seed: Option<u64>| {
+                    let data = data.clone();
+                    tokio::spawn(async move {
+                        data.write().await.seed = seed;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param {
+            name: "json_mode",
+            range: None,
+            default: false.into(),
+            store: RefCell::new(false),
+            getter: {
+                let _self = self.clone();
+                Box::new(move || _self.is_json_mode())
+            },
+            setter: {
+                let data = self.data.clone();
+                Box::new(move |json_mode| {
+                    let data = data.clone();
+                    tokio::spawn(async move {
+                        data.write().await.response_format =
+                            json_mode.then(ResponseFormat::json_object);
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param {
+            name: "user",
+            range: None,
+            default: None::<String>.into(),
+            store: RefCell::new(None),
+            getter: {
+                let data = self.data.clone();
+                Box::new(move || tokio::task::block_in_place(|| data.blocking_read().user.clone()))
+            },
+            setter: {
+                let data = self.data.clone();
+                Box::new(move |user| {
+                    let data = data.clone();
+                    tokio::spawn(async move {
+                        data.write().await.user = user;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param {
+            name: "logit_bias",
+            range: Some((-100., 100.).into()),
+            default: BTreeMap::new().into(),
+            store: RefCell::new(BTreeMap::new()),
+            getter: {
+                let data = self.data.clone();
+                Box::new(move || {
+                    tokio::task::block_in_place(|| {
+                        data.blocking_read().logit_bias.clone().unwrap_or_default()
+                    })
+                })
+            },
+            setter: {
+                let data = self.data.clone();
+                Box::new(move |logit_bias: BTreeMap<u32, f32>| {
+                    let data = data.clone();
+                    tokio::spawn(async move {
+                        data.write().await.logit_bias =
+                            (!logit_bias.is_empty()).then_some(logit_bias);
+                    });
+                })
+            },
+        }));
         v.push(Box::new(Param::<String> {
             name: "api_key",
             range: None,
@@ -497,6 +1184,226 @@ impl ParameterControl for ChatAPI {
                 })
             },
         }));
+        v.push(Box::new(Param::<Vec<(String, String)>> {
+            name: "headers",
+            range: None,
+            default: Vec::new().into(),
+            store: RefCell::new(self.headers()),
+            getter: {
+                let _self = self.clone();
+                Box::new(move || _self.headers())
+            },
+            setter: {
+                let _self = self.clone();
+                Box::new(move |headers| {
+                    let _self = _self.clone();
+                    tokio::spawn(async move {
+                        _self.set_headers(headers).await;
+                    });
+                })
+            },
+        }));
         v
     }
 }
+
+#[test]
+fn test_trim_messages() {
+    let message = |role, content: &str| ChatMessage {
+        role,
+        content: content.to_string(),
+    };
+    let mut messages = VecDeque::from([
+        message(Role::System, "Be brief."),
+        message(Role::User, &"a".repeat(400)),
+        message(Role::Assistant, &"b".repeat(400)),
+        message(Role::User, "Why?"),
+    ]);
+    assert!(estimate_tokens(&messages) > 200);
+    assert_eq!(trim_messages(&mut messages, 1000), 0);
+    assert_eq!(trim_messages(&mut messages, 120), 1);
+    assert_eq!(messages[1].role, Role::Assistant);
+    assert_eq!(trim_messages(&mut messages, 0), 1);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].role, Role::System);
+    assert_eq!(messages[1].content, "Why?");
+}
+
+#[test]
+fn test_invalid_key_format() {
+    let error = anyhow::Error::from(ChatError::invalid_key_format());
+    let error = error.downcast_ref::<ChatError>().unwrap();
+    assert_eq!(
+        error.hint().map(|(_, action)| action),
+        Some(ErrorAction::UpdateKey)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_params_follow_model() {
+    let max_tokens_range = |chat: &ChatAPI| {
+        chat.params()
+            .iter()
+            .find(|p| p.name() == "max_tokens")
+            .and_then(|p| p.range())
+    };
+    let mut chat = ChatAPIBuilder::new(String::new(), Arc::new(MultiClient::new()))
+        .with_model("gpt-4".to_string())
+        .build();
+    assert!(matches!(
+        max_tokens_range(&chat),
+        Some(super::ParameterRange::Integer(1, 8192))
+    ));
+    chat.set_model("gpt-3.5-turbo-0613".to_string()).await;
+    assert!(matches!(
+        max_tokens_range(&chat),
+        Some(super::ParameterRange::Integer(1, 4096))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_seed_param() {
+    let chat = ChatAPIBuilder::new(String::new(), Arc::new(MultiClient::new())).build();
+    let params = chat.params();
+    let seed = params.iter().find(|p| p.name() == "seed").unwrap();
+    seed.set(ParameterValue::OptionalLong(Some(u64::MAX)));
+    // the setter writes from a spawned task
+    for _ in 0..100 {
+        if chat.data().seed.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(chat.data().seed, Some(u64::MAX));
+    assert!(matches!(
+        seed.get(),
+        ParameterValue::OptionalLong(Some(u64::MAX))
+    ));
+    assert!(matches!(seed.store(), ParameterValue::Long(u64::MAX)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_request_json() {
+    let chat = ChatAPIBuilder::new("sk-secret".to_string(), Arc::new(MultiClient::new()))
+        .with_headers(vec![("Cookie".to_string(), "session=secret".to_string())])
+        .build();
+    let json = chat.request_json(Some("hello".to_string())).unwrap();
+    assert!(json.contains("\"hello\""));
+    assert!(json.contains("Bearer <redacted>"));
+    assert!(json.contains("\"Cookie\": \"<redacted>\""));
+    assert!(!json.contains("secret"));
+    assert!(chat.data().messages.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_generate_stream() {
+    use crate::client::MockClient;
+    // the second event is split in the middle of its JSON and of the blank line
+    let mock = MockClient::default().with_response(
+        hyper::StatusCode::OK,
+        &[
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"cont",
+            "ent\":\"Hello\"}}]}\n",
+            "\ndata: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\" world\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ],
+    );
+    let mut chat =
+        ChatAPIBuilder::new("sk-test".to_string(), Arc::new(MultiClient::mock(mock))).build();
+    chat.question("Hi".to_string(), chat.start()).await.unwrap();
+    let data = chat.data();
+    assert_eq!(data.messages.len(), 2);
+    assert_eq!(data.messages[1].role, Role::Assistant);
+    assert_eq!(data.messages[1].content, "Hello world");
+    assert!(!chat.is_truncated());
+    assert!(chat.get_generate().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_abort_before_generate() {
+    use crate::client::MockClient;
+    // no canned response, a request would panic
+    let mut chat = ChatAPIBuilder::new(
+        "sk-test".to_string(),
+        Arc::new(MultiClient::mock(MockClient::default())),
+    )
+    .build();
+    chat.set_auto_trim(true);
+    let token = chat.start();
+    chat.abort();
+    chat.question("Hi".to_string(), token).await.unwrap();
+    assert_eq!(chat.data().messages.len(), 1);
+    assert!(chat.get_generate().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_title_without_json_mode() {
+    use crate::client::MockClient;
+    let mock = MockClient::default().with_response(
+        hyper::StatusCode::OK,
+        &[
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Greetings\"}}]}\n\n",
+            "data: [DONE]\n\n",
+        ],
+    );
+    let chat = ChatAPIBuilder::new("sk-test".to_string(), Arc::new(MultiClient::mock(mock)))
+        .with_model("gpt-4-1106-preview".to_string())
+        .build();
+    {
+        let mut data = chat.data.write().await;
+        data.response_format = Some(ResponseFormat::json_object());
+        data.logit_bias = Some(BTreeMap::from([(1, -100.)]));
+        data.messages.extend([
+            ChatMessage {
+                role: Role::User,
+                content: "Hi".to_string(),
+            },
+            ChatMessage {
+                role: Role::Assistant,
+                content: "{\"reply\":\"Hello\"}".to_string(),
+            },
+        ]);
+    }
+    chat.exchange_log.set_enabled(true);
+    assert_eq!(chat.suggest_title().await.unwrap(), "Greetings");
+    let request = &chat.exchange_log.exchanges()[0].request;
+    assert!(request.contains("Summarize the conversation into a title"));
+    assert!(!request.contains("response_format"));
+    assert!(!request.contains("logit_bias"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_generate_error() {
+    use crate::client::MockClient;
+    let mock = MockClient::default()
+        .with_response(
+            hyper::StatusCode::OK,
+            &[
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"error\":{\"message\":\"The server had an error\",\"type\":\"server_error\"}}\n\n",
+            ],
+        )
+        // not an event stream, the whole body is the error
+        .with_response(
+            hyper::StatusCode::UNAUTHORIZED,
+            &[
+                "{\n  \"error\": {\n    \"message\": \"Incorrect API key provided\",\n",
+                "    \"type\": \"invalid_request_error\",\n    \"code\": \"invalid_api_key\"\n  }\n}\n",
+            ],
+        );
+    let mut chat =
+        ChatAPIBuilder::new("sk-test".to_string(), Arc::new(MultiClient::mock(mock))).build();
+    chat.question("Hi".to_string(), chat.start()).await.unwrap();
+    assert_eq!(
+        chat.get_generate(),
+        Some(Err("The server had an error".to_string()))
+    );
+    assert_eq!(chat.data().messages.len(), 1);
+    chat.generate(chat.start()).await.unwrap();
+    assert_eq!(
+        chat.get_generate(),
+        Some(Err("Incorrect API key provided".to_string()))
+    );
+    assert_eq!(chat.data().messages.len(), 1);
+}
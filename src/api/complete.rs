@@ -1,25 +1,35 @@
-use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
-use hyper::{Body, Request, Uri};
 use serde::{Deserialize, Serialize};
 use tokio::task;
 
-use crate::client::fetch_sse;
-use crate::client::MultiClient;
+use crate::client::{copy_request, MultiClient};
+use crate::client::{fetch_sse, replay_sse};
 use futures::StreamExt;
 
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{atomic, Arc, Mutex};
 use tokio::sync::RwLock;
 use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-use super::{Param, ParameterControl};
+use super::{Param, ParameterControl, ParameterValue};
 
 #[derive(Debug, Clone)]
 pub struct CompleteAPI {
     pub data: Arc<RwLock<Complete>>,
     pub pending_generate: Arc<RwLock<Option<String>>>,
+    /// tokens of the last generation, with their logprobs when requested
+    tokens: Arc<RwLock<Vec<TokenLogprob>>>,
+    /// why the last generation stopped, `"length"` when cut off by `max_tokens`
+    finish_reason: Arc<RwLock<Option<String>>>,
     api_key: Arc<RwLock<String>>,
     client: Arc<MultiClient>,
+    /// Cancels the running generation, replaced for every request
+    cancel: Arc<Mutex<CancellationToken>>,
+    /// waiting for a free place among the running generations
+    queued: Arc<atomic::AtomicBool>,
+    /// sent after the standard headers, e.g. for a gateway
+    headers: Arc<RwLock<Vec<(String, String)>>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -45,9 +55,49 @@ struct CompleteError {
 struct CompleteChoice {
     text: String,
     index: u32,
-    logprobs: Option<u32>,
+    logprobs: Option<Logprobs>,
     finish_reason: Option<String>,
 }
+
+/// Log probabilities of the generated tokens, when asked for with `logprobs`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Logprobs {
+    pub tokens: Vec<String>,
+    pub token_logprobs: Vec<Option<f32>>,
+    /// the most likely tokens at each position, up to `logprobs` of them
+    pub top_logprobs: Vec<Option<HashMap<String, f32>>>,
+    pub text_offset: Vec<u32>,
+}
+
+impl Logprobs {
+    fn token_logprobs(&self) -> impl Iterator<Item = TokenLogprob> + '_ {
+        self.tokens.iter().enumerate().map(|(i, token)| {
+            let mut top = self
+                .top_logprobs
+                .get(i)
+                .cloned()
+                .flatten()
+                .unwrap_or_default()
+                .into_iter()
+                .collect::<Vec<_>>();
+            top.sort_by(|a, b| b.1.total_cmp(&a.1));
+            TokenLogprob {
+                token: token.clone(),
+                logprob: self.token_logprobs.get(i).copied().flatten(),
+                top,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: Option<f32>,
+    /// the alternatives, most likely first
+    pub top: Vec<(String, f32)>,
+}
 #[derive(Debug, Deserialize, Serialize)]
 struct CompleteUsage {
     prompt_tokens: u32,
@@ -65,10 +115,75 @@ impl CompleteAPI {
     pub async fn set_prompt(&mut self, prompt: String) {
         self.data.write().await.prompt = prompt;
     }
-    pub async fn generate(&self) -> Result<String, anyhow::Error> {
-        let mut stream = self.complete().await?;
+    pub async fn set_api_key(&self, api_key: String) {
+        *self.api_key.write().await = api_key.trim().to_string();
+    }
+    /// Checked when sending, see [`crate::client::custom_headers`].
+    pub async fn set_headers(&self, headers: Vec<(String, String)>) {
+        *self.headers.write().await = headers;
+    }
+    pub fn headers(&self) -> Vec<(String, String)> {
+        task::block_in_place(|| self.headers.blocking_read().clone())
+    }
+    pub fn tokens(&self) -> Vec<TokenLogprob> {
+        task::block_in_place(|| self.tokens.blocking_read().clone())
+    }
+    /// `None` while generating, or when the last generation failed or was aborted.
+    pub fn finish_reason(&self) -> Option<String> {
+        task::block_in_place(|| self.finish_reason.blocking_read().clone())
+    }
+    /// Whether the last generation was cut off by `max_tokens`.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason().as_deref() == Some("length")
+    }
+    /// The token [`Self::abort`] cancels from now on, to pass to the next
+    /// generation. Taken before spawning it, so an abort right away is not lost.
+    pub fn start(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.cancel.lock().unwrap() = token.clone();
+        token
+    }
+    /// Stop the running generation, the text received so far becomes the prompt.
+    pub fn abort(&self) {
+        self.cancel.lock().unwrap().cancel();
+    }
+    pub fn is_queued(&self) -> bool {
+        self.queued.load(atomic::Ordering::Relaxed)
+    }
+    pub async fn generate(&self, token: CancellationToken) -> Result<String, anyhow::Error> {
+        // a failed or aborted request must not show the last reason
+        self.finish_reason.write().await.take();
+        self.queued.store(true, atomic::Ordering::Relaxed);
+        let permit = tokio::select! {
+            // aborted before it started
+            biased;
+            _ = token.cancelled() => None,
+            permit = self.client.acquire() => Some(permit),
+        };
+        self.queued.store(false, atomic::Ordering::Relaxed);
+        let Some(_permit) = permit else {
+            return Err(anyhow::anyhow!("Generation aborted"));
+        };
+        let mut stream = tokio::select! {
+            stream = self.complete() => stream?,
+            _ = token.cancelled() => {
+                return Err(anyhow::anyhow!("Generation aborted"));
+            }
+        };
         *self.pending_generate.write().await = Some(self.data.read().await.prompt.clone());
-        while let Some(res) = stream.next().await {
+        self.tokens.write().await.clear();
+        loop {
+            // dropping the stream closes the response body
+            let res = tokio::select! {
+                res = stream.next() => res,
+                _ = token.cancelled() => {
+                    tracing::info!("generation aborted");
+                    break;
+                }
+            };
+            let Some(res) = res else {
+                break;
+            };
             let res = match res {
                 Ok(s) => s,
                 Err(e) => {
@@ -86,6 +201,15 @@ impl CompleteAPI {
             let Some(first_choice) = &choices.first() else{
                 continue;
             };
+            if let Some(logprobs) = &first_choice.logprobs {
+                self.tokens.write().await.extend(logprobs.token_logprobs());
+            }
+            if let Some(finish_reason) = &first_choice.finish_reason {
+                self.finish_reason
+                    .write()
+                    .await
+                    .replace(finish_reason.clone());
+            }
             let text = &first_choice.text;
             // if text == "\n\n" || text == "\n\n\n" {
             //     continue;
@@ -103,37 +227,62 @@ impl CompleteAPI {
         self.data.write().await.prompt = text.clone();
         Ok(text)
     }
-    pub async fn insert(&self, index: usize) -> Result<String, anyhow::Error> {
-        {
+    /// Generate at the char `index` of the prompt, the prompt is put back together
+    /// when nothing is inserted, e.g. aborted before the first token.
+    pub async fn insert(
+        &self,
+        index: usize,
+        token: CancellationToken,
+    ) -> Result<String, anyhow::Error> {
+        let original = {
             let mut complete = self.data.write().await;
-            let prompt = complete.prompt.clone();
-            let (prompt, suffix) = split_by_char(&prompt, index);
+            // an insert whose task was dropped leaves its suffix behind
+            let mut original = complete.prompt.clone();
+            if let Some(suffix) = complete.suffix.take() {
+                original.push_str(&suffix);
+            }
+            let (prompt, suffix) = split_by_char(&original, index);
             complete.prompt = prompt.to_string();
             complete.suffix = Some(suffix.to_string());
-        }
+            original
+        };
         // tracing::info!(
         //     prompt = complete.prompt,
         //     suffix = complete.suffix.as_ref().unwrap_or(&"".to_string())
         // );
-        Ok(self.generate().await?)
+        let result = self.generate(token).await;
+        if result.is_err() {
+            let mut complete = self.data.write().await;
+            complete.prompt = original;
+            complete.suffix = None;
+        }
+        result
     }
     async fn complete(
         &self,
     ) -> Result<impl Stream<Item = Result<CompleteCompletion, anyhow::Error>>, anyhow::Error> {
-        let uri: Uri = Self::URL.parse()?;
-        let body = Body::from(serde_json::to_string(&self.data.write().await.clone())?);
-        let mut request_body = Request::new(body);
-        *request_body.method_mut() = hyper::Method::POST;
-        *request_body.uri_mut() = uri.clone();
-        request_body
-            .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        request_body.headers_mut().insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", self.api_key.read().await))?,
-        );
-        let response = self.client.request(request_body).await?;
-        let stream = fetch_sse::<CompleteCompletion>(response);
+        let data = self.data.read().await.clone();
+        let body = serde_json::to_string(&data)?;
+        let cache = self.client.cache();
+        // only a temperature of 0 gives the same completion every time
+        let cacheable = cache.is_enabled() && data.temperature == Some(0.);
+        if cacheable {
+            if let Some(events) = cache.get(&body) {
+                tracing::info!("replaying a cached response");
+                return Ok(replay_sse(events, None));
+            }
+        }
+        let cache = cacheable.then(|| (cache, body.clone()));
+        let request = self.client.build_request(
+            Self::URL,
+            &self.api_key.read().await,
+            &self.headers.read().await,
+            &body,
+        )?;
+        // the same idempotency key for every retry
+        let build = || copy_request(&request, &body);
+        let response = self.client.request_with_retry(build, None).await?;
+        let stream = fetch_sse(response, None, cache, self.client.stall_timeout());
         Ok(stream)
     }
 }
@@ -141,10 +290,12 @@ impl CompleteAPI {
 pub struct CompleteAPIBuilder {
     api_key: String,
     complete: Complete,
+    client: Arc<MultiClient>,
+    headers: Vec<(String, String)>,
 }
 
 impl CompleteAPIBuilder {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, client: Arc<MultiClient>) -> Self {
         let complete = Complete {
             model: CompleteAPI::DEFAULT_MODEL.to_string(),
             prompt: "".to_string(),
@@ -158,19 +309,35 @@ impl CompleteAPIBuilder {
             n: None,
             stream: Some(true),
             logprobs: None,
+            user: None,
+            logit_bias: None,
         };
-        Self { api_key, complete }
+        Self {
+            api_key,
+            complete,
+            client,
+            headers: Vec::new(),
+        }
     }
     pub fn with_data(mut self, complete: Complete) -> Self {
         self.complete = complete;
         self
     }
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
     pub fn build(self) -> CompleteAPI {
         CompleteAPI {
             data: Arc::new(RwLock::new(self.complete)),
             pending_generate: Arc::new(RwLock::new(None)),
+            tokens: Arc::new(RwLock::new(Vec::new())),
+            finish_reason: Arc::new(RwLock::new(None)),
             api_key: Arc::new(RwLock::new(self.api_key)),
-            client: Arc::new(MultiClient::new()),
+            client: self.client,
+            cancel: Arc::new(Mutex::new(CancellationToken::new())),
+            queued: Arc::new(atomic::AtomicBool::new(false)),
+            headers: Arc::new(RwLock::new(self.headers)),
         }
     }
 }
@@ -191,15 +358,25 @@ pub struct Complete {
     n: Option<u32>,
     stream: Option<bool>,
     logprobs: Option<u32>,
+    /// identifies the end-user to OpenAI, for abuse monitoring
+    pub user: Option<String>,
+    /// token id to a bias from -100 to 100, sorted to keep the requests cacheable
+    pub logit_bias: Option<BTreeMap<u32, f32>>,
 }
 
-fn split_by_char(string: &str, mid: usize) -> (&str, &str) {
-    let mut len = 0;
-    for ch in string.chars().by_ref().take(mid) {
-        len += ch.len_utf8();
+impl Complete {
+    pub fn model(&self) -> &str {
+        &self.model
     }
+}
 
-    (&string[..len], &string[len..])
+/// Split `string` before the char at `mid`, the whole string is the prompt
+/// when `mid` is at or after the end.
+fn split_by_char(string: &str, mid: usize) -> (&str, &str) {
+    match string.char_indices().nth(mid) {
+        Some((index, _)) => string.split_at(index),
+        None => (string, ""),
+    }
 }
 
 impl ParameterControl for CompleteAPI {
@@ -232,7 +409,9 @@ impl ParameterControl for CompleteAPI {
             name: "temperature",
             range: Some((0., 2.).into()),
             default: (0.3).into(),
-            store: RefCell::new(0.3),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().temperature.unwrap_or(0.3)
+            })),
             getter: {
                 let complete = self.data.clone();
                 Box::new(move || {
@@ -253,9 +432,11 @@ impl ParameterControl for CompleteAPI {
         }));
         v.push(Box::new(Param {
             name: "top_p",
-            range: Some((0., 2.).into()),
+            range: Some((0., 1.).into()),
             default: (1.).into(),
-            store: RefCell::new(1.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().top_p.unwrap_or(1.)
+            })),
             getter: {
                 let complete = self.data.clone();
                 Box::new(move || {
@@ -276,7 +457,9 @@ impl ParameterControl for CompleteAPI {
             name: "presence_penalty",
             range: Some((-2., 2.).into()),
             default: (0.).into(),
-            store: RefCell::new(0.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().presence_penalty.unwrap_or(0.)
+            })),
             getter: {
                 let complete = self.data.clone();
                 Box::new(move || {
@@ -299,7 +482,9 @@ impl ParameterControl for CompleteAPI {
             name: "frequency_penalty",
             range: Some((-2., 2.).into()),
             default: (0.).into(),
-            store: RefCell::new(0.),
+            store: RefCell::new(tokio::task::block_in_place(|| {
+                self.data.blocking_read().frequency_penalty.unwrap_or(0.)
+            })),
             getter: {
                 let complete = self.data.clone();
                 Box::new(move || {
@@ -318,6 +503,91 @@ impl ParameterControl for CompleteAPI {
                 })
             },
         }));
+        v.push(Box::new(Param {
+            name: "logprobs",
+            range: Some((0, 5).into()),
+            default: 1.into(),
+            store: RefCell::new(task::block_in_place(|| self.data.blocking_read().logprobs)),
+            getter: {
+                let complete = self.data.clone();
+                Box::new(move || task::block_in_place(|| complete.blocking_read().logprobs))
+            },
+            setter: {
+                let complete = self.data.clone();
+                Box::new(move |logprobs| {
+                    let complete = complete.clone();
+                    tokio::spawn(async move {
+                        complete.write().await.logprobs = logprobs;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param {
+            name: "user",
+            range: None,
+            default: None::<String>.into(),
+            store: RefCell::new(None),
+            getter: {
+                let complete = self.data.clone();
+                Box::new(move || task::block_in_place(|| complete.blocking_read().user.clone()))
+            },
+            setter: {
+                let complete = self.data.clone();
+                Box::new(move |user| {
+                    let complete = complete.clone();
+                    tokio::spawn(async move {
+                        complete.write().await.user = user;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param::<Vec<String>> {
+            name: "stop",
+            range: None,
+            default: ParameterValue::StringArray(Vec::new()),
+            store: RefCell::new(Vec::new()),
+            getter: {
+                let complete = self.data.clone();
+                Box::new(move || task::block_in_place(|| complete.blocking_read().stop.clone()))
+            },
+            setter: {
+                let complete = self.data.clone();
+                Box::new(move |stop| {
+                    let complete = complete.clone();
+                    tokio::spawn(async move {
+                        complete.write().await.stop = stop;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param {
+            name: "logit_bias",
+            range: Some((-100., 100.).into()),
+            default: BTreeMap::new().into(),
+            store: RefCell::new(BTreeMap::new()),
+            getter: {
+                let complete = self.data.clone();
+                Box::new(move || {
+                    task::block_in_place(|| {
+                        complete
+                            .blocking_read()
+                            .logit_bias
+                            .clone()
+                            .unwrap_or_default()
+                    })
+                })
+            },
+            setter: {
+                let complete = self.data.clone();
+                Box::new(move |logit_bias: BTreeMap<u32, f32>| {
+                    let complete = complete.clone();
+                    tokio::spawn(async move {
+                        complete.write().await.logit_bias =
+                            (!logit_bias.is_empty()).then_some(logit_bias);
+                    });
+                })
+            },
+        }));
         v.push(Box::new(Param::<String> {
             name: "api_key",
             range: None,
@@ -334,7 +604,26 @@ impl ParameterControl for CompleteAPI {
                 Box::new(move |api_key| {
                     let _self = _self.clone();
                     tokio::spawn(async move {
-                        *_self.api_key.write().await = api_key;
+                        _self.set_api_key(api_key).await;
+                    });
+                })
+            },
+        }));
+        v.push(Box::new(Param::<Vec<(String, String)>> {
+            name: "headers",
+            range: None,
+            default: Vec::new().into(),
+            store: RefCell::new(self.headers()),
+            getter: {
+                let _self = self.clone();
+                Box::new(move || _self.headers())
+            },
+            setter: {
+                let _self = self.clone();
+                Box::new(move |headers| {
+                    let _self = _self.clone();
+                    tokio::spawn(async move {
+                        _self.set_headers(headers).await;
                     });
                 })
             },
@@ -342,3 +631,93 @@ impl ParameterControl for CompleteAPI {
         v
     }
 }
+
+#[test]
+fn test_split_by_char() {
+    assert_eq!(split_by_char("hello", 0), ("", "hello"));
+    assert_eq!(split_by_char("hello", 2), ("he", "llo"));
+    assert_eq!(split_by_char("hello", 5), ("hello", ""));
+    assert_eq!(split_by_char("hello", 100), ("hello", ""));
+    assert_eq!(split_by_char("", 1), ("", ""));
+    assert_eq!(split_by_char("你好世界", 2), ("你好", "世界"));
+    assert_eq!(split_by_char("你好世界", 4), ("你好世界", ""));
+    assert_eq!(split_by_char("a😀b", 1), ("a", "😀b"));
+    assert_eq!(split_by_char("a😀b", 2), ("a😀", "b"));
+    assert_eq!(split_by_char("😀😀", 3), ("😀😀", ""));
+}
+
+#[test]
+fn test_logprobs() {
+    let choice: CompleteChoice = serde_json::from_str(
+        r#"{"text":" Hi","index":0,"finish_reason":null,"logprobs":{
+            "tokens":[" Hi"],"token_logprobs":[-0.5],
+            "top_logprobs":[{" Hello":-1.5," Hi":-0.5}],"text_offset":[12]}}"#,
+    )
+    .unwrap();
+    let tokens = choice
+        .logprobs
+        .unwrap()
+        .token_logprobs()
+        .collect::<Vec<_>>();
+    assert_eq!(tokens[0].token, " Hi");
+    assert_eq!(tokens[0].logprob, Some(-0.5));
+    assert_eq!(tokens[0].top[0].0, " Hi");
+    assert_eq!(tokens[0].top[1].0, " Hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_finish_reason() {
+    use crate::client::MockClient;
+    let mock = MockClient::default().with_response(
+        hyper::StatusCode::OK,
+        &[
+            "data: {\"choices\":[{\"text\":\" Once\",\"index\":0,\"finish_reason\":null}]}\n\n",
+            "data: {\"choices\":[{\"text\":\" upon\",\"index\":0,\"finish_reason\":\"length\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ],
+    )
+    .with_response(
+        hyper::StatusCode::BAD_REQUEST,
+        &[r#"{"error":{"message":"bad request","type":"invalid_request_error"}}"#],
+    );
+    let complete =
+        CompleteAPIBuilder::new("sk-test".to_string(), Arc::new(MultiClient::mock(mock))).build();
+    assert_eq!(complete.finish_reason(), None);
+    assert_eq!(
+        complete.generate(complete.start()).await.unwrap(),
+        " Once upon"
+    );
+    assert_eq!(complete.finish_reason().as_deref(), Some("length"));
+    assert!(complete.is_truncated());
+    // a failed request does not keep the reason of the previous one
+    let _ = complete.generate(complete.start()).await;
+    assert_eq!(complete.finish_reason(), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_abort_before_insert() {
+    use crate::client::MockClient;
+    // no canned response, a request would panic
+    let complete = CompleteAPIBuilder::new(
+        "sk-test".to_string(),
+        Arc::new(MultiClient::mock(MockClient::default())),
+    )
+    .build();
+    complete.data.write().await.prompt = "Once upon".to_string();
+    let token = complete.start();
+    complete.abort();
+    assert!(complete.insert(4, token).await.is_err());
+    let data = complete.data.read().await;
+    assert_eq!(data.prompt, "Once upon");
+    assert_eq!(data.suffix, None);
+}
+
+#[test]
+fn test_stop_round_trip() {
+    let data = r#"{"model":"text-davinci-003","prompt":"","stop":["\n\nQ:"," END "]}"#;
+    let complete: Complete = serde_json::from_str(data).unwrap();
+    assert_eq!(complete.stop, ["\n\nQ:", " END "]);
+    let saved = serde_json::to_string(&complete).unwrap();
+    let complete: Complete = serde_json::from_str(&saved).unwrap();
+    assert_eq!(complete.stop, ["\n\nQ:", " END "]);
+}
@@ -1,8 +1,22 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::BTreeMap};
 
+pub mod capabilities;
 pub mod chat;
 pub mod complete;
 pub mod models;
+#[cfg(feature = "tokenizer")]
+pub mod tokenizer;
+
+/// Whether `key` has the `sk-...` shape of an OpenAI key. Only a hint, the
+/// keys of other gateways are sent all the same.
+pub fn looks_like_api_key(key: &str) -> bool {
+    key.strip_prefix("sk-").is_some_and(|rest| {
+        rest.len() >= 20
+            && rest
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum ParameterRange {
@@ -27,8 +41,22 @@ pub enum ParameterValue {
     String(String),
     OptionalNumber(Option<f32>),
     OptionalInteger(Option<u32>),
+    /// for values past `u32::MAX`, such as seeds
+    Long(u64),
+    OptionalLong(Option<u64>),
     OptionalString(Option<String>),
     StringArray(Vec<String>),
+    Bool(bool),
+    /// token id to bias, from -100 to 100
+    LogitBias(BTreeMap<u32, f32>),
+    /// extra request headers, name and value
+    Headers(Vec<(String, String)>),
+}
+
+impl From<bool> for ParameterValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
 }
 
 impl From<f32> for ParameterValue {
@@ -55,6 +83,18 @@ impl From<Option<u32>> for ParameterValue {
     }
 }
 
+impl From<BTreeMap<u32, f32>> for ParameterValue {
+    fn from(value: BTreeMap<u32, f32>) -> Self {
+        Self::LogitBias(value)
+    }
+}
+
+impl From<Vec<(String, String)>> for ParameterValue {
+    fn from(value: Vec<(String, String)>) -> Self {
+        Self::Headers(value)
+    }
+}
+
 impl From<String> for ParameterValue {
     fn from(value: String) -> Self {
         Self::String(value)
@@ -105,24 +145,34 @@ impl Parameter for Param<u32> {
     fn set(&self, value: ParameterValue) {
         if let ParameterValue::Integer(value) = value {
             self.setter.call((value,));
+            self.store.replace(value);
         }
     }
 
     fn get(&self) -> ParameterValue {
         ParameterValue::Integer(self.getter.call(()))
     }
+
+    fn store(&self) -> ParameterValue {
+        ParameterValue::Integer(*self.store.borrow())
+    }
 }
 
 impl Parameter for Param<f32> {
     fn set(&self, value: ParameterValue) {
         if let ParameterValue::Number(value) = value {
             self.setter.call((value,));
+            self.store.replace(value);
         }
     }
 
     fn get(&self) -> ParameterValue {
         ParameterValue::Number(self.getter.call(()))
     }
+
+    fn store(&self) -> ParameterValue {
+        ParameterValue::Number(*self.store.borrow())
+    }
 }
 
 impl Parameter for Param<Option<u32>> {
@@ -148,6 +198,29 @@ impl Parameter for Param<Option<u32>> {
     }
 }
 
+impl Parameter for Param<Option<u64>> {
+    fn set(&self, value: ParameterValue) {
+        if let ParameterValue::OptionalLong(value) = value {
+            self.setter.call((value,));
+            if let Some(value) = value {
+                self.store.replace(Some(value));
+            }
+        }
+    }
+
+    fn get(&self) -> ParameterValue {
+        ParameterValue::OptionalLong(self.getter.call(()))
+    }
+
+    fn store(&self) -> ParameterValue {
+        if let Some(store) = *self.store.borrow() {
+            ParameterValue::Long(store)
+        } else {
+            self.default()
+        }
+    }
+}
+
 impl Parameter for Param<Option<String>> {
     fn set(&self, value: ParameterValue) {
         if let ParameterValue::OptionalString(value) = value {
@@ -188,6 +261,23 @@ impl Parameter for Param<String> {
     }
 }
 
+impl Parameter for Param<bool> {
+    fn set(&self, value: ParameterValue) {
+        if let ParameterValue::Bool(value) = value {
+            self.setter.call((value,));
+            self.store.replace(value);
+        }
+    }
+
+    fn get(&self) -> ParameterValue {
+        ParameterValue::Bool(self.getter.call(()))
+    }
+
+    fn store(&self) -> ParameterValue {
+        ParameterValue::Bool(*self.store.borrow())
+    }
+}
+
 impl Parameter for Param<Vec<String>> {
     fn set(&self, value: ParameterValue) {
         if let ParameterValue::StringArray(value) = value {
@@ -205,6 +295,50 @@ impl Parameter for Param<Vec<String>> {
     }
 }
 
+impl Parameter for Param<BTreeMap<u32, f32>> {
+    fn set(&self, value: ParameterValue) {
+        if let ParameterValue::LogitBias(value) = value {
+            self.setter.call((value.clone(),));
+            self.store.replace(value);
+        }
+    }
+
+    fn get(&self) -> ParameterValue {
+        ParameterValue::LogitBias(self.getter.call(()))
+    }
+
+    fn store(&self) -> ParameterValue {
+        ParameterValue::LogitBias(self.store.borrow().clone())
+    }
+}
+
+impl Parameter for Param<Vec<(String, String)>> {
+    fn set(&self, value: ParameterValue) {
+        if let ParameterValue::Headers(value) = value {
+            self.setter.call((value.clone(),));
+            self.store.replace(value);
+        }
+    }
+
+    fn get(&self) -> ParameterValue {
+        ParameterValue::Headers(self.getter.call(()))
+    }
+
+    fn store(&self) -> ParameterValue {
+        ParameterValue::Headers(self.store.borrow().clone())
+    }
+}
+
 pub trait ParameterControl {
     fn params(&self) -> Vec<Box<dyn Parameter>>;
 }
+
+#[test]
+fn test_looks_like_api_key() {
+    assert!(looks_like_api_key("sk-0123456789abcdefghijKLMN"));
+    assert!(looks_like_api_key("sk-proj-0123456789abcdefghij_KL"));
+    assert!(!looks_like_api_key(""));
+    assert!(!looks_like_api_key("0123456789abcdefghijKLMN"));
+    assert!(!looks_like_api_key("sk-0123456789abcdefghijKLMN\n"));
+    assert!(!looks_like_api_key("sk-short"));
+}
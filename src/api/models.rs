@@ -23,33 +23,55 @@ pub struct ModelData {
 pub struct ModelsAPI {
     pub models: Arc<RwLock<Option<Models>>>,
     pub is_ready: Arc<atomic::AtomicBool>,
-    api_key: String,
+    /// shared with the conversation, so a new key is used from the next fetch
+    api_key: Arc<RwLock<String>>,
     client: Arc<MultiClient>,
 }
 impl ModelsAPI {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: Arc<RwLock<String>>, client: Arc<MultiClient>) -> Self {
         Self {
             models: Arc::new(RwLock::new(None)),
-            client: Arc::new(MultiClient::new()),
+            client,
             is_ready: Arc::new(atomic::AtomicBool::new(true)),
             api_key,
         }
     }
     pub async fn get_models(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.is_ready.store(false, atomic::Ordering::Relaxed);
+        let res = self.fetch_models().await;
+        self.is_ready.store(true, atomic::Ordering::Relaxed);
+        let mut models = res?;
+        tracing::info!("fetched {} models", models.data.len());
+        models.data.sort_by(|a, b| a.id.cmp(&b.id));
+        self.models.write().await.replace(models);
+        Ok(())
+    }
+    async fn fetch_models(&self) -> Result<Models, Box<dyn std::error::Error + Send + Sync>> {
         let mut request_body = Request::new(Body::default());
         *request_body.method_mut() = hyper::Method::GET;
         *request_body.uri_mut() = "https://api.openai.com/v1/models".parse().unwrap();
         request_body.headers_mut().insert(
             AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", self.api_key)).unwrap(),
+            HeaderValue::from_str(&format!("Bearer {}", self.api_key.read().await.trim()))
+                .map_err(|_| "Invalid API key format")?,
         );
         let response = self.client.request(request_body).await?;
         let body = body::to_bytes(response.into_body()).await?;
         let models: Models = serde_json::from_slice(&body)?;
-        println!("{:?}", models);
-        self.models.write().await.replace(models);
-        self.is_ready.store(true, atomic::Ordering::Relaxed);
-        Ok(())
+        tracing::debug!("{:?}", models);
+        Ok(models)
     }
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fetch_with_new_key() {
+    use crate::client::MockClient;
+    let mock = MockClient::default()
+        .with_response(hyper::StatusCode::OK, &[r#"{"object":"list","data":[]}"#]);
+    let received = mock.received();
+    let api_key = Arc::new(RwLock::new(String::new()));
+    let mut models = ModelsAPI::new(api_key.clone(), Arc::new(MultiClient::mock(mock)));
+    *api_key.write().await = "sk-new".to_string();
+    models.get_models().await.unwrap();
+    assert_eq!(received.lock().unwrap()[0][AUTHORIZATION], "Bearer sk-new");
+}
@@ -0,0 +1,60 @@
+use tiktoken_rs::{
+    cl100k_base_singleton, o200k_base_singleton, p50k_base_singleton, p50k_edit_singleton,
+    r50k_base_singleton,
+    tokenizer::{get_tokenizer, Tokenizer},
+    CoreBPE,
+};
+
+/// Run `f` with the tokenizer of `model`, built once for all the models using it
+/// as building one takes a while. Unknown models use the tokenizer of gpt-4.
+fn with_bpe<T>(model: &str, f: impl FnOnce(&CoreBPE) -> T) -> T {
+    let bpe = match get_tokenizer(model) {
+        Some(Tokenizer::O200kBase) => o200k_base_singleton(),
+        Some(Tokenizer::P50kBase) => p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => r50k_base_singleton(),
+        Some(Tokenizer::Cl100kBase) | None => cl100k_base_singleton(),
+    };
+    let bpe = bpe.lock();
+    f(&bpe)
+}
+
+/// The ids of the tokens `word` is made of, at the start of a text and after a
+/// space, where its tokens differ. Biasing them all also biases the other
+/// words made of these tokens.
+pub fn word_token_ids(model: &str, word: &str) -> Vec<u32> {
+    let word = word.trim();
+    if word.is_empty() {
+        return Vec::new();
+    }
+    let mut ids = with_bpe(model, |bpe| {
+        let mut ids = bpe.encode_ordinary(word);
+        ids.extend(bpe.encode_ordinary(&format!(" {}", word)));
+        ids
+    });
+    ids.sort_unstable();
+    ids.dedup();
+    ids.into_iter().map(|id| id as u32).collect()
+}
+
+/// How many tokens `text` is made of for `model`.
+pub fn count_tokens(model: &str, text: &str) -> usize {
+    with_bpe(model, |bpe| bpe.encode_ordinary(text).len())
+}
+
+#[test]
+fn test_word_token_ids() {
+    // "hello" and " hello"
+    assert_eq!(word_token_ids("gpt-4", "hello"), vec![15339, 24748]);
+    assert_eq!(
+        word_token_ids("my-fine-tune", " hello "),
+        vec![15339, 24748]
+    );
+    assert!(word_token_ids("gpt-4", "  ").is_empty());
+}
+
+#[test]
+fn test_count_tokens() {
+    assert_eq!(count_tokens("gpt-4", "hello world"), 2);
+    assert_eq!(count_tokens("my-fine-tune", ""), 0);
+}
@@ -1,28 +1,393 @@
+use arc_swap::ArcSwap;
+use futures::future::BoxFuture;
 use hyper::body::HttpBody;
 use hyper::client::{HttpConnector, ResponseFuture};
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
 
 use hyper::{Client, Request, Uri};
 use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_socks2::{Auth, SocksConnector};
 use hyper_tls::HttpsConnector;
 
 use std::any::Any;
-use std::error::Error;
-use std::{fmt::Debug, ops::Not};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{atomic, Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use futures::Stream;
 use hyper::{Body, Response};
 
-use serde::Deserialize;
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
 use tokio_stream::wrappers::ReceiverStream;
 
+/// How requests reach the OpenAI API.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ProxyConfig {
+    /// `HTTP_PROXY`, or the internet settings on Windows
+    #[default]
+    System,
+    /// Connect directly
+    None,
+    /// An `http://`, `https://` or `socks5://` proxy uri
+    Custom(String),
+}
+
+/// Where a request is in its automatic retries, while waiting for the next attempt.
+#[derive(Debug, Clone)]
+pub struct RetryState {
+    /// the attempt waited for, from 2
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub retry_at: Instant,
+    /// why the previous attempt failed
+    pub error: String,
+}
+
+/// The rate limits left, from the `x-ratelimit-*` headers of the last response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub remaining_requests: Option<u64>,
+    pub limit_requests: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    pub limit_tokens: Option<u64>,
+    /// until the remaining requests are back to the limit, e.g. `1s`
+    pub reset_requests: Option<String>,
+    pub reset_tokens: Option<String>,
+}
+
+impl RateLimits {
+    /// `None` when the response has none of them, e.g. from another gateway.
+    pub fn from_headers(headers: &hyper::HeaderMap) -> Option<Self> {
+        let text = |name: &str| {
+            headers
+                .get(format!("x-ratelimit-{}", name))
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+        let number = |name: &str| text(name).and_then(|value| value.parse().ok());
+        let limits = Self {
+            remaining_requests: number("remaining-requests"),
+            limit_requests: number("limit-requests"),
+            remaining_tokens: number("remaining-tokens"),
+            limit_tokens: number("limit-tokens"),
+            reset_requests: text("reset-requests"),
+            reset_tokens: text("reset-tokens"),
+        };
+        (limits != Self::default()).then_some(limits)
+    }
+    /// Whether less than a tenth of a limit is left.
+    pub fn is_low(&self) -> bool {
+        let low = |remaining: Option<u64>, limit: Option<u64>| {
+            remaining
+                .zip(limit)
+                .is_some_and(|(remaining, limit)| remaining * 10 < limit)
+        };
+        low(self.remaining_requests, self.limit_requests)
+            || low(self.remaining_tokens, self.limit_tokens)
+    }
+}
+
+/// How the connections to the API are kept and reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    /// idle connections kept open for the next requests to a host
+    pub pool_max_idle_per_host: usize,
+    /// idle connections are closed after this long
+    pub pool_idle_timeout: Duration,
+    /// HTTP/2 without negotiating it, HTTP/1.1 is used otherwise
+    pub http2_only: bool,
+    /// TCP keep-alive and HTTP/2 pings, `None` to go without
+    pub keep_alive: Option<Duration>,
+}
+
+impl Default for ConnectionOptions {
+    /// A few connections for the conversations generating at once, kept a
+    /// while between the messages; the API speaks HTTP/1.1 to any client.
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: 8,
+            pool_idle_timeout: Duration::from_secs(90),
+            http2_only: false,
+            keep_alive: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl ConnectionOptions {
+    fn client_builder(&self) -> hyper::client::Builder {
+        let mut builder = Client::builder();
+        builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .http2_only(self.http2_only);
+        if let Some(interval) = self.keep_alive {
+            builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder
+    }
+    fn http_connector(&self) -> HttpConnector {
+        let mut connector = HttpConnector::new();
+        connector.set_keepalive(self.keep_alive);
+        // https uris are handled by the connector around it
+        connector.enforce_http(false);
+        connector
+    }
+}
+
+type DirectClient = Client<HttpsConnector<HttpConnector>>;
+type HttpProxyClient = Client<ProxyConnector<HttpsConnector<HttpConnector>>>;
+type SocksProxyClient = Client<HttpsConnector<SocksConnector<HttpConnector>>>;
+
+/// A hyper client whose connector can be swapped at runtime.
 #[derive(Debug)]
-pub struct MultiClient(Box<dyn Any + Send + Sync>);
+pub struct MultiClient {
+    client: ArcSwap<Box<dyn Any + Send + Sync>>,
+    /// limits the generations running at once, shared by every conversation
+    queue: ArcSwap<Semaphore>,
+    cache: Arc<ResponseCache>,
+    /// seconds without a streamed event before the stream is given up
+    stall_timeout: atomic::AtomicU64,
+    /// kept for the clients rebuilt with another proxy
+    options: ConnectionOptions,
+    /// send an [`IDEMPOTENCY_KEY`] with the requests
+    idempotency_keys: atomic::AtomicBool,
+}
+
+/// Answers each request with the next of its canned responses, in place of the network.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockClient {
+    /// status and body chunks, each chunk received on its own
+    responses: std::sync::Mutex<VecDeque<(hyper::StatusCode, Vec<&'static str>)>>,
+    /// the headers of the requests answered, kept for the test to look at
+    received: Arc<std::sync::Mutex<Vec<hyper::HeaderMap>>>,
+}
+
+#[cfg(test)]
+impl MockClient {
+    /// Answer the next request with `chunks` after the responses already given.
+    pub fn with_response(self, status: hyper::StatusCode, chunks: &[&'static str]) -> Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .push_back((status, chunks.to_vec()));
+        self
+    }
+    pub fn received(&self) -> Arc<std::sync::Mutex<Vec<hyper::HeaderMap>>> {
+        self.received.clone()
+    }
+    fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        self.received
+            .lock()
+            .unwrap()
+            .push(request.headers().clone());
+        let (status, chunks) = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("no canned response left");
+        let chunks = chunks.into_iter().map(Ok::<_, std::io::Error>);
+        let mut response = Response::new(Body::wrap_stream(futures::stream::iter(chunks)));
+        *response.status_mut() = status;
+        response
+    }
+}
+
+/// Lets the server recognize the retries of a request it already handled.
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Another request like `request`, posting `body`, as requests cannot be cloned.
+pub fn copy_request(request: &Request<Body>, body: &str) -> Request<Body> {
+    let mut copy = Request::new(Body::from(body.to_string()));
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.headers_mut() = request.headers().clone();
+    copy
+}
+
+/// A header set by the user, checked before it is sent.
+pub fn custom_header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("Invalid header name \"{}\"", name))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("Invalid value for the header {}", name))?;
+    Ok((name, value))
+}
+
+/// Every header of `headers`, the first invalid one as an error.
+pub fn custom_headers(
+    headers: &[(String, String)],
+) -> Result<Vec<(HeaderName, HeaderValue)>, anyhow::Error> {
+    headers
+        .iter()
+        .map(|(name, value)| custom_header(name, value).map_err(anyhow::Error::msg))
+        .collect()
+}
+
+/// Why [`MultiClient::build_request`] failed.
+#[derive(Debug, thiserror::Error)]
+pub enum BuildRequestError {
+    /// the key cannot be sent in a header
+    #[error("Invalid API key format")]
+    InvalidKey,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Builds a [`MultiClient`] with other [`ConnectionOptions`] than the default ones.
+#[derive(Debug, Default)]
+pub struct MultiClientBuilder {
+    options: ConnectionOptions,
+}
+
+impl MultiClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn pool_max_idle_per_host(mut self, max_idle: usize) -> Self {
+        self.options.pool_max_idle_per_host = max_idle;
+        self
+    }
+    pub fn http2_only(mut self, enabled: bool) -> Self {
+        self.options.http2_only = enabled;
+        self
+    }
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.options.keep_alive = interval;
+        self
+    }
+    pub fn build(self) -> MultiClient {
+        let options = self.options;
+        let client = MultiClient::build(&ProxyConfig::System, &options).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring system proxy: {}", e);
+            Box::new(
+                options
+                    .client_builder()
+                    .build::<_, Body>(HttpsConnector::new_with_connector(options.http_connector())),
+            )
+        });
+        MultiClient {
+            client: ArcSwap::from_pointee(client),
+            queue: ArcSwap::from_pointee(Semaphore::new(MultiClient::DEFAULT_CONCURRENCY)),
+            cache: Arc::new(ResponseCache::new(ResponseCache::DIR)),
+            stall_timeout: atomic::AtomicU64::new(MultiClient::DEFAULT_STALL_TIMEOUT),
+            options,
+            idempotency_keys: atomic::AtomicBool::new(true),
+        }
+    }
+}
 
 impl MultiClient {
+    pub const DEFAULT_CONCURRENCY: usize = 3;
+    pub const DEFAULT_STALL_TIMEOUT: u64 = 30;
+    /// the first attempt included
+    pub const MAX_ATTEMPTS: u32 = 5;
+
     pub fn new() -> Self {
-        let https_connector = HttpsConnector::new();
-        let proxy = std::env::var("HTTP_PROXY");
+        MultiClientBuilder::new().build()
+    }
+    /// A client answering with the responses of `mock`, for the tests.
+    #[cfg(test)]
+    pub fn mock(mock: MockClient) -> Self {
+        let client = Self::new();
+        client.client.store(Arc::new(Box::new(mock)));
+        client
+    }
+    pub fn connection_options(&self) -> ConnectionOptions {
+        self.options
+    }
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout.load(atomic::Ordering::Relaxed))
+    }
+    /// Give up a stream after `secs` seconds without an event, for streams started later.
+    pub fn set_stall_timeout(&self, secs: u64) {
+        self.stall_timeout
+            .store(secs.max(1), atomic::Ordering::Relaxed);
+    }
+    pub fn sends_idempotency_keys(&self) -> bool {
+        self.idempotency_keys.load(atomic::Ordering::Relaxed)
+    }
+    pub fn set_idempotency_keys(&self, enabled: bool) {
+        self.idempotency_keys
+            .store(enabled, atomic::Ordering::Relaxed);
+    }
+    /// A new key for a request, to be sent again with each of its retries;
+    /// `None` when the keys are not sent.
+    pub fn idempotency_key(&self) -> Option<HeaderValue> {
+        self.sends_idempotency_keys().then(|| {
+            HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                .expect("a uuid is a valid header value")
+        })
+    }
+    /// The request posting `body` to `url`, with the API key, a new idempotency
+    /// key and the custom `headers`, which replace the standard ones.
+    pub fn build_request(
+        &self,
+        url: &str,
+        api_key: &str,
+        headers: &[(String, String)],
+        body: &str,
+    ) -> Result<Request<Body>, BuildRequestError> {
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", api_key.trim()))
+            .map_err(|_| BuildRequestError::InvalidKey)?;
+        let custom = custom_headers(headers)?;
+        let mut request = Request::new(Body::from(body.to_string()));
+        *request.method_mut() = hyper::Method::POST;
+        *request.uri_mut() = url.parse().map_err(anyhow::Error::from)?;
+        let headers = request.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(AUTHORIZATION, authorization);
+        if let Some(key) = self.idempotency_key() {
+            headers.insert(IDEMPOTENCY_KEY, key);
+        }
+        // a custom header can still be repeated
+        for (name, _) in &custom {
+            headers.remove(name);
+        }
+        for (name, value) in custom {
+            headers.append(name, value);
+        }
+        Ok(request)
+    }
+    pub fn cache(&self) -> Arc<ResponseCache> {
+        self.cache.clone()
+    }
+    /// Rebuild the client, requests already in flight keep the old connection.
+    pub fn set_proxy(&self, proxy: &ProxyConfig) -> Result<(), anyhow::Error> {
+        self.client
+            .store(Arc::new(Self::build(proxy, &self.options)?));
+        Ok(())
+    }
+    /// Allow `permits` generations at once, those already running keep their place.
+    pub fn set_concurrency(&self, permits: usize) {
+        self.queue.store(Arc::new(Semaphore::new(permits.max(1))));
+    }
+    /// Wait for a free place among the generations, held until the permit is dropped.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.queue
+            .load_full()
+            .acquire_owned()
+            .await
+            .expect("the queue is never closed")
+    }
+    fn system_proxy() -> Option<String> {
+        let proxy = [
+            "HTTPS_PROXY",
+            "https_proxy",
+            "HTTP_PROXY",
+            "http_proxy",
+            "ALL_PROXY",
+            "all_proxy",
+        ]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok())
+        .ok_or(std::env::VarError::NotPresent);
         #[cfg(target_os = "windows")]
         let proxy = {
             use proxyconf::internet_settings::modern::registry::{get_current_user_location, read};
@@ -37,85 +402,412 @@ impl MultiClient {
                 Ok::<String, anyhow::Error>(proxy_url)
             })
         };
-        let proxy_connector = if let Ok(proxy_uri) = proxy {
-            tracing::info!("Using proxy: {}", proxy_uri);
-            let proxy_uri = proxy_uri.parse().unwrap();
-            let proxy = Proxy::new(Intercept::All, proxy_uri);
-            let proxy_connector =
-                ProxyConnector::from_proxy(https_connector.clone(), proxy).unwrap();
-            Some(proxy_connector)
+        proxy.ok()
+    }
+    /// The connector types match the clients tried in [`Self::request`].
+    fn build(
+        proxy: &ProxyConfig,
+        options: &ConnectionOptions,
+    ) -> Result<Box<dyn Any + Send + Sync>, anyhow::Error> {
+        let https_connector = HttpsConnector::new_with_connector(options.http_connector());
+        let proxy_uri = match proxy {
+            ProxyConfig::System => Self::system_proxy(),
+            ProxyConfig::None => None,
+            ProxyConfig::Custom(uri) if uri.trim().is_empty() => {
+                return Err(anyhow::anyhow!("No proxy uri given"))
+            }
+            ProxyConfig::Custom(uri) => Some(uri.trim().to_string()),
+        };
+        let Some(proxy_uri) = proxy_uri else {
+            return Ok(Box::new(
+                options.client_builder().build::<_, Body>(https_connector),
+            ));
+        };
+        tracing::info!("Using proxy: {}", proxy_uri);
+        let proxy_uri: Uri = proxy_uri.parse()?;
+        match proxy_uri.scheme_str() {
+            Some("socks5" | "socks5h") => {
+                let auth = proxy_uri
+                    .authority()
+                    .and_then(|authority| authority.as_str().rsplit_once('@'))
+                    .and_then(|(user_info, _)| user_info.split_once(':'))
+                    .map(|(username, password)| Auth::new(username, password));
+                // the proxy address is not an http uri, which it allows
+                let socks_connector = SocksConnector {
+                    proxy_addr: proxy_uri,
+                    auth,
+                    connector: options.http_connector(),
+                }
+                .with_tls()?;
+                Ok(Box::new(
+                    options.client_builder().build::<_, Body>(socks_connector),
+                ))
+            }
+            Some("http" | "https") => {
+                let proxy = Proxy::new(Intercept::All, proxy_uri);
+                let proxy_connector = ProxyConnector::from_proxy(https_connector, proxy)?;
+                Ok(Box::new(
+                    options.client_builder().build::<_, Body>(proxy_connector),
+                ))
+            }
+            _ => Err(anyhow::anyhow!("Unsupported proxy: {}", proxy_uri)),
+        }
+    }
+    pub fn request(&self, req: Request<Body>) -> BoxFuture<'static, hyper::Result<Response<Body>>> {
+        let client = self.client.load();
+        if let Some(c) = client.downcast_ref::<DirectClient>() {
+            Box::pin(c.request(req))
+        } else if let Some(c) = client.downcast_ref::<HttpProxyClient>() {
+            Box::pin(c.request(req))
+        } else if let Some(c) = client.downcast_ref::<SocksProxyClient>() {
+            Box::pin(c.request(req))
         } else {
-            None
+            #[cfg(test)]
+            if let Some(c) = client.downcast_ref::<MockClient>() {
+                return Box::pin(futures::future::ready(Ok(c.respond(&req))));
+            }
+            panic!("Unknown client type")
+        }
+    }
+    /// Send the request made by `build`, trying again with an increasing delay
+    /// after a connection error, a rate limit or a server error. Waiting for the
+    /// next attempt is reported to `progress`, cleared once done; the last
+    /// failure is returned as is.
+    pub async fn request_with_retry(
+        &self,
+        build: impl Fn() -> Request<Body>,
+        progress: Option<&watch::Sender<Option<RetryState>>>,
+    ) -> Result<Response<Body>, anyhow::Error> {
+        let mut attempt = 1;
+        let result = loop {
+            let last = attempt >= Self::MAX_ATTEMPTS;
+            let error = match self.request(build()).await {
+                Err(e) if !last => e.to_string(),
+                Err(e) => break Err(e.into()),
+                Ok(response) if last || !Self::should_retry(response.status()) => {
+                    break Ok(response)
+                }
+                Ok(response) => {
+                    let (parts, body) = response.into_parts();
+                    let body = hyper::body::to_bytes(body).await?;
+                    // running out of credits is not solved by waiting
+                    if String::from_utf8_lossy(&body).contains("insufficient_quota") {
+                        break Ok(Response::from_parts(parts, Body::from(body)));
+                    }
+                    parts.status.to_string()
+                }
+            };
+            let delay = Duration::from_secs(1 << (attempt - 1));
+            attempt += 1;
+            tracing::warn!(
+                "{}, retrying ({}/{}) in {:?}",
+                error,
+                attempt,
+                Self::MAX_ATTEMPTS,
+                delay
+            );
+            if let Some(progress) = progress {
+                progress.send_replace(Some(RetryState {
+                    attempt,
+                    max_attempts: Self::MAX_ATTEMPTS,
+                    retry_at: Instant::now() + delay,
+                    error,
+                }));
+            }
+            tokio::time::sleep(delay).await;
         };
-        let client = proxy_connector.map_or_else(
-            || {
-                Box::new(Client::builder().build::<_, hyper::Body>(https_connector))
-                    as Box<dyn Any + Send + Sync>
-            },
-            |proxy| Box::new(Client::builder().build::<_, hyper::Body>(proxy)),
-        );
-        Self(client)
-    }
-    pub fn request<B>(&self, req: Request<B>) -> ResponseFuture
-    where
-        B: HttpBody + Send + 'static,
-        B::Data: Send,
-        B::Error: Into<Box<dyn Error + Send + Sync>>,
-    {
-        match self
-            .0
-            .downcast_ref::<Client<HttpsConnector<HttpConnector>, B>>()
-        {
-            Some(c) => c.request(req),
-            None => match self
-                .0
-                .downcast_ref::<Client<ProxyConnector<HttpsConnector<HttpConnector>>, B>>()
-            {
-                Some(c) => c.request(req),
-                None => panic!("Unknown client type"),
-            },
+        if let Some(progress) = progress {
+            progress.send_replace(None);
         }
+        result
+    }
+    fn should_retry(status: hyper::StatusCode) -> bool {
+        status == hyper::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
     }
     pub fn get(&self, uri: Uri) -> ResponseFuture {
-        match self
-            .0
-            .downcast_ref::<Client<HttpsConnector<HttpConnector>>>()
-        {
-            Some(c) => c.get(uri),
-            None => match self
-                .0
-                .downcast_ref::<Client<ProxyConnector<HttpsConnector<HttpConnector>>>>()
-            {
-                Some(c) => c.get(uri),
-                None => panic!("Unknown client type"),
+        let client = self.client.load();
+        if let Some(c) = client.downcast_ref::<DirectClient>() {
+            c.get(uri)
+        } else if let Some(c) = client.downcast_ref::<HttpProxyClient>() {
+            c.get(uri)
+        } else if let Some(c) = client.downcast_ref::<SocksProxyClient>() {
+            c.get(uri)
+        } else {
+            panic!("Unknown client type")
+        }
+    }
+}
+
+/// A request body and the raw server-sent events received for it.
+#[derive(Debug, Clone, Default)]
+pub struct Exchange {
+    pub request: String,
+    pub response: Vec<String>,
+}
+
+impl std::fmt::Display for Exchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "request:")?;
+        writeln!(f, "{}", self.request)?;
+        writeln!(f, "response:")?;
+        for raw in &self.response {
+            writeln!(f, "{}", raw)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of the latest exchanges, only recorded while enabled.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeLog {
+    enabled: Arc<atomic::AtomicBool>,
+    exchanges: Arc<RwLock<VecDeque<Exchange>>>,
+}
+
+impl ExchangeLog {
+    const CAPACITY: usize = 20;
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(atomic::Ordering::Relaxed)
+    }
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, atomic::Ordering::Relaxed);
+    }
+    /// Start a new exchange with the serialized request body.
+    pub fn begin(&self, request: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let request = serde_json::from_str::<serde_json::Value>(request)
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .unwrap_or_else(|_| request.to_string());
+        let mut exchanges = self.exchanges.write().unwrap();
+        exchanges.push_back(Exchange {
+            request,
+            response: Vec::new(),
+        });
+        while exchanges.len() > Self::CAPACITY {
+            exchanges.pop_front();
+        }
+    }
+    /// Append a raw event to the latest exchange.
+    pub fn record(&self, raw: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let Some(exchange) = self.exchanges.write().unwrap().back_mut() {
+            exchange.response.push(raw.trim().to_string());
+        }
+    }
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.read().unwrap().iter().cloned().collect()
+    }
+    pub fn clear(&self) {
+        self.exchanges.write().unwrap().clear();
+    }
+}
+
+/// Raw events of complete responses, keyed by their request body and kept on
+/// disk. Only used for deterministic requests while enabled.
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    enabled: atomic::AtomicBool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    request: String,
+    events: Vec<String>,
+}
+
+impl ResponseCache {
+    pub const DIR: &'static str = "./cache";
+    /// the oldest responses are removed past this many
+    const CAPACITY: usize = 200;
+
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            enabled: atomic::AtomicBool::new(false),
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(atomic::Ordering::Relaxed)
+    }
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, atomic::Ordering::Relaxed);
+    }
+    fn path(&self, request: &str) -> PathBuf {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        request.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+    /// The events received for `request`, if it was sent before.
+    pub fn get(&self, request: &str) -> Option<Vec<String>> {
+        let file = std::fs::File::open(self.path(request)).ok()?;
+        let cached = serde_json::from_reader::<_, CachedResponse>(file).ok()?;
+        // a different request with the same hash
+        (cached.request == request).then_some(cached.events)
+    }
+    pub fn put(&self, request: &str, events: Vec<String>) -> Result<(), anyhow::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let file = std::fs::File::create(self.path(request))?;
+        serde_json::to_writer(
+            file,
+            &CachedResponse {
+                request: request.to_string(),
+                events,
             },
+        )?;
+        self.evict()
+    }
+    fn evict(&self) -> Result<(), anyhow::Error> {
+        let mut entries = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Some((modified, entry.path()))
+            })
+            .collect::<Vec<_>>();
+        if entries.len() <= Self::CAPACITY {
+            return Ok(());
+        }
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - Self::CAPACITY] {
+            std::fs::remove_file(path)?;
         }
+        Ok(())
     }
+    /// The number of cached responses.
+    pub fn count(&self) -> usize {
+        std::fs::read_dir(&self.dir).map_or(0, |entries| entries.count())
+    }
+    pub fn clear(&self) -> Result<(), anyhow::Error> {
+        match std::fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Stream the events of a cached response as if they were received again.
+pub fn replay_sse<C>(
+    events: Vec<String>,
+    log: Option<ExchangeLog>,
+) -> ReceiverStream<Result<C, anyhow::Error>>
+where
+    for<'a> C: Deserialize<'a> + Debug + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel::<Result<C, anyhow::Error>>(100);
+    tokio::spawn(async move {
+        for raw in events {
+            tracing::info!("replayed: {}", raw);
+            if let Some(log) = &log {
+                log.record(&raw);
+            }
+            let completion = serde_json::from_str::<C>(&raw).map_err(anyhow::Error::from);
+            if sender.send(completion).await.is_err() {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(receiver)
 }
 
-pub fn fetch_sse<C>(mut response: Response<Body>) -> impl Stream<Item = Result<C, anyhow::Error>>
+/// The data of a server-sent event, its `data:` lines joined. A body that is
+/// not an event stream, e.g. an error, is taken whole.
+fn sse_data(event: &str) -> Option<String> {
+    let event = event.trim();
+    if event.is_empty() {
+        return None;
+    }
+    let mut data = Vec::new();
+    let mut is_event = false;
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+            is_event = true;
+        } else if line.starts_with(':')
+            || ["event:", "id:", "retry:"]
+                .iter()
+                .any(|field| line.starts_with(field))
+        {
+            is_event = true;
+        }
+    }
+    if !is_event {
+        return Some(event.to_string());
+    }
+    (!data.is_empty()).then(|| data.join("\n"))
+}
+
+/// Stream the events of `response`, stored in `cache` under the request body
+/// once the response is complete.
+pub fn fetch_sse<C>(
+    mut response: Response<Body>,
+    log: Option<ExchangeLog>,
+    cache: Option<(Arc<ResponseCache>, String)>,
+    stall_timeout: Duration,
+) -> ReceiverStream<Result<C, anyhow::Error>>
 where
     for<'a> C: Deserialize<'a> + Debug + Send + 'static,
 {
     let (sender, receiver) = mpsc::channel::<Result<C, anyhow::Error>>(100);
     tokio::spawn(async move {
+        let mut events = Vec::new();
+        // an event can be split between chunks, it is complete at a blank line
+        let mut buffer = Vec::new();
         let res: Result<(), anyhow::Error> = 'stream: {
-            while let Some(chunk) = response.body_mut().data().await {
-                let chunk = match chunk {
-                    Ok(chunk) => chunk,
-                    Err(e) => {
+            loop {
+                // stop reading as soon as the receiver is gone, the timeout
+                // starts over for every chunk
+                let chunk = tokio::select! {
+                    chunk = tokio::time::timeout(stall_timeout, response.body_mut().data()) => chunk,
+                    _ = sender.closed() => return,
+                };
+                let Ok(chunk) = chunk else {
+                    tracing::warn!("stream stalled");
+                    break 'stream Err(anyhow::anyhow!(
+                        "Stream stalled, nothing was received for {} seconds",
+                        stall_timeout.as_secs()
+                    ));
+                };
+                let last = match chunk {
+                    Some(Ok(chunk)) => {
+                        buffer.extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+                        false
+                    }
+                    Some(Err(e)) => {
                         tracing::error!("{}", e);
                         break 'stream Err(e.into());
                     }
+                    // the rest, e.g. an error body that is not an event stream
+                    None => true,
                 };
-                for raw in std::str::from_utf8(&chunk)
-                    .unwrap()
-                    .split("data: ")
-                    .filter_map(|v| v.trim().is_empty().not().then_some(v))
+                let mut complete = Vec::new();
+                while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                    complete.push(buffer.drain(..end + 2).collect::<Vec<_>>());
+                }
+                if last {
+                    complete.push(std::mem::take(&mut buffer));
+                }
+                for raw in complete
+                    .iter()
+                    .filter_map(|event| sse_data(&String::from_utf8_lossy(event)))
                 {
+                    let raw = raw.as_str();
                     tracing::info!("received: {}", raw);
+                    if let Some(log) = &log {
+                        log.record(raw);
+                    }
                     if raw.starts_with("[DONE]") {
                         tracing::info!("received: [DONE]");
+                        if let Some((cache, request)) = &cache {
+                            if let Err(e) = cache.put(request, std::mem::take(&mut events)) {
+                                tracing::warn!("failed to cache the response: {}", e);
+                            }
+                        }
                         break 'stream Ok(());
                     }
                     let completion = match serde_json::from_str::<C>(raw) {
@@ -125,10 +817,16 @@ where
                             break 'stream Err(e.into());
                         }
                     };
+                    if cache.is_some() {
+                        events.push(raw.trim().to_string());
+                    }
                     if (sender.send(Ok(completion)).await).is_err() {
                         return;
                     }
                 }
+                if last {
+                    break;
+                }
             }
             Ok(())
         };
@@ -138,3 +836,143 @@ where
     });
     ReceiverStream::new(receiver)
 }
+
+#[test]
+fn test_rate_limits() {
+    let mut headers = hyper::HeaderMap::new();
+    assert_eq!(RateLimits::from_headers(&headers), None);
+    headers.insert("x-ratelimit-limit-requests", HeaderValue::from_static("60"));
+    headers.insert(
+        "x-ratelimit-remaining-requests",
+        HeaderValue::from_static("59"),
+    );
+    headers.insert("x-ratelimit-reset-requests", HeaderValue::from_static("1s"));
+    headers.insert(
+        "x-ratelimit-limit-tokens",
+        HeaderValue::from_static("90000"),
+    );
+    headers.insert(
+        "x-ratelimit-remaining-tokens",
+        HeaderValue::from_static("8000"),
+    );
+    let limits = RateLimits::from_headers(&headers).unwrap();
+    assert_eq!(limits.remaining_requests, Some(59));
+    assert_eq!(limits.reset_requests.as_deref(), Some("1s"));
+    assert_eq!(limits.reset_tokens, None);
+    assert!(limits.is_low());
+    headers.insert(
+        "x-ratelimit-remaining-tokens",
+        HeaderValue::from_static("9000"),
+    );
+    assert!(!RateLimits::from_headers(&headers).unwrap().is_low());
+}
+
+#[test]
+fn test_sse_data() {
+    assert_eq!(sse_data("data: {}\n\n"), Some("{}".to_string()));
+    assert_eq!(sse_data("data:[DONE]"), Some("[DONE]".to_string()));
+    assert_eq!(sse_data(": keep-alive\n"), None);
+    assert_eq!(
+        sse_data("event: x\ndata: a\ndata: b"),
+        Some("a\nb".to_string())
+    );
+    assert_eq!(
+        sse_data("{\n  \"error\": {}\n}\n"),
+        Some("{\n  \"error\": {}\n}".to_string())
+    );
+    assert_eq!(sse_data("\n"), None);
+}
+
+#[test]
+fn test_response_cache() {
+    let dir = std::env::temp_dir().join(format!("chatgpt-rs-cache-{}", std::process::id()));
+    let cache = ResponseCache::new(&dir);
+    assert_eq!(cache.get("a"), None);
+    cache.put("a", vec!["event".to_string()]).unwrap();
+    assert_eq!(cache.get("a"), Some(vec!["event".to_string()]));
+    assert_eq!(cache.get("b"), None);
+    assert_eq!(cache.count(), 1);
+    cache.clear().unwrap();
+    assert_eq!(cache.count(), 0);
+    cache.clear().unwrap();
+}
+
+#[test]
+fn test_connection_options() {
+    let client = MultiClientBuilder::new()
+        .pool_max_idle_per_host(2)
+        .http2_only(true)
+        .keep_alive(None)
+        .build();
+    let options = client.connection_options();
+    assert_eq!(options.pool_max_idle_per_host, 2);
+    assert!(options.http2_only);
+    assert_eq!(options.keep_alive, None);
+    assert_eq!(
+        options.pool_idle_timeout,
+        ConnectionOptions::default().pool_idle_timeout
+    );
+    // rebuilt with the same options
+    client.set_proxy(&ProxyConfig::None).unwrap();
+    assert_eq!(client.connection_options(), options);
+}
+
+#[test]
+fn test_idempotency_key() {
+    let client = MultiClient::new();
+    let key = client.idempotency_key().unwrap();
+    assert_eq!(key.len(), 36);
+    assert_ne!(client.idempotency_key(), Some(key));
+    client.set_idempotency_keys(false);
+    assert_eq!(client.idempotency_key(), None);
+}
+
+#[test]
+fn test_custom_headers() {
+    let headers = custom_headers(&[("X-Api-Version ".to_string(), " 2".to_string())]).unwrap();
+    assert_eq!(headers[0].0, "x-api-version");
+    assert_eq!(headers[0].1, "2");
+    assert!(custom_header("X Api", "2").is_err());
+    assert!(custom_header("", "2").is_err());
+    assert!(custom_header("Cookie", "a\nb").is_err());
+    assert!(custom_headers(&[("Cookie".to_string(), "a\nb".to_string())]).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_build_request() {
+    let client = MultiClient::new();
+    let headers = [
+        ("X-Api-Version".to_string(), "2".to_string()),
+        (
+            "Content-Type".to_string(),
+            "application/json; v=2".to_string(),
+        ),
+    ];
+    let url = "https://api.openai.com/v1/chat/completions";
+    let request = client
+        .build_request(url, " sk-secret\n", &headers, "{}")
+        .unwrap();
+    assert_eq!(request.method(), hyper::Method::POST);
+    assert_eq!(request.uri(), url);
+    let sent = request.headers();
+    assert_eq!(sent[AUTHORIZATION], "Bearer sk-secret");
+    // replaced, not sent twice
+    assert_eq!(
+        sent.get_all(CONTENT_TYPE).iter().collect::<Vec<_>>(),
+        ["application/json; v=2"]
+    );
+    assert!(sent.contains_key(IDEMPOTENCY_KEY));
+    assert_eq!(sent["x-api-version"], "2");
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    assert_eq!(body, "{}");
+
+    assert!(matches!(
+        client.build_request(url, "sk-\n1", &headers, "{}"),
+        Err(BuildRequestError::InvalidKey)
+    ));
+    let invalid = [("X Api".to_string(), "2".to_string())];
+    assert!(matches!(
+        client.build_request(url, "sk-secret", &invalid, "{}"),
+        Err(BuildRequestError::Other(_))
+    ));
+}
@@ -0,0 +1,125 @@
+//! One question without the GUI, for scripts.
+use crate::{
+    api::chat::{ChatAPIBuilder, Role},
+    client::MultiClient,
+};
+use std::{io::Write, sync::Arc, time::Duration};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub prompt: String,
+    pub system: Option<String>,
+    pub model: Option<String>,
+}
+
+impl Args {
+    pub const USAGE: &'static str =
+        "usage: chatgpt-rs --headless --prompt <text> [--system <text>] [--model <id>]";
+
+    /// `None` without `--headless`, the arguments exclude the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Option<Result<Self, String>> {
+        let args: Vec<_> = args.into_iter().collect();
+        if !args.iter().any(|arg| arg == "--headless") {
+            return None;
+        }
+        let mut parsed = Self::default();
+        let mut prompt = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "--headless" => continue,
+                "--prompt" => &mut prompt,
+                "--system" => &mut parsed.system,
+                "--model" => &mut parsed.model,
+                _ => return Some(Err(format!("unknown argument {arg}"))),
+            };
+            let Some(value) = args.next() else {
+                return Some(Err(format!("{arg} needs a value")));
+            };
+            *target = Some(value);
+        }
+        Some(match prompt {
+            Some(prompt) => Ok(Self { prompt, ..parsed }),
+            None => Err("--prompt is required".to_string()),
+        })
+    }
+}
+
+/// Ask `args.prompt` and stream the reply to stdout as it arrives.
+pub async fn run(args: Args) -> Result<(), anyhow::Error> {
+    let api_key = std::env::var("OPENAI_API_KEY")
+        .map_err(|_| anyhow::anyhow!("OPENAI_API_KEY is not set"))?;
+    let mut builder = ChatAPIBuilder::new(api_key, Arc::new(MultiClient::new()));
+    if let Some(model) = args.model {
+        builder = builder.with_model(model);
+    }
+    let chat = builder.build();
+    chat.set_system_message(args.system).await;
+    let mut question = tokio::spawn({
+        let mut chat = chat.clone();
+        let token = chat.start();
+        async move { chat.question(args.prompt, token).await }
+    });
+
+    let mut printed = 0;
+    let mut print = |text: &str| -> std::io::Result<()> {
+        if let Some(new) = text.get(printed..) {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(new.as_bytes())?;
+            stdout.flush()?;
+            printed = text.len();
+        }
+        Ok(())
+    };
+    let result = loop {
+        let finished = tokio::select! {
+            result = &mut question => Some(result),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => None,
+        };
+        match chat.pending_generate.read().await.as_ref() {
+            Some(Ok(reply)) => print(reply.content.as_deref().unwrap_or_default())?,
+            // the error is kept for display instead of returned
+            Some(Err(e)) => anyhow::bail!("{:#}", e),
+            None => {}
+        }
+        if let Some(result) = finished {
+            break result;
+        }
+    };
+    result??;
+    // the end of the reply arrived after the last poll
+    if let Some(reply) = chat
+        .data
+        .read()
+        .await
+        .messages
+        .back()
+        .filter(|msg| msg.role == Role::Assistant)
+    {
+        print(&reply.content)?;
+    }
+    println!();
+    Ok(())
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+    assert_eq!(args(&[]), None);
+    assert_eq!(
+        args(&["--headless", "--prompt", "hi", "--model", "gpt-4"]),
+        Some(Ok(Args {
+            prompt: "hi".to_string(),
+            system: None,
+            model: Some("gpt-4".to_string()),
+        }))
+    );
+    assert!(matches!(args(&["--headless"]), Some(Err(_))));
+    assert!(matches!(
+        args(&["--prompt", "hi", "--headless"]),
+        Some(Ok(_))
+    ));
+    assert!(matches!(args(&["--bogus", "--headless"]), Some(Err(_))));
+    // unknown arguments are left to the GUI
+    assert_eq!(args(&["--bogus"]), None);
+}
@@ -5,33 +5,67 @@
 
 use eframe::egui;
 use std::error::Error;
-use std::{fs, io::Write, panic};
+use std::{
+    fs,
+    io::Write,
+    panic,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use tracing::Level;
 use tracing_subscriber::prelude::*;
 mod api;
 mod client;
+mod headless;
 mod ui;
 
-use ui::logger::Logger;
+use ui::logger::{self, Logger};
+
+/// latest logs written to the panic report
+const PANIC_LOG_LINES: usize = 200;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     panic::set_hook(Box::new(|panic_info| {
         eprintln!("{panic_info}");
-        if let Ok(f) = fs::File::create("panic.log") {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        // one report per run, earlier ones are kept
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        if let Ok(f) = fs::File::create(format!("panic-{timestamp}.log")) {
             let mut f = std::io::BufWriter::new(f);
-            writeln!(f, "{panic_info}").ok();
+            writeln!(f, "{panic_info}\n\n{backtrace}\nLatest logs:").ok();
+            for line in logger::recent_lines(PANIC_LOG_LINES) {
+                writeln!(f, "{line}").ok();
+            }
         }
     }));
 
     dotenv::dotenv().ok();
-    tracing_subscriber::registry()
-        .with(Logger::new(Level::TRACE))
-        .init();
+    let log_capacity = std::env::var("LOG_CAPACITY")
+        .ok()
+        .and_then(|capacity| capacity.parse().ok())
+        .unwrap_or(Logger::DEFAULT_CAPACITY);
+    let logger = Logger::new(Level::TRACE).with_capacity(log_capacity);
+    let log_level = logger.max_level();
+    tracing_subscriber::registry().with(logger).init();
+
+    if let Some(args) = headless::Args::parse(std::env::args().skip(1)) {
+        let args = args.unwrap_or_else(|e| {
+            eprintln!("{e}\n{}", headless::Args::USAGE);
+            std::process::exit(2);
+        });
+        if let Err(e) = headless::run(args).await {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     let local = tokio::task::LocalSet::new();
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1024.0, 768.0)),
+        // an explicit theme from the settings overrides this in `ChatApp::new`
         follow_system_theme: true,
         drag_and_drop_support: true,
         ..Default::default()
@@ -40,7 +74,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         eframe::run_native(
             "ChatGPT-rs",
             options,
-            Box::new(|cc| Box::new(ui::ChatApp::new(cc))),
+            Box::new(|cc| Box::new(ui::ChatApp::new(cc, log_level))),
         )
         .map_err(|e| anyhow::anyhow!(e.to_string()))?;
         Ok::<(), anyhow::Error>(())
@@ -1,23 +1,90 @@
 use super::{
+    components,
     easy_mark::{self, MemoizedEasymarkHighlighter},
+    list_view,
     model_table::ModelTable,
     parameter_control::ParameterControler,
-    ModelType, View, Window,
+    settings::{Density, SendMode, SharedSettings},
+    template, ModelType, TabRequest, TabWindow, View, Window, UNDO_TIMEOUT,
 };
-use crate::api::{
-    chat::{ChatAPI, Role},
-    ParameterControl,
+use crate::{
+    api::{
+        chat::{Chat, ChatAPI, ChatMessage, ErrorAction, ResponseFormat, Role},
+        ParameterControl,
+    },
+    client::{RateLimits, RetryState},
 };
 
 use eframe::egui::{self, Modifiers};
 use egui_notify::Toasts;
 use std::{
     cell::RefCell,
+    collections::{HashSet, VecDeque},
+    future::Future,
     ops::AddAssign,
+    path::Path,
     rc::Rc,
     sync::{atomic, Arc},
+    time::{Duration, Instant},
 };
-use tokio::task::JoinHandle;
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+/// Inputs sent in a conversation, recalled with Up and Down like in a shell.
+#[derive(Default)]
+struct InputHistory {
+    entries: VecDeque<String>,
+    /// the recalled entry, `None` while editing the draft
+    position: Option<usize>,
+    /// the input before the first recall, restored past the newest entry
+    draft: String,
+}
+
+impl InputHistory {
+    const CAPACITY: usize = 100;
+
+    fn push(&mut self, input: String) {
+        self.position = None;
+        self.draft.clear();
+        if self.entries.back() != Some(&input) {
+            self.entries.push_back(input);
+            if self.entries.len() > Self::CAPACITY {
+                self.entries.pop_front();
+            }
+        }
+    }
+    /// The entry before the recalled one, `current` is kept as the draft when
+    /// leaving it.
+    fn older(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(position) => position - 1,
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+    /// The entry after the recalled one, or the draft past the newest entry.
+    fn newer(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+enum ScrollRequest {
+    Top,
+    Bottom,
+}
 
 pub struct ChatWindow {
     window_name: String,
@@ -27,18 +94,64 @@ pub struct ChatWindow {
     is_ready: Arc<atomic::AtomicBool>,
     show_model_table: bool,
     show_parameter_control: bool,
+    show_developer: bool,
     model_table: ModelTable,
     parameter_control: ParameterControler,
     toasts: Toasts,
     highlighters: Vec<Rc<RefCell<easy_mark::MemoizedEasymarkHighlighter>>>,
     enable_markdown: bool,
+    /// code blocks scroll sideways instead of wrapping when off
+    wrap_code: bool,
+    density: Density,
     edit_focused: bool,
+    ime_composing: bool,
+    settings: SharedSettings,
+    /// whether the transcript follows new messages
+    pinned_to_bottom: bool,
+    scroll_request: Option<ScrollRequest>,
+    /// the in-progress reply as last displayed, and when it was taken
+    stream_snapshot: Option<(String, Instant)>,
+    request: Option<TabRequest>,
+    /// count only the latest reply in the status line
+    count_last_reply: bool,
+    confirm_clear: bool,
+    dont_ask_again: bool,
+    /// when the messages were cleared, while they can still be restored
+    cleared_at: Option<Instant>,
+    title_handle: Option<JoinHandle<Result<String, anyhow::Error>>>,
+    /// a title is only asked for once
+    title_requested: bool,
+    confirm_summarize: bool,
+    /// only the messages are shown, for screen sharing
+    presentation: bool,
+    summarize_handle: Option<JoinHandle<Result<(), anyhow::Error>>>,
+    history: InputHistory,
+    /// the text last selected in the transcript, for `{{selection}}`
+    selection: Option<String>,
+    retries: watch::Receiver<Option<RetryState>>,
+    /// Send shows the request instead of making it
+    dry_run: bool,
+    /// the request of the last dry run, until dismissed
+    dry_run_request: Option<String>,
+    /// indices of the long messages shown whole
+    expanded: HashSet<usize>,
 }
 
 impl ChatWindow {
-    pub fn new(window_name: String, chatgpt: ChatAPI) -> Self {
-        let model_table = ModelTable::new(ModelType::Chat);
+    /// bytes of a dropped file inserted into the input
+    const MAX_ATTACHMENT: usize = 64 * 1024;
+    /// latest messages left as they are when summarizing
+    const SUMMARY_KEEP_LAST: usize = 4;
+    /// taller system messages scroll inside their header
+    const SYSTEM_MAX_HEIGHT: f32 = 120.;
+
+    pub fn new(window_name: String, chatgpt: ChatAPI, settings: SharedSettings) -> Self {
+        let model_table =
+            ModelTable::new(ModelType::Chat, chatgpt.shared_api_key(), chatgpt.client());
         let parameter_control = ParameterControler::new(chatgpt.params());
+        let enable_markdown = settings.borrow().markdown_by_default;
+        let density = settings.borrow().density;
+        let retries = chatgpt.subscribe_retries();
         Self {
             window_name,
             chatgpt,
@@ -48,13 +161,424 @@ impl ChatWindow {
             model_table,
             show_model_table: false,
             show_parameter_control: false,
+            show_developer: false,
             parameter_control,
             toasts: Toasts::default(),
             highlighters: Vec::new(),
 
-            enable_markdown: true,
+            enable_markdown,
+            wrap_code: true,
+            density,
             edit_focused: false,
+            ime_composing: false,
+            settings,
+            pinned_to_bottom: true,
+            scroll_request: None,
+            stream_snapshot: None,
+            request: None,
+            count_last_reply: false,
+            confirm_clear: false,
+            dont_ask_again: false,
+            cleared_at: None,
+            title_handle: None,
+            title_requested: false,
+            confirm_summarize: false,
+            presentation: false,
+            summarize_handle: None,
+            history: InputHistory::default(),
+            selection: None,
+            retries,
+            dry_run: false,
+            dry_run_request: None,
+            expanded: HashSet::new(),
+        }
+    }
+
+    const STREAM_INTERVAL: Duration = Duration::from_millis(33);
+
+    /// The in-progress reply refreshed at most once per [`Self::STREAM_INTERVAL`],
+    /// with a blinking cursor at the end.
+    fn smooth_streaming_text(&mut self, ui: &egui::Ui, generate: &str) -> String {
+        let now = Instant::now();
+        let text = match &self.stream_snapshot {
+            Some((text, taken))
+                if now.duration_since(*taken) < Self::STREAM_INTERVAL
+                    && generate.starts_with(text.as_str()) =>
+            {
+                text.clone()
+            }
+            _ => {
+                self.stream_snapshot = Some((generate.to_string(), now));
+                generate.to_string()
+            }
+        };
+        ui.ctx().request_repaint_after(Self::STREAM_INTERVAL);
+        if ui.input(|i| i.time) % 1. < 0.5 {
+            format!("{text}_")
+        } else {
+            text
+        }
+    }
+
+    fn clear(&mut self) {
+        let mut chat = self.chatgpt.clone();
+        tokio::spawn(async move {
+            chat.clear_message().await;
+        });
+        self.expanded.clear();
+        self.cleared_at = Some(Instant::now());
+        self.toasts
+            .info("Messages cleared")
+            .set_duration(Some(UNDO_TIMEOUT));
+    }
+    fn undo_clear_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(cleared_at) = self.cleared_at else {
+            return;
+        };
+        if cleared_at.elapsed() > UNDO_TIMEOUT {
+            self.cleared_at = None;
+            let chat = self.chatgpt.clone();
+            tokio::spawn(async move { chat.forget_cleared().await });
+            return;
+        }
+        ui.ctx()
+            .request_repaint_after(UNDO_TIMEOUT.saturating_sub(cleared_at.elapsed()));
+        if ui
+            .add_sized(egui::vec2(50., 40.), egui::Button::new("Undo"))
+            .on_hover_text("Restore the cleared messages")
+            .clicked()
+        {
+            self.cleared_at = None;
+            let mut chat = self.chatgpt.clone();
+            tokio::spawn(async move { chat.undo_clear().await });
+        }
+    }
+    fn confirm_clear_ui(&mut self, ctx: &egui::Context) {
+        if !self.confirm_clear {
+            return;
+        }
+        let text = format!("Clear all messages of {}?", self.window_name);
+        match components::confirm(ctx, "Clear conversation", &text, &mut self.dont_ask_again) {
+            Some(true) => {
+                if self.dont_ask_again {
+                    self.settings.borrow_mut().confirm_destructive = false;
+                }
+                self.clear();
+                self.confirm_clear = false;
+            }
+            Some(false) => self.confirm_clear = false,
+            None => {}
+        }
+    }
+    /// The request of the last dry run, with a button to copy it.
+    fn dry_run_ui(&mut self, ctx: &egui::Context) {
+        let Some(request) = &self.dry_run_request else {
+            return;
+        };
+        let mut open = true;
+        let mut close = false;
+        egui::Window::new("Dry run")
+            .id(egui::Id::new("dry_run").with(&self.window_name))
+            .open(&mut open)
+            .collapsible(false)
+            .default_size([500., 400.])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Not sent, no message was added.");
+                    if ui.button("Copy").clicked() {
+                        ui.output_mut(|o| o.copied_text = request.clone());
+                    }
+                    close = ui.button("Close").clicked();
+                });
+                egui::ScrollArea::both().show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut request.as_str())
+                            .code_editor()
+                            .desired_width(f32::INFINITY),
+                    );
+                });
+            });
+        if !open || close {
+            self.dry_run_request = None;
+        }
+    }
+    fn summarize(&mut self) {
+        let chat = self.chatgpt.clone();
+        self.summarize_handle = spawn_guarded(&self.is_ready, async move {
+            chat.summarize_and_compress(Self::SUMMARY_KEEP_LAST).await
+        });
+    }
+    fn summarize_ui(&mut self, ctx: &egui::Context) {
+        if self.confirm_summarize {
+            let text = format!(
+                "Replace all but the last {} messages of {} with a summary?",
+                Self::SUMMARY_KEEP_LAST,
+                self.window_name
+            );
+            match components::confirm(
+                ctx,
+                "Summarize conversation",
+                &text,
+                &mut self.dont_ask_again,
+            ) {
+                Some(true) => {
+                    if self.dont_ask_again {
+                        self.settings.borrow_mut().confirm_destructive = false;
+                    }
+                    self.summarize();
+                    self.confirm_summarize = false;
+                }
+                Some(false) => self.confirm_summarize = false,
+                None => {}
+            }
+        }
+        if self
+            .summarize_handle
+            .as_ref()
+            .is_some_and(|h| h.is_finished())
+        {
+            let handle = self.summarize_handle.take().unwrap();
+            let res =
+                tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(handle));
+            match res {
+                Ok(Ok(())) => {
+                    // the replaced messages are restored like cleared ones
+                    self.cleared_at = Some(Instant::now());
+                    self.toasts
+                        .info("Older messages summarized")
+                        .set_duration(Some(UNDO_TIMEOUT));
+                }
+                Ok(Err(e)) => {
+                    self.toasts.error(format!("Failed to summarize: {}", e));
+                }
+                Err(e) => tracing::error!("{}", e),
+            }
+        }
+    }
+    /// The failed reply, with an explanation and a fix for known API errors.
+    /// `retry` is false when the failed message offers its own Resend.
+    fn error_card(&mut self, ui: &mut egui::Ui, error: &str, retry: bool) {
+        let hint = self.chatgpt.get_error().and_then(|error| error.hint());
+        let density = self.density;
+        let retry = message(
+            ui,
+            |ui| {
+                let color = ui.visuals().error_fg_color;
+                if let Some((explanation, _)) = hint {
+                    ui.colored_label(color, explanation);
+                    ui.small(error);
+                } else {
+                    ui.colored_label(color, error);
+                }
+                ui.horizontal(|ui| {
+                    match hint.map(|(_, action)| action) {
+                        Some(ErrorAction::UpdateKey) => {
+                            if ui.button("Open Tuning").clicked() {
+                                self.show_parameter_control = true;
+                            }
+                        }
+                        Some(ErrorAction::CheckBilling) => {
+                            ui.hyperlink_to(
+                                "Open billing",
+                                "https://platform.openai.com/account/billing",
+                            );
+                        }
+                        Some(ErrorAction::TrimConversation) => {
+                            if ui.button("Remove oldest message").clicked() {
+                                let mut chat = self.chatgpt.clone();
+                                tokio::spawn(async move { chat.remove_oldest().await });
+                            }
+                        }
+                        Some(ErrorAction::Retry) | None => {}
+                    }
+                    retry && ui.button("Retry").clicked()
+                })
+                .inner
+            },
+            &Role::Assistant,
+            density,
+        );
+        if retry {
+            self.resend();
+        }
+    }
+    /// Append the input with `role`, without calling the API.
+    fn inject(&mut self, role: Role) {
+        let content = self.text.trim().to_string();
+        if content.is_empty() {
+            return;
+        }
+        let mut chat = self.chatgpt.clone();
+        tokio::spawn(async move { chat.add_message(ChatMessage { role, content }).await });
+        self.text.clear();
+    }
+    /// Generate a reply to the messages as they are, the error is cleared once it starts.
+    fn resend(&mut self) {
+        let mut chat = self.chatgpt.clone();
+        self.spawn_generation(|token| async move {
+            chat.generate(token).await.ok();
+        });
+    }
+    /// Start a generation unless one is running, `is_ready` is cleared before
+    /// anything is spawned so a second submit in the same frame is refused.
+    /// `task` gets the token to generate with, an abort from now on cancels it.
+    fn spawn_generation<F>(&mut self, task: impl FnOnce(CancellationToken) -> F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // the running generation keeps its token, only this thread clears `is_ready`
+        if !self.is_ready.load(atomic::Ordering::Acquire) {
+            return false;
+        }
+        let handle = spawn_guarded(&self.is_ready, task(self.chatgpt.start()));
+        let spawned = handle.is_some();
+        if spawned {
+            self.complete_handle = handle;
+        }
+        spawned
+    }
+    /// Ask the input, it is kept when a generation is already running.
+    fn submit(&mut self) -> bool {
+        let input_text = self.text.trim().to_string();
+        if input_text.is_empty() {
+            return false;
+        }
+        if self.dry_run {
+            // the input is kept, to send it for real afterwards
+            match self.chatgpt.request_json(Some(self.expand(&input_text))) {
+                Ok(json) => self.dry_run_request = Some(json),
+                Err(e) => tracing::error!("{}", e),
+            }
+            return false;
+        }
+        let spawned = self.spawn_question(input_text);
+        if spawned {
+            self.text.clear();
+        }
+        spawned
+    }
+    /// `input_text` with its template variables replaced, when enabled.
+    fn expand(&self, input_text: &str) -> String {
+        if self.settings.borrow().expand_templates {
+            template::expand(input_text, |name| {
+                template::variable(name, self.selection.as_deref())
+            })
+        } else {
+            input_text.to_string()
+        }
+    }
+    /// Generate a reply to `input_text`, added to the history once spawned.
+    fn spawn_question(&mut self, input_text: String) -> bool {
+        let mut chat = self.chatgpt.clone();
+        // the history keeps the variables, to send them again later
+        let question = self.expand(&input_text);
+        let spawned = self.spawn_generation(|token| async move {
+            chat.question(question, token).await.ok();
+        });
+        if spawned {
+            self.history.push(input_text);
+        }
+        spawned
+    }
+    /// Message, character and word counts, including the reply being streamed.
+    fn status_line(
+        &mut self,
+        ui: &mut egui::Ui,
+        messages: &VecDeque<ChatMessage>,
+        generating: Option<&str>,
+    ) {
+        let (count, chars, words) = if self.count_last_reply {
+            let last_reply = generating.or_else(|| {
+                messages
+                    .iter()
+                    .rev()
+                    .find(|msg| msg.role == Role::Assistant)
+                    .map(|msg| msg.content.as_str())
+            });
+            count_text(last_reply)
+        } else {
+            count_text(
+                messages
+                    .iter()
+                    .map(|msg| msg.content.as_str())
+                    .chain(generating),
+            )
+        };
+        ui.horizontal(|ui| {
+            ui.small(format!(
+                "{} messages · {} characters · ~{} words",
+                count, chars, words
+            ));
+            ui.selectable_label(
+                self.count_last_reply,
+                egui::RichText::new("Last reply").small(),
+            )
+            .on_hover_text("Count only the latest assistant reply")
+            .clicked()
+            .then(|| self.count_last_reply = !self.count_last_reply);
+            if let Some(limits) = self.chatgpt.rate_limits() {
+                rate_limits_ui(ui, &limits);
+            }
+        });
+    }
+    /// Replace the input with an older or newer one on Up on the first line or
+    /// Down on the last line.
+    fn recall_history(&mut self, ui: &mut egui::Ui, input_id: egui::Id) {
+        let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), input_id) else {
+            return;
+        };
+        let Some(range) = state.ccursor_range() else {
+            return;
+        };
+        let cursor = self
+            .text
+            .char_indices()
+            .nth(range.primary.index)
+            .map_or(self.text.len(), |(i, _)| i);
+        let recalled = if !self.text[..cursor].contains('\n')
+            && ui.input_mut(|i| i.consume_key(Modifiers::NONE, egui::Key::ArrowUp))
+        {
+            self.history.older(&self.text)
+        } else if !self.text[cursor..].contains('\n')
+            && ui.input_mut(|i| i.consume_key(Modifiers::NONE, egui::Key::ArrowDown))
+        {
+            self.history.newer()
+        } else {
+            None
+        };
+        if let Some(recalled) = recalled {
+            self.text = recalled.to_string();
+            let end = egui::text::CCursor::new(self.text.chars().count());
+            state.set_ccursor_range(Some(egui::text::CCursorRange::one(end)));
+            state.store(ui.ctx(), input_id);
+        }
+    }
+    /// Consume the key combination that sends the input, unless an IME composition
+    /// is in progress or was committed by this Enter press.
+    fn consume_send_key(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut committed = false;
+        ui.input(|i| {
+            for event in &i.events {
+                match event {
+                    egui::Event::CompositionStart | egui::Event::CompositionUpdate(_) => {
+                        self.ime_composing = true;
+                    }
+                    egui::Event::CompositionEnd(_) => {
+                        self.ime_composing = false;
+                        committed = true;
+                    }
+                    _ => {}
+                }
+            }
+        });
+        if self.ime_composing || committed {
+            return false;
         }
+        let modifiers = match self.settings.borrow().send_mode {
+            SendMode::Enter => Modifiers::NONE,
+            SendMode::CtrlEnter => Modifiers::COMMAND,
+        };
+        ui.input_mut(|i| i.consume_key(modifiers, egui::Key::Enter))
     }
 }
 
@@ -62,18 +586,68 @@ impl super::Window for ChatWindow {
     fn name(&self) -> &str {
         &self.window_name
     }
-    fn show(&mut self, ctx: &egui::Context, _open: &mut bool) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            self.ui(ui);
-        });
+    /// A floating window, for a conversation detached from the dock.
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool) {
+        egui::Window::new(self.window_name.clone())
+            .id(egui::Id::new("detached").with(&self.window_name))
+            .open(open)
+            .default_size([640., 480.])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| TabWindow::actions(self, ui));
+                ui.separator();
+                self.ui(ui);
+            });
     }
 }
 
-impl super::TabWindow for ChatWindow {
+impl TabWindow for ChatWindow {
     fn set_name(&mut self, name: String) {
         self.window_name = name;
     }
 
+    fn markdown(&self) -> bool {
+        self.enable_markdown
+    }
+
+    fn set_markdown(&mut self, enabled: bool) {
+        self.enable_markdown = enabled;
+    }
+
+    fn wrap_code(&self) -> Option<bool> {
+        Some(self.wrap_code)
+    }
+
+    fn set_wrap_code(&mut self, wrap: bool) {
+        self.wrap_code = wrap;
+    }
+
+    fn is_generating(&self) -> bool {
+        self.complete_handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+
+    fn abort(&self) {
+        self.chatgpt.abort();
+    }
+
+    fn toggle_presentation(&mut self) {
+        self.presentation = !self.presentation;
+    }
+
+    fn draft(&self) -> Option<&str> {
+        (!self.text.trim().is_empty()).then_some(self.text.as_str())
+    }
+
+    fn ask(&mut self, question: String) -> bool {
+        let question = question.trim().to_string();
+        !question.is_empty() && self.chatgpt.has_valid_key() && self.spawn_question(question)
+    }
+
+    fn set_draft(&mut self, draft: String) {
+        self.text = draft;
+    }
+
     fn actions(&mut self, ui: &mut egui::Ui) {
         ui.selectable_label(self.show_model_table, "Model")
             .clicked()
@@ -85,41 +659,317 @@ impl super::TabWindow for ChatWindow {
             .then(|| {
                 self.show_parameter_control = !self.show_parameter_control;
             });
+        ui.selectable_label(self.show_developer, "Developer")
+            .on_hover_text("Capture the raw requests and responses of this conversation")
+            .clicked()
+            .then(|| {
+                self.show_developer = !self.show_developer;
+                self.chatgpt.exchange_log.set_enabled(self.show_developer);
+            });
+        if ui
+            .button("Copy request")
+            .on_hover_text("Copy the API request of the next reply as JSON, without the key")
+            .clicked()
+        {
+            match self.chatgpt.request_json(None) {
+                Ok(json) => {
+                    ui.output_mut(|o| o.copied_text = json);
+                    self.toasts.info("Request copied");
+                }
+                Err(e) => tracing::error!("{}", e),
+            }
+        }
+        if ui
+            .button("Export HTML")
+            .on_hover_text("Save the rendered conversation as a self-contained HTML file")
+            .clicked()
+        {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_file_name(&format!("{}.html", self.window_name))
+                .add_filter("HTML", &["html"])
+                .save_file()
+            {
+                let chat =
+                    tokio::task::block_in_place(|| self.chatgpt.data.blocking_read().clone());
+                match std::fs::write(&path, transcript_html(&self.window_name, &chat.messages)) {
+                    Ok(()) => {
+                        self.toasts.info(format!("Exported to {}", path.display()));
+                    }
+                    Err(e) => {
+                        self.toasts.error(format!("Failed to export: {}", e));
+                    }
+                }
+            }
+        }
+        ui.selectable_label(self.presentation, "Present")
+            .on_hover_text(format!(
+                "Show only the messages ({})",
+                ui.ctx().format_shortcut(&super::ChatApp::PRESENT)
+            ))
+            .clicked()
+            .then(|| self.presentation = !self.presentation);
+        let can_summarize = self.is_ready.load(atomic::Ordering::Relaxed)
+            && self.summarize_handle.is_none()
+            && self.chatgpt.has_valid_key();
+        ui.add_enabled(can_summarize, egui::Button::new("Summarize"))
+            .on_hover_text(format!(
+                "Replace all but the last {} messages with a summary",
+                Self::SUMMARY_KEEP_LAST
+            ))
+            .clicked()
+            .then(|| {
+                if self.settings.borrow().confirm_destructive {
+                    self.confirm_summarize = true;
+                } else {
+                    self.summarize();
+                }
+            });
+    }
+
+    fn take_request(&mut self) -> Option<TabRequest> {
+        self.request.take()
+    }
+
+    fn attach_file(&mut self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        let mut content = match std::fs::read(path) {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(content) if !content.contains('\0') => content,
+                _ => {
+                    self.toasts.error(format!("{} is not a text file", name));
+                    return true;
+                }
+            },
+            Err(e) => {
+                self.toasts.error(format!("Cannot read {}: {}", name, e));
+                return true;
+            }
+        };
+        if content.len() > Self::MAX_ATTACHMENT {
+            let mut end = Self::MAX_ATTACHMENT;
+            while !content.is_char_boundary(end) {
+                end -= 1;
+            }
+            content.truncate(end);
+            self.toasts.warning(format!(
+                "Only the first {} KB of {} were attached",
+                Self::MAX_ATTACHMENT / 1024,
+                name
+            ));
+        }
+        let language = path
+            .extension()
+            .map_or_else(String::new, |ext| ext.to_string_lossy().to_string());
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.text.push_str(&format!(
+            "{}\n```{}\n{}\n```\n",
+            name,
+            language,
+            content.trim_end()
+        ));
+        true
     }
 }
 
 impl ChatWindow {
-    fn selectable_text(&mut self, ui: &mut egui::Ui, mut text: &str, idx: &mut usize) {
-        if self.enable_markdown {
-            let highlighter = self.highlighters.get(*idx).cloned().unwrap_or_else(|| {
-                let highlighter = Rc::new(RefCell::new(MemoizedEasymarkHighlighter::default()));
-                self.highlighters.push(highlighter.clone());
-                highlighter
-            });
-            let mut layouter = |ui: &egui::Ui, easymark: &str, wrap_width: f32| {
-                let mut layout_job = highlighter.borrow_mut().highlight(ui, easymark);
-                layout_job.wrap.max_width = wrap_width;
-                ui.fonts(|f| f.layout_job(layout_job))
-            };
-            egui::TextEdit::multiline(&mut text)
-                .desired_width(f32::INFINITY)
-                .desired_rows(1)
-                .layouter(&mut layouter)
-                .show(ui)
+    /// A JSON mode reply pretty printed, in a code block when rendering markdown.
+    fn pretty_json(&self, content: &str) -> Option<String> {
+        let value = serde_json::from_str::<serde_json::Value>(content).ok()?;
+        let json = serde_json::to_string_pretty(&value).ok()?;
+        if self.enable_markdown || self.presentation {
+            Some(format!("```json\n{}\n```", json))
         } else {
-            egui::TextEdit::multiline(&mut text)
-                .desired_width(f32::INFINITY)
-                .desired_rows(1)
-                .show(ui)
+            Some(json)
         }
-        .response
-        .context_menu(|ui| {
+    }
+    fn selectable_text(&mut self, ui: &mut egui::Ui, text: &str, idx: &mut usize) {
+        let markdown = self.enable_markdown || self.presentation;
+        let parts = if markdown && !self.wrap_code {
+            // a highlighter for each part, the code blocks scroll sideways
+            easy_mark::parser::split_code_blocks(text)
+        } else {
+            vec![(text, false)]
+        };
+        let mut selection = None;
+        let mut response: Option<egui::Response> = None;
+        for (part, code) in parts {
+            let output = self.text_part(ui, part, markdown, code, idx);
+            if let Some(range) = output.state.ccursor_range() {
+                let [start, end] = range.sorted();
+                if start.index < end.index {
+                    selection = Some(
+                        part.chars()
+                            .skip(start.index)
+                            .take(end.index - start.index)
+                            .collect::<String>(),
+                    );
+                }
+            }
+            response = Some(match response {
+                Some(all) => all | output.response,
+                None => output.response,
+            });
+        }
+        let Some(response) = response else {
+            return;
+        };
+        if let Some(selection) = &selection {
+            self.selection = Some(selection.clone());
+        }
+        let can_ask = self.is_ready.load(atomic::Ordering::Relaxed) && self.chatgpt.has_valid_key();
+        response.context_menu(|ui| {
+            if let Some(selection) = selection.filter(|s| !s.trim().is_empty()) {
+                if ui
+                    .add_enabled(can_ask, egui::Button::new("Explain this"))
+                    .on_hover_text("Ask to explain the selected text in this conversation")
+                    .clicked()
+                {
+                    self.explain(&selection);
+                    ui.close_menu();
+                }
+                ui.separator();
+            }
             ui.button("Copy All").clicked().then(|| {
                 ui.output_mut(|o| o.copied_text = text.to_string());
                 ui.close_menu();
             });
+            ui.button("Copy as plain text").clicked().then(|| {
+                ui.output_mut(|o| o.copied_text = easy_mark::parser::plain_text(text));
+                ui.close_menu();
+            });
         });
+    }
+    /// `text` without wrapping in a horizontal scroll area when `scroll`.
+    fn text_part(
+        &mut self,
+        ui: &mut egui::Ui,
+        mut text: &str,
+        markdown: bool,
+        scroll: bool,
+        idx: &mut usize,
+    ) -> egui::text_edit::TextEditOutput {
+        let width = ui.available_width();
+        let links = if markdown {
+            easy_mark::parser::links(text)
+        } else {
+            Vec::new()
+        };
+        let link_color = ui.visuals().hyperlink_color;
+        let mut show = |ui: &mut egui::Ui| {
+            if markdown {
+                let highlighter = self.highlighters.get(*idx).cloned().unwrap_or_else(|| {
+                    let highlighter = Rc::new(RefCell::new(MemoizedEasymarkHighlighter::default()));
+                    self.highlighters.push(highlighter.clone());
+                    highlighter
+                });
+                let mut layouter = |ui: &egui::Ui, easymark: &str, wrap_width: f32| {
+                    let mut layout_job = highlighter.borrow_mut().highlight(ui, easymark);
+                    for (range, _) in &links {
+                        layout_job = components::format_range(layout_job, range.clone(), |f| {
+                            f.color = link_color;
+                            f.underline = egui::Stroke::new(1., link_color);
+                        });
+                    }
+                    layout_job.wrap.max_width = if scroll { f32::INFINITY } else { wrap_width };
+                    ui.fonts(|f| f.layout_job(layout_job))
+                };
+                egui::TextEdit::multiline(&mut text)
+                    .desired_width(width)
+                    .desired_rows(1)
+                    .layouter(&mut layouter)
+                    .show(ui)
+            } else {
+                let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                    let layout_job = components::plain_job(ui, text, wrap_width);
+                    ui.fonts(|f| f.layout_job(layout_job))
+                };
+                egui::TextEdit::multiline(&mut text)
+                    .desired_width(width)
+                    .desired_rows(1)
+                    .layouter(&mut layouter)
+                    .show(ui)
+            }
+        };
+        let output = if scroll {
+            egui::ScrollArea::horizontal()
+                .id_source(("code", *idx))
+                .show(ui, show)
+                .inner
+        } else {
+            show(ui)
+        };
+        link_ui(ui, &output, text, &links);
         idx.add_assign(1);
+        output
+    }
+    /// Ask about `selection` as a follow-up in this conversation.
+    fn explain(&mut self, selection: &str) {
+        let mut chat = self.chatgpt.clone();
+        let question = format!("Explain: {}", selection.trim());
+        self.spawn_generation(|token| async move {
+            chat.question(question, token).await.ok();
+        });
+    }
+}
+
+impl ChatWindow {
+    /// Ask for a title once the first reply of an untitled conversation is done.
+    fn auto_title(&mut self, chat: &Chat, is_ready: bool) {
+        if is_ready
+            && !self.title_requested
+            && self.settings.borrow().auto_title
+            && list_view::is_untitled(&self.window_name)
+            && chat.messages.iter().any(|msg| msg.role == Role::Assistant)
+        {
+            self.title_requested = true;
+            let chat = self.chatgpt.clone();
+            self.title_handle = Some(tokio::spawn(async move { chat.suggest_title().await }));
+        }
+        if self.title_handle.as_ref().is_some_and(|h| h.is_finished()) {
+            let handle = self.title_handle.take().unwrap();
+            let title =
+                tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(handle));
+            match title {
+                Ok(Ok(title)) => self.request = Some(TabRequest::Rename(title)),
+                Ok(Err(e)) => tracing::warn!("failed to suggest a title: {}", e),
+                Err(e) => tracing::warn!("failed to suggest a title: {}", e),
+            }
+        }
+    }
+
+    fn developer_ui(&mut self, ui: &mut egui::Ui) {
+        let exchanges = self.chatgpt.exchange_log.exchanges();
+        ui.horizontal(|ui| {
+            ui.heading("Developer");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.button("Clear")
+                    .clicked()
+                    .then(|| self.chatgpt.exchange_log.clear());
+                ui.button("Copy").clicked().then(|| {
+                    let text = exchanges.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                    ui.output_mut(|o| o.copied_text = text.join("\n"));
+                });
+            });
+        });
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                let last = exchanges.len().saturating_sub(1);
+                for (i, exchange) in exchanges.iter().enumerate() {
+                    egui::CollapsingHeader::new(format!("Request #{}", i + 1))
+                        .default_open(i == last)
+                        .show(ui, |ui| {
+                            ui.monospace(&exchange.request);
+                            ui.label("Response");
+                            ui.monospace(exchange.response.join("\n"));
+                        });
+                }
+            });
     }
 }
 
@@ -133,7 +983,11 @@ impl super::View for ChatWindow {
             .is_some_and(|generate| generate.is_err());
         let generate_text = generate_res.map(|generate| generate.unwrap_or_else(|e| e));
 
+        self.confirm_clear_ui(ui.ctx());
+        self.dry_run_ui(ui.ctx());
+        self.summarize_ui(ui.ctx());
         let is_ready = self.is_ready.load(atomic::Ordering::Relaxed);
+        let has_valid_key = self.chatgpt.has_valid_key();
         let ready_to_retry = chat
             .messages
             .back()
@@ -143,10 +997,30 @@ impl super::View for ChatWindow {
         if is_ready {
             self.complete_handle.take();
         }
+        if generate_text.is_none() {
+            self.stream_snapshot = None;
+        }
+        self.auto_title(&chat, is_ready);
+        self.chatgpt.set_auto_trim(self.settings.borrow().auto_trim);
+        let trimmed = self.chatgpt.take_trimmed();
+        if trimmed > 0 {
+            self.toasts.info(format!(
+                "Removed the {} oldest messages to fit the context window",
+                trimmed
+            ));
+        }
 
+        let presenting = self.presentation;
+        let density = self.density;
+        let json_mode = chat.response_format == Some(ResponseFormat::json_object());
+        self.model_table.selected = Some(chat.model.clone());
+        if self.parameter_control.set_model(&chat.model) {
+            // the ranges follow the model
+            self.parameter_control.refresh(self.chatgpt.params());
+        }
         egui::SidePanel::left(format!("left_{}", self.name())).show_animated_inside(
             ui,
-            self.show_model_table,
+            self.show_model_table && !presenting,
             |ui| match self.model_table.ui(ui) {
                 super::model_table::ResponseEvent::SelectModel(id) => {
                     let mut chatgpt = self.chatgpt.clone();
@@ -158,65 +1032,133 @@ impl super::View for ChatWindow {
 
         egui::SidePanel::right(format!("right_{}", self.name())).show_animated_inside(
             ui,
-            self.show_parameter_control,
+            self.show_parameter_control && !presenting,
             |ui| {
                 self.parameter_control.ui(ui);
             },
         );
-        egui::TopBottomPanel::top(format!("top_{}", self.name())).show_inside(ui, |ui| {
-            ui.horizontal(|ui| {
-                ui.heading(&self.window_name);
-                ui.separator();
-                ui.heading(chat.model);
-                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    ui.checkbox(&mut self.enable_markdown, "Markdown");
-                });
-            });
-        });
-        egui::TopBottomPanel::bottom(format!("bottom_{}", self.name())).show_inside(ui, |ui| {
-            ui.with_layout(egui::Layout::top_down(egui::Align::RIGHT), |ui| {
-                ui.add_enabled_ui(is_ready, |ui| {
-                    if self.edit_focused
-                        && ui.input_mut(|i| i.consume_key(Modifiers::NONE, egui::Key::Enter))
-                    {
-                        let input_text = self.text.trim().to_string();
-                        if !input_text.is_empty() {
-                            let mut chat = self.chatgpt.clone();
-                            let is_ready = self.is_ready.clone();
-                            self.complete_handle.replace(tokio::spawn(async move {
-                                is_ready.store(false, atomic::Ordering::Relaxed);
-                                chat.question(input_text).await.ok();
-                                is_ready.store(true, atomic::Ordering::Relaxed);
-                            }));
-                            self.text.clear();
-                            return;
-                        }
+        egui::SidePanel::right(format!("developer_{}", self.name())).show_animated_inside(
+            ui,
+            self.show_developer && !presenting,
+            |ui| self.developer_ui(ui),
+        );
+        egui::TopBottomPanel::top(format!("top_{}", self.name())).show_animated_inside(
+            ui,
+            !presenting,
+            |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(&self.window_name);
+                    ui.separator();
+                    ui.heading(chat.model);
+                    if let Some(fingerprint) = self.chatgpt.system_fingerprint() {
+                        ui.weak(fingerprint)
+                            .on_hover_text("System fingerprint of the last reply");
                     }
-                    let response = ui.add(
-                        egui::TextEdit::multiline(&mut self.text).desired_width(f32::INFINITY),
-                    );
-                    self.edit_focused = response.has_focus();
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.checkbox(&mut self.enable_markdown, "Markdown");
+                        ui.add_enabled(
+                            self.enable_markdown,
+                            egui::Checkbox::new(&mut self.wrap_code, "Wrap code"),
+                        )
+                        .on_hover_text("Scroll long lines of code blocks sideways when off");
+                        let mut compact = self.density == Density::Compact;
+                        if ui
+                            .checkbox(&mut compact, "Compact")
+                            .on_hover_text("Show the messages without borders, closer together")
+                            .changed()
+                        {
+                            self.density = if compact {
+                                Density::Compact
+                            } else {
+                                Density::Comfortable
+                            };
+                            // the next windows open the same way
+                            self.settings.borrow_mut().density = self.density;
+                        }
+                        ui.separator();
+                        let latest = if self.pinned_to_bottom {
+                            "⏷"
+                        } else {
+                            "⏷ Jump to latest"
+                        };
+                        ui.button(latest)
+                            .on_hover_text("Scroll to bottom")
+                            .clicked()
+                            .then(|| self.scroll_request = Some(ScrollRequest::Bottom));
+                        ui.button("⏶")
+                            .on_hover_text("Scroll to top")
+                            .clicked()
+                            .then(|| self.scroll_request = Some(ScrollRequest::Top));
+                    });
                 });
-                ui.add_space(5.);
-                ui.horizontal(|ui| {
+            },
+        );
+        egui::TopBottomPanel::bottom(format!("bottom_{}", self.name())).show_animated_inside(
+            ui,
+            !presenting,
+            |ui| {
+                self.status_line(ui, &chat.messages, generate_text.as_deref());
+                ui.with_layout(egui::Layout::top_down(egui::Align::RIGHT), |ui| {
                     ui.add_enabled_ui(is_ready, |ui| {
-                        ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Send"))
+                        if self.edit_focused
+                            && (has_valid_key || self.dry_run)
+                            && self.consume_send_key(ui)
+                            && self.submit()
+                        {
+                            return;
+                        }
+                        let input_id = egui::Id::new("input").with(&self.window_name);
+                        if self.edit_focused {
+                            self.recall_history(ui, input_id);
+                        }
+                        let response = ui.add(
+                            egui::TextEdit::multiline(&mut self.text)
+                                .id(input_id)
+                                .desired_width(f32::INFINITY),
+                        );
+                        self.edit_focused = response.has_focus();
+                        response.context_menu(|ui| {
+                            if ui.button("Insert date/time").clicked() {
+                                let now = template::variable("datetime", None).unwrap_or_default();
+                                self.text.push_str(&now);
+                                ui.close_menu();
+                            }
+                        });
+                    });
+                    ui.add_space(5.);
+                    ui.horizontal(|ui| {
+                        ui.add_enabled_ui(is_ready, |ui| {
+                            ui.add_enabled_ui(has_valid_key || self.dry_run, |ui| {
+                                ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Send"))
+                            })
+                            .inner
+                            .on_disabled_hover_text(
+                                "Set an API key in the Tuning panel or with OPENAI_API_KEY first",
+                            )
                             .clicked()
-                            .then(|| {
-                                let input_text = self.text.trim().to_string();
-                                if !input_text.is_empty() {
-                                    let mut chat = self.chatgpt.clone();
-                                    let is_ready = self.is_ready.clone();
-                                    self.complete_handle.replace(tokio::spawn(async move {
-                                        is_ready.store(false, atomic::Ordering::Relaxed);
-                                        chat.question(input_text).await.ok();
-                                        is_ready.store(true, atomic::Ordering::Relaxed);
-                                    }));
-                                    self.text.clear();
+                            .then(|| self.submit());
+                            ui.vertical(|ui| {
+                                for (role, label) in [
+                                    (Role::Assistant, "As assistant"),
+                                    (Role::System, "As system"),
+                                ] {
+                                    ui.small_button(label)
+                                        .on_hover_text(format!(
+                                            "Add the input as a {} message, without sending it",
+                                            role.to_string().to_lowercase()
+                                        ))
+                                        .clicked()
+                                        .then(|| self.inject(role));
                                 }
+                                ui.checkbox(&mut self.dry_run, "Dry run").on_hover_text(
+                                    "Show the request Send would make, without sending it",
+                                );
                             });
-                        ui.add_enabled_ui(can_remove_last, |ui| {
-                            ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Remove Last"))
+                            ui.add_enabled_ui(can_remove_last, |ui| {
+                                ui.add_sized(
+                                    egui::vec2(50., 40.),
+                                    egui::Button::new("Remove Last"),
+                                )
                                 .clicked()
                                 .then(|| {
                                     let mut chat = self.chatgpt.clone();
@@ -224,108 +1166,509 @@ impl super::View for ChatWindow {
                                         chat.remove_last().await;
                                     });
                                 });
+                            });
+                            ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Clear"))
+                                .clicked()
+                                .then(|| {
+                                    if self.settings.borrow().confirm_destructive {
+                                        self.confirm_clear = true;
+                                    } else {
+                                        self.clear();
+                                    }
+                                });
+                            self.undo_clear_ui(ui);
                         });
-                        ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Clear"))
-                            .clicked()
-                            .then(|| {
-                                let mut chat = self.chatgpt.clone();
-                                tokio::spawn(async move {
-                                    chat.clear_message().await;
+                        if self.complete_handle.is_some() {
+                            ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Abort"))
+                                .clicked()
+                                .then(|| {
+                                    // the task finishes with the partial reply
+                                    self.chatgpt.abort();
                                 });
-                            });
+                        }
+                        if ready_to_retry {
+                            ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Retry"))
+                                .clicked()
+                                .then(|| self.resend());
+                        }
                     });
-                    if self.complete_handle.is_some() {
-                        ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Abort"))
-                            .clicked()
-                            .then(|| {
-                                self.complete_handle.take().unwrap().abort();
-                                self.is_ready.store(true, atomic::Ordering::Relaxed);
-                            });
-                    }
-                    if ready_to_retry {
-                        ui.add_sized(egui::vec2(50., 40.), egui::Button::new("Retry"))
-                            .clicked()
-                            .then(|| {
-                                let mut chat = self.chatgpt.clone();
-                                let is_ready = self.is_ready.clone();
-                                self.complete_handle.replace(tokio::spawn(async move {
-                                    is_ready.store(false, atomic::Ordering::Relaxed);
-                                    chat.generate().await.ok();
-                                    is_ready.store(true, atomic::Ordering::Relaxed);
-                                }));
-                            });
-                    }
                 });
-            });
-        });
+            },
+        );
+        // governs the whole conversation, so it stays above the transcript
+        let system = chat.messages.front().filter(|msg| msg.role == Role::System);
+        // the highlighters are counted from the system message on
+        let mut idx = 0;
+        if let Some(system) = system {
+            egui::TopBottomPanel::top(format!("system_{}", self.name()))
+                .frame(egui::Frame::side_top_panel(ui.style()).fill(ui.visuals().faint_bg_color))
+                .show_inside(ui, |ui| {
+                    egui::CollapsingHeader::new("System message")
+                        .id_source(("system", &self.window_name))
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            egui::ScrollArea::vertical()
+                                .id_source("system_scroll")
+                                .max_height(Self::SYSTEM_MAX_HEIGHT)
+                                .show(ui, |ui| self.selectable_text(ui, &system.content, &mut idx));
+                        });
+                });
+        }
         egui::CentralPanel::default().show_inside(ui, |ui| {
-            egui::ScrollArea::vertical()
-                .stick_to_bottom(true)
-                .show(ui, |ui| {
-                    ui.vertical(|ui| {
-                        let mut idx = 0;
-                        for msg in chat.messages.iter() {
-                            message(
-                                ui,
-                                |ui| {
-                                    self.selectable_text(ui, &msg.content, &mut idx);
-                                },
-                                &msg.role,
-                            );
+            let mut scroll_area =
+                egui::ScrollArea::vertical().stick_to_bottom(self.pinned_to_bottom);
+            if let Some(ScrollRequest::Top) = self.scroll_request {
+                scroll_area = scroll_area.vertical_scroll_offset(0.);
+            }
+            let output = scroll_area.show(ui, |ui| {
+                ui.vertical(|ui| {
+                    let shown_above = usize::from(system.is_some());
+                    let collapse_lines =
+                        Some(self.settings.borrow().collapse_lines).filter(|&lines| lines > 0);
+                    let last = chat.messages.len().saturating_sub(1);
+                    for (i, msg) in chat.messages.iter().enumerate().skip(shown_above) {
+                        let can_regenerate = i == last
+                            && msg.role == Role::Assistant
+                            && is_ready
+                            && generate_text.is_none()
+                            && has_valid_key;
+                        // the question the failed request was sent for
+                        let failed = is_error && i == last && msg.role == Role::User;
+                        let (fork, delta, continue_reply, resend) = message(
+                            ui,
+                            |ui| {
+                                let json = (json_mode && msg.role == Role::Assistant)
+                                    .then(|| self.pretty_json(&msg.content))
+                                    .flatten();
+                                let content = json.as_deref().unwrap_or(&msg.content);
+                                let expanded = self.expanded.contains(&i);
+                                let collapsed = collapse_lines
+                                    .and_then(|lines| preview(content, lines))
+                                    .filter(|_| !expanded);
+                                match collapsed {
+                                    Some((preview, _)) => {
+                                        self.selectable_text(ui, &format!("{}…", preview), &mut idx)
+                                    }
+                                    None => self.selectable_text(ui, content, &mut idx),
+                                }
+                                if let Some((_, hidden)) = collapsed {
+                                    ui.horizontal(|ui| {
+                                        if ui
+                                            .small_button(format!("Show more ({} lines)", hidden))
+                                            .clicked()
+                                        {
+                                            self.expanded.insert(i);
+                                        }
+                                        if ui
+                                            .small_button("Copy")
+                                            .on_hover_text("Copy the whole message")
+                                            .clicked()
+                                        {
+                                            ui.output_mut(|o| o.copied_text = content.to_string());
+                                        }
+                                    });
+                                } else if expanded && ui.small_button("Show less").clicked() {
+                                    self.expanded.remove(&i);
+                                }
+                                if presenting {
+                                    return (false, None, false, false);
+                                }
+                                ui.horizontal(|ui| {
+                                    let resend = failed && {
+                                        ui.colored_label(ui.visuals().error_fg_color, "Not sent");
+                                        ui.add_enabled(
+                                            is_ready && has_valid_key,
+                                            egui::Button::new("Resend").small(),
+                                        )
+                                        .on_hover_text("Send this message again")
+                                        .clicked()
+                                    };
+                                    let fork = ui
+                                        .small_button("Fork from here")
+                                        .on_hover_text(
+                                            "Continue in a new conversation from this message",
+                                        )
+                                        .clicked();
+                                    let delta =
+                                        can_regenerate.then(|| regenerate_buttons(ui)).flatten();
+                                    let continue_reply = can_regenerate
+                                        && self.chatgpt.is_truncated()
+                                        && ui
+                                            .small_button("Continue")
+                                            .on_hover_text("The reply was cut off by max_tokens")
+                                            .clicked();
+                                    (fork, delta, continue_reply, resend)
+                                })
+                                .inner
+                            },
+                            &msg.role,
+                            density,
+                        );
+                        if resend {
+                            self.resend();
+                        }
+                        if fork {
+                            let fork = self.chatgpt.fork(i);
+                            self.request = Some(TabRequest::Fork(fork.into()));
+                        }
+                        if let Some(delta) = delta {
+                            let chat = self.chatgpt.clone();
+                            self.spawn_generation(|token| async move {
+                                chat.regenerate_with_temperature(delta, token).await.ok();
+                            });
+                        }
+                        if continue_reply {
+                            let chat = self.chatgpt.clone();
+                            self.spawn_generation(|token| async move {
+                                chat.continue_reply(token).await.ok();
+                            });
                         }
+                    }
 
-                        if let Some(generate) = &generate_text {
+                    if is_error {
+                        let failed_question = chat
+                            .messages
+                            .back()
+                            .is_some_and(|msg| msg.role == Role::User);
+                        self.error_card(ui, &generate_text.unwrap_or_default(), !failed_question);
+                    } else if let Some(generate) = &generate_text {
+                        if self.settings.borrow().smooth_streaming {
+                            let generate = self.smooth_streaming_text(ui, generate);
                             message(
                                 ui,
                                 |ui| self.selectable_text(ui, &generate, &mut idx),
                                 &Role::Assistant,
+                                density,
                             );
-
-                            ui.ctx().request_repaint();
-                        } else if is_error {
+                        } else {
                             message(
                                 ui,
-                                |ui| {
-                                    self.selectable_text(ui, &generate_text.unwrap(), &mut idx);
-                                    ui.button("Retry")
-                                },
-                                &Role::Assistant,
-                            )
-                            .clicked()
-                            .then(|| {
-                                let mut chat = self.chatgpt.clone();
-                                tokio::spawn(async move { chat.generate().await })
-                            });
-                        } else if !is_ready {
-                            message(
-                                ui,
-                                |ui| {
-                                    ui.spinner();
-                                },
+                                |ui| self.selectable_text(ui, generate, &mut idx),
                                 &Role::Assistant,
+                                density,
                             );
+                            ui.ctx().request_repaint();
                         }
-                        if idx + 1 < self.highlighters.len() {
-                            self.highlighters.pop();
+                    } else if !is_ready {
+                        let queued = self.chatgpt.is_queued();
+                        let retry = self.retries.borrow().clone();
+                        message(
+                            ui,
+                            |ui| {
+                                if queued {
+                                    ui.weak("Queued, waiting for other conversations");
+                                } else if let Some(retry) = &retry {
+                                    ui.horizontal(|ui| {
+                                        ui.spinner();
+                                        let wait = retry
+                                            .retry_at
+                                            .saturating_duration_since(Instant::now());
+                                        ui.weak(format!(
+                                            "retrying ({}/{}) in {}s…",
+                                            retry.attempt,
+                                            retry.max_attempts,
+                                            wait.as_secs_f32().ceil()
+                                        ))
+                                        .on_hover_text(&retry.error);
+                                    });
+                                } else {
+                                    ui.spinner();
+                                }
+                            },
+                            &Role::Assistant,
+                            density,
+                        );
+                        if queued || retry.is_some() {
+                            ui.ctx().request_repaint();
                         }
-                    });
+                    }
+                    if idx + 1 < self.highlighters.len() {
+                        self.highlighters.pop();
+                    }
                 });
+                let end = ui.allocate_response(egui::Vec2::ZERO, egui::Sense::hover());
+                if let Some(ScrollRequest::Bottom) = self.scroll_request {
+                    end.scroll_to_me(Some(egui::Align::BOTTOM));
+                }
+            });
+            // a requested jump only shows up in the offset next frame
+            self.pinned_to_bottom = match self.scroll_request.take() {
+                Some(ScrollRequest::Top) => false,
+                Some(ScrollRequest::Bottom) => true,
+                None => {
+                    output.state.offset.y + output.inner_rect.height() >= output.content_size.y - 1.
+                }
+            };
         });
         self.toasts.show(ui.ctx());
     }
 }
 
+/// Spawn `task` unless `is_ready` is already cleared, it is set again once `task` ends.
+fn spawn_guarded<F>(is_ready: &Arc<atomic::AtomicBool>, task: F) -> Option<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    is_ready
+        .compare_exchange(
+            true,
+            false,
+            atomic::Ordering::AcqRel,
+            atomic::Ordering::Relaxed,
+        )
+        .ok()?;
+    let is_ready = is_ready.clone();
+    Some(tokio::spawn(async move {
+        let output = task.await;
+        is_ready.store(true, atomic::Ordering::Release);
+        output
+    }))
+}
+
+/// Open the link of `links` under the pointer when clicked, by byte range of `text`.
+fn link_ui(
+    ui: &egui::Ui,
+    output: &egui::text_edit::TextEditOutput,
+    text: &str,
+    links: &[(std::ops::Range<usize>, &str)],
+) {
+    let Some(pos) = output.response.hover_pos() else {
+        return;
+    };
+    let pos = pos - output.text_draw_pos;
+    if links.is_empty() || !output.galley.rect.contains(pos.to_pos2()) {
+        return;
+    }
+    let cursor = output.galley.cursor_from_pos(pos);
+    // the cursor is between two characters, the one under the pointer may be before it
+    let mut index = cursor.ccursor.index;
+    if pos.x < output.galley.pos_from_cursor(&cursor).min.x {
+        index = index.saturating_sub(1);
+    }
+    let byte = text
+        .char_indices()
+        .nth(index)
+        .map_or(text.len(), |(i, _)| i);
+    let Some((_, url)) = links.iter().find(|(range, _)| range.contains(&byte)) else {
+        return;
+    };
+    ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+    if output.response.clicked() {
+        ui.ctx().output_mut(|o| o.open_url(url));
+    } else {
+        output.response.clone().on_hover_text_at_pointer(*url);
+    }
+}
+
+/// Number of texts, characters and whitespace separated words.
+fn count_text<'a>(texts: impl IntoIterator<Item = &'a str>) -> (usize, usize, usize) {
+    texts
+        .into_iter()
+        .fold((0, 0, 0), |(count, chars, words), text| {
+            (
+                count + 1,
+                chars + text.chars().count(),
+                words + text.split_whitespace().count(),
+            )
+        })
+}
+
+/// A standalone HTML document of the messages with their role as section headers.
+fn transcript_html<'a>(title: &str, messages: impl IntoIterator<Item = &'a ChatMessage>) -> String {
+    const STYLE: &str =
+        "body{font-family:sans-serif;max-width:800px;margin:2em auto;line-height:1.5}\
+section{margin-bottom:1.5em}\
+h2{font-size:1em;margin:0 0 .5em;padding:.2em .6em;border-radius:4px;background:#eee}\
+.user h2{background:#dbeafe}.assistant h2{background:#dcfce7}.system h2{background:#fef9c3}\
+pre{background:#f6f8fa;padding:.8em;overflow-x:auto}\
+code{font-family:monospace;background:#f6f8fa}\
+table{border-collapse:collapse}th,td{border:1px solid #ccc;padding:.3em .6em}\
+.h1{font-size:1.6em;font-weight:bold}.h2{font-size:1.4em;font-weight:bold}\
+.h3,.h4,.h5,.h6{font-size:1.2em;font-weight:bold}\
+.quote{color:#666;border-left:3px solid #ccc;padding-left:.5em}";
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        easy_mark::parser::escape_html(title),
+        STYLE
+    );
+    for message in messages {
+        html.push_str(&format!(
+            "<section class=\"{}\">\n<h2>{}</h2>\n{}\n</section>\n",
+            message.role.to_string().to_lowercase(),
+            message.role,
+            easy_mark::parser::html(&message.content)
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Temperature change requested by the quick regenerate buttons.
+fn regenerate_buttons(ui: &mut egui::Ui) -> Option<f32> {
+    const TEMPERATURE_STEP: f32 = 0.4;
+    ui.horizontal(|ui| {
+        if ui
+            .small_button("More creative")
+            .on_hover_text("Regenerate with a higher temperature")
+            .clicked()
+        {
+            Some(TEMPERATURE_STEP)
+        } else if ui
+            .small_button("More precise")
+            .on_hover_text("Regenerate with a lower temperature")
+            .clicked()
+        {
+            Some(-TEMPERATURE_STEP)
+        } else {
+            None
+        }
+    })
+    .inner
+}
+
+/// Tells the roles apart in both themes: the accent for the user, a neutral
+/// gray for the assistant and the warning color for the system.
+fn role_color(role: &Role, visuals: &egui::Visuals) -> egui::Color32 {
+    match role {
+        Role::User => visuals.hyperlink_color,
+        Role::Assistant => visuals.weak_text_color(),
+        Role::System => visuals.warn_fg_color,
+    }
+}
+
 pub fn message<R>(
     ui: &mut egui::Ui,
     add_contents: impl FnOnce(&mut egui::Ui) -> R,
     role: &Role,
+    density: Density,
 ) -> R {
-    ui.group(|ui| {
-        ui.vertical(|ui| {
-            ui.label(format!("{}: ", role.to_string()));
-            add_contents(ui)
+    let color = role_color(role, ui.visuals());
+    match density {
+        Density::Comfortable => {
+            let response = ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.colored_label(color, format!("{}: ", role.to_string()));
+                    add_contents(ui)
+                })
+                .inner
+            });
+            // a border on the left, the others keep the color of the group
+            let rect = response.response.rect;
+            ui.painter().vline(
+                rect.left(),
+                rect.top()..=rect.bottom(),
+                egui::Stroke::new(2., color),
+            );
+            response.inner
+        }
+        Density::Compact => {
+            ui.vertical(|ui| {
+                ui.spacing_mut().item_spacing.y = 2.;
+                ui.label(egui::RichText::new(role.to_string()).small().color(color));
+                add_contents(ui)
+            })
+            .inner
+        }
+    }
+}
+
+/// What is left of the rate limits, in the warning color when running low.
+fn rate_limits_ui(ui: &mut egui::Ui, limits: &RateLimits) {
+    let left = [
+        (limits.remaining_requests, "requests"),
+        (limits.remaining_tokens, "tokens"),
+    ]
+    .into_iter()
+    .filter_map(|(remaining, unit)| remaining.map(|remaining| format!("{} {}", remaining, unit)))
+    .collect::<Vec<_>>();
+    if left.is_empty() {
+        return;
+    }
+    let mut text = egui::RichText::new(format!("{} left", left.join(" · "))).small();
+    if limits.is_low() {
+        text = text.color(ui.visuals().warn_fg_color);
+    }
+    let resets = [
+        (limits.limit_requests, &limits.reset_requests, "requests"),
+        (limits.limit_tokens, &limits.reset_tokens, "tokens"),
+    ]
+    .into_iter()
+    .filter_map(|(limit, reset, unit)| {
+        let limit = limit?;
+        Some(match reset {
+            Some(reset) => format!("limit of {} {}, all back in {}", limit, unit, reset),
+            None => format!("limit of {} {}", limit, unit),
         })
-        .inner
     })
-    .inner
+    .collect::<Vec<_>>();
+    let response = ui.label(text);
+    if !resets.is_empty() {
+        response.on_hover_text(resets.join("\n"));
+    }
+}
+
+/// The first `lines` lines of `text` and how many more there are, `None` when it
+/// is not longer.
+fn preview(text: &str, lines: usize) -> Option<(&str, usize)> {
+    let total = text.lines().count();
+    if total <= lines {
+        return None;
+    }
+    let end = text
+        .match_indices('\n')
+        .nth(lines - 1)
+        .map_or(text.len(), |(end, _)| end);
+    Some((&text[..end], total - lines))
+}
+
+#[test]
+fn test_input_history() {
+    let mut history = InputHistory::default();
+    assert_eq!(history.older("draft"), None);
+    history.push("first".to_string());
+    history.push("second".to_string());
+    history.push("second".to_string());
+    assert_eq!(history.older("draft"), Some("second"));
+    assert_eq!(history.older("second"), Some("first"));
+    assert_eq!(history.older("first"), None);
+    assert_eq!(history.newer(), Some("second"));
+    assert_eq!(history.newer(), Some("draft"));
+    assert_eq!(history.newer(), None);
+}
+
+#[test]
+fn test_double_submit() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let is_ready = Arc::new(atomic::AtomicBool::new(true));
+        let requests = Arc::new(atomic::AtomicUsize::new(0));
+        let release = Arc::new(tokio::sync::Notify::new());
+        let submit = || {
+            let requests = requests.clone();
+            let release = release.clone();
+            spawn_guarded(&is_ready, async move {
+                requests.fetch_add(1, atomic::Ordering::Relaxed);
+                release.notified().await;
+            })
+        };
+        // two enters before the first task has run
+        let first = submit();
+        let second = submit();
+        assert!(first.is_some());
+        assert!(second.is_none());
+        release.notify_one();
+        first.unwrap().await.unwrap();
+        assert_eq!(requests.load(atomic::Ordering::Relaxed), 1);
+        assert!(is_ready.load(atomic::Ordering::Relaxed));
+        assert!(submit().is_some());
+    });
+}
+
+#[test]
+fn test_preview() {
+    assert_eq!(preview("a\nb\nc", 3), None);
+    assert_eq!(preview("a\nb\nc\n", 3), None);
+    assert_eq!(preview("a\nb\nc\nd", 2), Some(("a\nb", 2)));
+    assert_eq!(preview("a\n\nc", 1), Some(("a", 2)));
 }
@@ -0,0 +1,156 @@
+use eframe::egui;
+
+/// An action of the palette, run by [`super::ChatApp`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    NewChat,
+    NewComplete,
+    Load,
+    Save,
+    ToggleList,
+    /// index into the widgets, e.g. the log
+    ToggleWidget(usize),
+    SwitchTo(String),
+    /// conversation, model
+    SetModel(String, String),
+}
+
+#[derive(Default)]
+pub struct CommandPalette {
+    open: bool,
+    query: String,
+    /// index into the matching commands
+    selected: usize,
+}
+
+impl CommandPalette {
+    pub const SHORTCUT: egui::KeyboardShortcut =
+        egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::P);
+    const MAX_SHOWN: usize = 12;
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    /// The palette above the dock, returns the command that was picked.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        commands: Vec<(String, Command)>,
+    ) -> Option<Command> {
+        if !self.open {
+            return None;
+        }
+        let mut matches: Vec<_> = commands
+            .into_iter()
+            .filter_map(|(label, command)| {
+                fuzzy_score(&self.query, &label).map(|score| (score, label, command))
+            })
+            .collect();
+        // stable, so equal scores keep the given order
+        matches.sort_by_key(|(score, ..)| std::cmp::Reverse(*score));
+        matches.truncate(Self::MAX_SHOWN);
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if down {
+            self.selected += 1;
+        }
+        self.selected = self.selected.min(matches.len().saturating_sub(1));
+
+        let mut picked = enter
+            .then(|| {
+                matches
+                    .get(self.selected)
+                    .map(|(_, _, command)| command.clone())
+            })
+            .flatten();
+        egui::Window::new("Command palette")
+            .title_bar(false)
+            .anchor(egui::Align2::CENTER_TOP, [0., 40.])
+            .resizable(false)
+            .fixed_size([400., 0.])
+            .show(ctx, |ui| {
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.query)
+                        .hint_text("Type a command or a conversation")
+                        .desired_width(f32::INFINITY),
+                );
+                response.request_focus();
+                if response.changed() {
+                    self.selected = 0;
+                }
+                ui.separator();
+                if matches.is_empty() {
+                    ui.weak("No matching command");
+                }
+                for (index, (_, label, command)) in matches.iter().enumerate() {
+                    let label = ui.selectable_label(index == self.selected, label);
+                    if index == self.selected && (up || down) {
+                        label.scroll_to_me(None);
+                    }
+                    if label.clicked() {
+                        picked = Some(command.clone());
+                    }
+                }
+            });
+        if picked.is_some() || escape {
+            self.open = false;
+        }
+        picked
+    }
+}
+
+/// How well `query` matches `label`, `None` unless all the characters of `query`
+/// appear in order. Consecutive characters and word starts score higher.
+pub fn fuzzy_score(query: &str, label: &str) -> Option<i32> {
+    let mut score = 0;
+    let mut label_chars = label.chars().enumerate();
+    let mut last_match: Option<usize> = None;
+    let mut before: Option<char> = None;
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let q = q.to_lowercase().next().unwrap_or(q);
+        let index = loop {
+            let (index, c) = label_chars.next()?;
+            let found = c.to_lowercase().next() == Some(q);
+            let previous = before.replace(c);
+            if found {
+                score += 1;
+                if last_match.is_some_and(|last| last + 1 == index) {
+                    score += 2;
+                } else if !previous.is_some_and(|p| p.is_alphanumeric()) {
+                    score += 3;
+                }
+                break index;
+            }
+        };
+        last_match = Some(index);
+    }
+    // shorter labels are closer to what was typed
+    Some(score * 100 - label.chars().count() as i32)
+}
+
+#[test]
+fn test_fuzzy_score() {
+    assert!(fuzzy_score("nc", "New Chat").is_some());
+    assert!(fuzzy_score("cn", "New Chat").is_none());
+    assert!(fuzzy_score("", "Save").is_some());
+    assert!(fuzzy_score("SAVE", "Save").is_some());
+    // word starts beat letters in the middle of a word
+    assert!(fuzzy_score("nc", "New Chat") > fuzzy_score("nc", "Switch to unclear"));
+    assert!(fuzzy_score("save", "Save") > fuzzy_score("save", "Switch to save_notes"));
+}
@@ -0,0 +1,127 @@
+use eframe::egui;
+
+use super::{
+    chat_window::message,
+    settings::{Density, SharedSettings},
+    View, Window,
+};
+use crate::api::chat::{ChatAPI, Role};
+
+/// Two chats side by side, scrolled together. The conversations are looked up
+/// by name in the ones given every frame, so they stay live while streaming.
+pub struct CompareWindow {
+    names: [Option<String>; 2],
+    /// the open chats, shared with their tabs
+    chats: Vec<(String, ChatAPI)>,
+    /// how far both columns are scrolled, from 0 at the top to 1 at the bottom
+    scroll: f32,
+    /// the furthest each column can scroll, as of the last frame
+    max_offsets: [f32; 2],
+    settings: SharedSettings,
+}
+
+impl CompareWindow {
+    pub fn new(settings: SharedSettings) -> Self {
+        Self {
+            names: [None, None],
+            chats: Vec::new(),
+            scroll: 0.,
+            max_offsets: [0.; 2],
+            settings,
+        }
+    }
+    pub fn compare(&mut self, left: String, right: String) {
+        self.names = [Some(left), Some(right)];
+        self.scroll = 0.;
+    }
+    pub fn set_chats(&mut self, chats: Vec<(String, ChatAPI)>) {
+        self.chats = chats;
+    }
+    pub fn rename(&mut self, from: &str, to: &str) {
+        for name in self.names.iter_mut().flatten() {
+            if name == from {
+                *name = to.to_string();
+            }
+        }
+    }
+    /// The transcript of `chat` with the reply being streamed, `true` while streaming.
+    fn transcript(ui: &mut egui::Ui, chat: &ChatAPI, density: Density) -> bool {
+        let data = chat.data();
+        for msg in &data.messages {
+            message(ui, |ui| ui.label(&msg.content), &msg.role, density);
+        }
+        match chat.get_generate() {
+            Some(Ok(generate)) => {
+                message(ui, |ui| ui.label(generate), &Role::Assistant, density);
+                true
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+impl Window for CompareWindow {
+    fn name(&self) -> &str {
+        "Compare"
+    }
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool) {
+        egui::Window::new(self.name())
+            .open(open)
+            .default_size([800., 500.])
+            .show(ctx, |ui| self.ui(ui));
+    }
+}
+
+impl View for CompareWindow {
+    type Response = ();
+    fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response {
+        let density = self.settings.borrow().density;
+        let mut fractions = [None; 2];
+        let mut streaming = false;
+        ui.columns(2, |columns| {
+            for (column, ui) in columns.iter_mut().enumerate() {
+                egui::ComboBox::from_id_source(("compare", column))
+                    .selected_text(self.names[column].as_deref().unwrap_or("Pick a chat"))
+                    .width(ui.available_width())
+                    .show_ui(ui, |ui| {
+                        for (name, _) in &self.chats {
+                            ui.selectable_value(&mut self.names[column], Some(name.clone()), name);
+                        }
+                    });
+                let chat = self.names[column]
+                    .as_ref()
+                    .and_then(|name| self.chats.iter().find(|(n, _)| n == name));
+                let Some((_, chat)) = chat else {
+                    ui.weak("No such chat");
+                    continue;
+                };
+                let output = egui::ScrollArea::vertical()
+                    .id_source(("compare_scroll", column))
+                    .vertical_scroll_offset(self.scroll * self.max_offsets[column])
+                    .show(ui, |ui| Self::transcript(ui, chat, density));
+                streaming |= output.inner;
+                let max_offset = (output.content_size.y - output.inner_rect.height()).max(0.);
+                self.max_offsets[column] = max_offset;
+                if max_offset > 0. {
+                    fractions[column] = Some(output.state.offset.y / max_offset);
+                }
+            }
+        });
+        // the column scrolled by hand leads, the other follows the next frame
+        if let Some(fraction) = fractions
+            .into_iter()
+            .flatten()
+            .find(|fraction| (fraction - self.scroll).abs() > 1e-3)
+        {
+            self.scroll = fraction.clamp(0., 1.);
+            ui.ctx().request_repaint();
+        }
+        if streaming {
+            ui.ctx().request_repaint();
+        }
+    }
+}
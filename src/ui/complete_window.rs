@@ -1,9 +1,15 @@
 use eframe::egui;
 use tokio::task::JoinHandle;
 
-use crate::api::{complete::CompleteAPI, ParameterControl};
+use crate::api::{
+    complete::{CompleteAPI, TokenLogprob},
+    ParameterControl,
+};
 
-use super::{easy_mark, parameter_control::ParameterControler, TabWindow, View, Window};
+use super::{
+    components, easy_mark, parameter_control::ParameterControler, TabRequest, TabWindow, View,
+    Window,
+};
 pub struct CompleteWindow {
     window_name: String,
     complete: CompleteAPI,
@@ -13,12 +19,19 @@ pub struct CompleteWindow {
     parameter_control: ParameterControler,
     show_parameter_control: bool,
     enable_markdown: bool,
+    /// color the generated tokens by their probability
+    show_logprobs: bool,
+    /// last known cursor in the prompt, kept when the editor loses focus
     cursor_index: Option<usize>,
+    /// bytes of the text before and after the inserted part, to highlight it
+    inserted: Option<(usize, usize)>,
+    request: Option<TabRequest>,
 }
 
 impl CompleteWindow {
-    pub fn new(window_name: String, complete: CompleteAPI) -> Self {
-        let parameter_control = ParameterControler::new(complete.params());
+    pub fn new(window_name: String, complete: CompleteAPI, enable_markdown: bool) -> Self {
+        let mut parameter_control = ParameterControler::new(complete.params());
+        parameter_control.set_model(complete.data().model());
         Self {
             window_name,
             text: tokio::task::block_in_place(|| complete.data.blocking_read().prompt.clone()),
@@ -27,15 +40,29 @@ impl CompleteWindow {
             show_parameter_control: false,
             promise: None,
             highlighter: Default::default(),
-            enable_markdown: true,
+            enable_markdown,
+            show_logprobs: false,
             cursor_index: None,
+            inserted: None,
+            request: None,
         }
     }
 
+    /// behind the text inserted between the prompt and the suffix
+    const INSERTED_BACKGROUND: egui::Color32 =
+        egui::Color32::from_rgba_premultiplied(30, 90, 30, 90);
+
     fn on_insert(&mut self, cursor_index: usize) {
+        let split = self
+            .text
+            .char_indices()
+            .nth(cursor_index)
+            .map_or(self.text.len(), |(i, _)| i);
+        self.inserted = Some((split, self.text.len() - split));
         let complete = self.complete.clone();
+        let token = complete.start();
         self.promise = Some(tokio::spawn(async move {
-            match complete.insert(cursor_index).await {
+            match complete.insert(cursor_index, token).await {
                 Ok(res) => Ok(res),
                 Err(e) => {
                     tracing::error!("{}", e);
@@ -46,9 +73,11 @@ impl CompleteWindow {
     }
 
     fn on_complete(&mut self) {
+        self.inserted = None;
         let complete = self.complete.clone();
+        let token = complete.start();
         self.promise = Some(tokio::spawn(async move {
-            match complete.generate().await {
+            match complete.generate(token).await {
                 Ok(res) => Ok(res),
                 Err(e) => {
                     tracing::error!("{}", e);
@@ -63,10 +92,17 @@ impl super::Window for CompleteWindow {
         &self.window_name
     }
 
+    /// A floating window, for a conversation detached from the dock.
     fn show(&mut self, ctx: &egui::Context, open: &mut bool) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            self.ui(ui);
-        });
+        egui::Window::new(self.window_name.clone())
+            .id(egui::Id::new("detached").with(&self.window_name))
+            .open(open)
+            .default_size([640., 480.])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| TabWindow::actions(self, ui));
+                ui.separator();
+                self.ui(ui);
+            });
     }
 }
 impl TabWindow for CompleteWindow {
@@ -74,6 +110,24 @@ impl TabWindow for CompleteWindow {
         self.window_name = name;
     }
 
+    fn markdown(&self) -> bool {
+        self.enable_markdown
+    }
+
+    fn set_markdown(&mut self, enabled: bool) {
+        self.enable_markdown = enabled;
+    }
+
+    fn is_generating(&self) -> bool {
+        self.promise
+            .as_ref()
+            .is_some_and(|promise| !promise.is_finished())
+    }
+
+    fn abort(&self) {
+        self.complete.abort();
+    }
+
     fn actions(&mut self, ui: &mut egui::Ui) {
         ui.selectable_label(self.show_parameter_control, "Tuning")
             .clicked()
@@ -81,6 +135,10 @@ impl TabWindow for CompleteWindow {
                 self.show_parameter_control = !self.show_parameter_control;
             });
     }
+
+    fn take_request(&mut self) -> Option<TabRequest> {
+        self.request.take()
+    }
 }
 
 impl View for CompleteWindow {
@@ -95,6 +153,7 @@ impl View for CompleteWindow {
         }
         if let Some(generate) = generate {
             self.text = generate;
+            self.cursor_index = None;
             if let Some(suffix) =
                 tokio::task::block_in_place(|| self.complete.data.blocking_read().suffix.clone())
             {
@@ -110,6 +169,10 @@ impl View for CompleteWindow {
             });
             if let Ok(Ok(text)) = text {
                 self.text = text.clone();
+            } else {
+                self.inserted = None;
+                // the streamed part is dropped, back to the prompt as it was
+                self.text = self.complete.data().prompt;
             }
         }
         egui::TopBottomPanel::top(format!("top_{}", self.name())).show_inside(ui, |ui| {
@@ -117,6 +180,8 @@ impl View for CompleteWindow {
                 ui.heading(&self.window_name);
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     ui.checkbox(&mut self.enable_markdown, "Markdown");
+                    ui.checkbox(&mut self.show_logprobs, "Logprobs")
+                        .on_hover_text("Show how likely each generated token was");
                 });
             });
         });
@@ -129,33 +194,53 @@ impl View for CompleteWindow {
                         .then(|| {
                             self.on_complete();
                         });
-                    if let Some(cursor_index) = self.cursor_index {
-                        ui.add_sized([50., 40.], egui::Button::new("Insert"))
-                            .clicked()
-                            .then(|| {
-                                self.on_insert(cursor_index);
-                            });
+                    let insert = ui
+                        .add_enabled_ui(self.cursor_index.is_some(), |ui| {
+                            ui.add_sized([50., 40.], egui::Button::new("Insert"))
+                        })
+                        .inner
+                        .on_hover_text("Generate text at the cursor")
+                        .on_disabled_hover_text("Place the cursor in the prompt to insert there");
+                    if insert.clicked() {
+                        if let Some(cursor_index) = self.cursor_index {
+                            self.on_insert(cursor_index.min(self.text.chars().count()));
+                        }
                     }
                 });
+                if let Some(reason) = is_ready.then(|| self.complete.finish_reason()).flatten() {
+                    // an insert is followed by its suffix, which the prompt now ends with
+                    if self.complete.is_truncated()
+                        && self.inserted.is_none()
+                        && ui
+                            .add_sized([50., 40.], egui::Button::new("Continue"))
+                            .on_hover_text("The completion was cut off by max_tokens")
+                            .clicked()
+                    {
+                        self.on_complete();
+                    }
+                    ui.weak(format!("stopped: {}", reason));
+                }
                 if !is_ready {
                     ui.add_sized([50., 40.], egui::Button::new("Abort"))
                         .clicked()
                         .then(|| {
-                            if let Some(promise) = self.promise.take() {
-                                promise.abort();
-                                let mut complete = self.complete.clone();
-                                tokio::spawn(async move {
-                                    let pending_generate =
-                                        complete.pending_generate.write().await.take();
-                                    if let Some(text) = pending_generate {
-                                        complete.set_prompt(text).await;
-                                    }
-                                });
-                            }
+                            // the promise resolves with the partial text
+                            self.complete.abort();
                         });
+                    if self.complete.is_queued() {
+                        ui.weak("Queued");
+                    }
                 }
             });
         });
+        let tokens = if self.show_logprobs {
+            self.complete.tokens()
+        } else {
+            Vec::new()
+        };
+        egui::TopBottomPanel::bottom(format!("logprobs_{}", self.name()))
+            .resizable(true)
+            .show_animated_inside(ui, self.show_logprobs, |ui| logprobs_ui(ui, &tokens));
         egui::SidePanel::right(format!("right_{}", self.name())).show_animated_inside(
             ui,
             self.show_parameter_control,
@@ -170,10 +255,18 @@ impl View for CompleteWindow {
                 .show(ui, |ui| {
                     ui.add_enabled_ui(is_ready, |ui| {
                         ui.visuals_mut().widgets.hovered = ui.visuals().widgets.inactive;
+                        let inserted = self.inserted;
 
                         let response = if self.enable_markdown {
                             let mut layouter = |ui: &egui::Ui, easymark: &str, wrap_width: f32| {
                                 let mut layout_job = self.highlighter.highlight(ui, easymark);
+                                if let Some(range) = inserted_range(inserted, easymark) {
+                                    layout_job = components::highlight_range(
+                                        layout_job,
+                                        range,
+                                        Self::INSERTED_BACKGROUND,
+                                    );
+                                }
                                 layout_job.wrap.max_width = wrap_width;
                                 ui.fonts(|f| f.layout_job(layout_job))
                             };
@@ -185,28 +278,106 @@ impl View for CompleteWindow {
                                     .layouter(&mut layouter),
                             )
                         } else {
+                            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                                let mut layout_job = components::plain_job(ui, text, wrap_width);
+                                if let Some(range) = inserted_range(inserted, text) {
+                                    layout_job = components::highlight_range(
+                                        layout_job,
+                                        range,
+                                        Self::INSERTED_BACKGROUND,
+                                    );
+                                }
+                                ui.fonts(|f| f.layout_job(layout_job))
+                            };
+
                             ui.add_sized(
                                 ui.available_size(),
                                 egui::TextEdit::multiline(&mut self.text)
-                                    .desired_width(f32::INFINITY),
+                                    .desired_width(f32::INFINITY)
+                                    .layouter(&mut layouter),
                             )
                         };
 
                         response.changed().then(|| {
+                            // no longer where the insert put it
+                            self.inserted = None;
                             let mut complete = self.complete.clone();
                             let text = self.text.clone();
                             tokio::spawn(async move {
                                 complete.set_prompt(text).await;
                             });
                         });
-                        self.cursor_index = None;
+                        let mut selection = None;
                         if let Some(state) = egui::TextEdit::load_state(ui.ctx(), response.id) {
                             if let Some(ccursor_range) = state.ccursor_range() {
                                 self.cursor_index = Some(ccursor_range.primary.index);
+                                let [start, end] = ccursor_range.sorted();
+                                selection = Some(start.index..end.index);
                             }
                         }
+                        response.context_menu(|ui| {
+                            if ui
+                                .button("Send to new chat")
+                                .on_hover_text("Start a chat with the selected text, or all of it")
+                                .clicked()
+                            {
+                                let selected = selection
+                                    .filter(|range| !range.is_empty())
+                                    .map(|range| {
+                                        self.text
+                                            .chars()
+                                            .skip(range.start)
+                                            .take(range.len())
+                                            .collect::<String>()
+                                    })
+                                    .unwrap_or_else(|| self.text.clone());
+                                if !selected.trim().is_empty() {
+                                    self.request = Some(TabRequest::NewChat(selected));
+                                }
+                                ui.close_menu();
+                            }
+                        });
                     });
                 });
         });
     }
 }
+
+/// The inserted part of `text`, given the bytes before and after it.
+fn inserted_range(inserted: Option<(usize, usize)>, text: &str) -> Option<std::ops::Range<usize>> {
+    let (before, after) = inserted?;
+    let end = text.len().checked_sub(after)?;
+    (before <= end && text.is_char_boundary(before) && text.is_char_boundary(end))
+        .then_some(before..end)
+}
+
+/// The generated tokens, from red for unlikely to green for likely ones.
+fn logprobs_ui(ui: &mut egui::Ui, tokens: &[TokenLogprob]) {
+    if tokens.is_empty() {
+        ui.weak("Turn on logprobs in Tuning, then complete to see token probabilities.");
+        return;
+    }
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing.x = 0.;
+            for token in tokens {
+                let probability = token.logprob.map_or(0., f32::exp);
+                let color = egui::Color32::from_rgb(
+                    (255. * (1. - probability)) as u8,
+                    (200. * probability) as u8,
+                    60,
+                )
+                .gamma_multiply(0.5);
+                let text = egui::RichText::new(token.token.replace('\n', "⏎"))
+                    .monospace()
+                    .background_color(color);
+                ui.label(text).on_hover_ui(|ui| {
+                    ui.label(format!("{:.1}%", probability * 100.));
+                    for (alternative, logprob) in &token.top {
+                        ui.monospace(format!("{:?} {:.1}%", alternative, logprob.exp() * 100.));
+                    }
+                });
+            }
+        });
+    });
+}
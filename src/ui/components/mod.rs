@@ -1 +1,188 @@
+use eframe::egui;
 
+/// Dim everything behind a modal window and swallow clicks on it.
+pub fn backdrop(ctx: &egui::Context, id: impl Into<egui::Id>) {
+    egui::Area::new(id).fixed_pos([0., 0.]).show(ctx, |ui| {
+        let rect = ui.input(|i| i.screen_rect());
+        ui.allocate_rect(rect, egui::Sense::click());
+        ui.painter()
+            .rect_filled(rect, 0., egui::Color32::from_black_alpha(160));
+    });
+}
+
+/// A modal asking to confirm `text`, `Some(true)` once confirmed and `Some(false)`
+/// once cancelled. Enter confirms and Escape cancels.
+pub fn confirm(
+    ctx: &egui::Context,
+    title: &str,
+    text: &str,
+    dont_ask_again: &mut bool,
+) -> Option<bool> {
+    backdrop(ctx, egui::Id::new(title).with("backdrop"));
+    // keep a focused text edit from receiving the keys meant for the dialog
+    ctx.memory_mut(|m| m.stop_text_input());
+    let mut answer = ctx.input_mut(|i| {
+        if i.consume_key(egui::Modifiers::NONE, egui::Key::Enter) {
+            Some(true)
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::Escape) {
+            Some(false)
+        } else {
+            None
+        }
+    });
+    let response = egui::Window::new(title)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(text);
+            ui.checkbox(dont_ask_again, "Don't ask again");
+            ui.horizontal(|ui| {
+                if ui.button("Yes").clicked() {
+                    answer = Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    answer = Some(false);
+                }
+            });
+        });
+    if let Some(response) = response {
+        ctx.move_to_top(response.response.layer_id);
+    }
+    answer
+}
+
+/// Plain `text` in the style a `TextEdit` would use, wrapped at `wrap_width`.
+pub fn plain_job(ui: &egui::Ui, text: &str, wrap_width: f32) -> egui::text::LayoutJob {
+    let font_id = egui::TextStyle::Body.resolve(ui.style());
+    let color = ui
+        .visuals()
+        .override_text_color
+        .unwrap_or_else(|| ui.visuals().widgets.inactive.text_color());
+    egui::text::LayoutJob::simple(text.to_owned(), font_id, color, wrap_width)
+}
+
+/// `job` with `background` behind the text of `range`, in bytes on char boundaries.
+pub fn highlight_range(
+    job: egui::text::LayoutJob,
+    range: std::ops::Range<usize>,
+    background: egui::Color32,
+) -> egui::text::LayoutJob {
+    format_range(job, range, |format| format.background = background)
+}
+
+/// `job` with the format of the text in `range` changed by `change`.
+pub fn format_range(
+    mut job: egui::text::LayoutJob,
+    range: std::ops::Range<usize>,
+    change: impl Fn(&mut egui::TextFormat),
+) -> egui::text::LayoutJob {
+    let mut sections = Vec::with_capacity(job.sections.len() + 2);
+    for section in job.sections {
+        let bytes = section.byte_range.clone();
+        // the parts of the section before, inside and after `range`
+        let cuts = [
+            bytes.start,
+            range.start.clamp(bytes.start, bytes.end),
+            range.end.clamp(bytes.start, bytes.end),
+            bytes.end,
+        ];
+        for (i, part) in cuts.windows(2).enumerate() {
+            if part[0] >= part[1] {
+                continue;
+            }
+            let mut format = section.format.clone();
+            if i == 1 {
+                change(&mut format);
+            }
+            sections.push(egui::text::LayoutSection {
+                leading_space: if part[0] == bytes.start {
+                    section.leading_space
+                } else {
+                    0.
+                },
+                byte_range: part[0]..part[1],
+                format,
+            });
+        }
+    }
+    job.sections = sections;
+    job
+}
+
+#[test]
+fn test_wrap_mixed_scripts() {
+    let conversation = [
+        "What does 一期一会 mean?",
+        "「一期一会」は一生に一度だけの機会という意味です。It means treasure every meeting, because it will never happen again.",
+        "用中文再解释一下，好吗？",
+    ];
+    let long_cjk = conversation[2].repeat(4);
+    let ctx = egui::Context::default();
+    let mut galleys = Vec::new();
+    let _ = ctx.run(Default::default(), |ctx| {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            for text in conversation.into_iter().chain([long_cjk.as_str()]) {
+                // the jobs of the plain and of the markdown layouters
+                let mut markdown =
+                    super::easy_mark::MemoizedEasymarkHighlighter::default().highlight(ui, text);
+                markdown.wrap.max_width = 80.;
+                for job in [plain_job(ui, text, 80.), markdown] {
+                    galleys.push((text.to_owned(), ui.fonts(|f| f.layout_job(job))));
+                }
+            }
+        });
+    });
+    assert_eq!(galleys.len(), 8);
+    for (text, galley) in &galleys {
+        for rows in galley.rows.windows(2) {
+            let (Some(end), Some(start)) = (rows[0].glyphs.last(), rows[1].glyphs.first()) else {
+                continue;
+            };
+            // a Latin word is never split across rows
+            assert!(
+                !(end.chr.is_ascii_alphanumeric() && start.chr.is_ascii_alphanumeric()),
+                "{text:?} broken between {:?} and {:?}",
+                end.chr,
+                start.chr
+            );
+            // closing punctuation never starts a row
+            assert!(
+                !"、。，」？".contains(start.chr),
+                "{text:?} row starts with {:?}",
+                start.chr
+            );
+        }
+    }
+    // long CJK runs without spaces still wrap
+    for (_, galley) in &galleys[6..] {
+        assert!(galley.rows.len() > 1);
+    }
+}
+
+#[test]
+fn test_highlight_range() {
+    let mut job = egui::text::LayoutJob::default();
+    job.append("before ", 0., Default::default());
+    job.append("inserted after", 0., Default::default());
+    let job = highlight_range(job, 4..15, egui::Color32::RED);
+    let highlighted: Vec<_> = job
+        .sections
+        .iter()
+        .map(|section| {
+            (
+                &job.text[section.byte_range.clone()],
+                section.format.background == egui::Color32::RED,
+            )
+        })
+        .collect();
+    assert_eq!(
+        highlighted,
+        [
+            ("befo", false),
+            ("re ", true),
+            ("inserted", true),
+            (" after", false)
+        ]
+    );
+}
@@ -6,53 +6,134 @@ use eframe::egui;
 
 /// Highlight easymark, memoizing previous output to save CPU.
 ///
-/// In practice, the highlighter is fast enough not to need any caching.
+/// The output is kept as blocks, split where the highlighter starts over, so a
+/// change only highlights again from the first block that changed.
 #[derive(Default)]
 pub struct MemoizedEasymarkHighlighter {
     style: egui::Style,
-    code: String,
     output: egui::text::LayoutJob,
+    blocks: Vec<Block>,
+    /// the text after the last block, which may still change how it is highlighted
+    tail: Block,
+}
+
+/// A run of the highlighted text, by the hash of its content.
+#[derive(Default, PartialEq)]
+struct Block {
+    hash: u64,
+    len: usize,
+}
+
+impl Block {
+    fn new(text: &str) -> Self {
+        Self {
+            hash: egui::util::hash(text),
+            len: text.len(),
+        }
+    }
 }
 
 impl MemoizedEasymarkHighlighter {
     pub fn highlight(&mut self, ui: &egui::Ui, code: &str) -> egui::text::LayoutJob {
-        let egui_style: &egui::Style = ui.style();
-        if (&self.style, self.code.as_str()) != (egui_style, code) {
+        self.update(ui.ctx(), ui.style(), code);
+        self.output.to_owned()
+    }
+
+    fn update(&mut self, ctx: &egui::Context, egui_style: &egui::Style, code: &str) {
+        if &self.style != egui_style {
             self.style = egui_style.clone();
-            self.code = code.to_string();
-            self.output = highlight_easymark(ui.ctx(), egui_style, code);
+            self.blocks.clear();
+            self.tail = Block::new("");
+            self.output = Default::default();
         }
-        self.output.to_owned()
+        let mut start = 0;
+        let unchanged = self
+            .blocks
+            .iter()
+            .take_while(|block| {
+                let same = code
+                    .get(start..start + block.len)
+                    .is_some_and(|text| Block::new(text) == **block);
+                if same {
+                    start += block.len;
+                }
+                same
+            })
+            .count();
+        if unchanged == self.blocks.len() && Block::new(&code[start..]) == self.tail {
+            return;
+        }
+        self.blocks.truncate(unchanged);
+        // every section ends before or starts after the start of a block
+        self.output.text.truncate(start);
+        self.output
+            .sections
+            .retain(|section| section.byte_range.end <= start);
+
+        let (mut job, checkpoints) = highlight_easymark(ctx, egui_style, &code[start..]);
+        let mut from = 0;
+        for checkpoint in checkpoints.into_iter().filter(|&c| c > 0) {
+            self.blocks.push(Block::new(&job.text[from..checkpoint]));
+            from = checkpoint;
+        }
+        self.tail = Block::new(&job.text[from..]);
+        for section in &mut job.sections {
+            section.byte_range =
+                (section.byte_range.start + start)..(section.byte_range.end + start);
+        }
+        self.output.text.push_str(&job.text);
+        self.output.sections.append(&mut job.sections);
     }
 }
 
+/// The highlighted `text`, and the byte offsets where the highlighter is back to
+/// its initial state, so highlighting the rest alone gives the same output.
 pub fn highlight_easymark(
     ctx: &egui::Context,
     egui_style: &egui::Style,
     mut text: &str,
-) -> egui::text::LayoutJob {
+) -> (egui::text::LayoutJob, Vec<usize>) {
     let mut job = egui::text::LayoutJob::default();
     let mut style = easy_mark_parser::Style::default();
     let mut start_of_line = true;
+    let mut checkpoints = Vec::new();
     let theme = syntax_highlighting::CodeTheme::from_style(&egui_style);
 
     while !text.is_empty() {
+        if start_of_line && style == easy_mark_parser::Style::default() {
+            checkpoints.push(job.text.len());
+        }
         if start_of_line && text.starts_with("```") {
-            let language = text[3..]
-                .find('\n')
-                .map_or_else(|| "text", |i| &text[3..3 + i]);
-            let start = text.find('\n').map_or_else(|| 3, |i| i + 1);
+            let Some(newline) = text.find('\n') else {
+                // the language is still streaming in, the fence opens on the next line
+                job.append(text, 0.0, format_from_style(egui_style, &style));
+                break;
+            };
+            let language = &text[3..newline];
+            let start = newline + 1;
             job.append(&text[..start], 0.0, format_from_style(egui_style, &style));
             text = &text[start..];
             let mut section_end = false;
             let end = text.find("\n```").map_or_else(
-                || text.len(),
+                // an unterminated fence lasts until the end, without the start
+                // of a closing fence that is still streaming in
+                || {
+                    ["\n``", "\n`"]
+                        .iter()
+                        .find_map(|partial| text.strip_suffix(partial))
+                        .map_or(text.len(), str::len)
+                },
                 |i| {
                     section_end = true;
                     i
                 },
             );
 
+            let language = if language.trim().is_empty() {
+                syntax_highlighting::guess_language(&text[..end])
+            } else {
+                language
+            };
             let mut code_job = syntax_highlighting::highlight(ctx, &theme, &text[..end], language);
             let offset = job.text.len();
             code_job.sections.iter_mut().for_each(|s| {
@@ -71,7 +152,8 @@ pub fn highlight_easymark(
                 );
                 text = &text[end + 4..];
             } else {
-                text = &text[end..];
+                job.append(&text[end..], 0.0, format_from_style(egui_style, &style));
+                break;
             }
             style = Default::default();
             continue;
@@ -217,7 +299,7 @@ pub fn highlight_easymark(
         }
     }
 
-    job
+    (job, checkpoints)
 }
 
 fn format_from_style(
@@ -290,3 +372,36 @@ fn format_from_style(
         valign,
     }
 }
+
+#[test]
+fn test_incremental_highlight() {
+    let ctx = egui::Context::default();
+    let style = egui::Style::default();
+    let text =
+        "Title\nSome **bold** text\n```rust\nfn main() {}\n```\nand `code`\n```\nopen fence\n``";
+    let mut highlighter = MemoizedEasymarkHighlighter::default();
+    for (end, _) in text.char_indices().skip(1) {
+        highlighter.update(&ctx, &style, &text[..end]);
+        assert_eq!(
+            highlighter.output,
+            highlight_easymark(&ctx, &style, &text[..end]).0,
+            "streamed up to {:?}",
+            &text[..end]
+        );
+    }
+    // an edit in the middle only changes what follows
+    let edited = text.replace("bold", "strong");
+    highlighter.update(&ctx, &style, &edited);
+    assert_eq!(
+        highlighter.output,
+        highlight_easymark(&ctx, &style, &edited).0
+    );
+    assert!(highlighter.blocks.len() > 2);
+
+    // a partial language or closing fence is not highlighted as code
+    let (job, _) = highlight_easymark(&ctx, &style, "```ru");
+    assert_eq!(job.sections.len(), 1);
+    let (job, _) = highlight_easymark(&ctx, &style, "```rust\nlet a = 1;\n``");
+    let last = job.sections.last().unwrap();
+    assert_eq!(&job.text[last.byte_range.clone()], "\n``");
+}
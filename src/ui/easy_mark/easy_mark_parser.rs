@@ -330,6 +330,330 @@ impl<'a> Iterator for Parser<'a> {
     }
 }
 
+/// The visible text of `s`, without the markup.
+pub fn plain_text(s: &str) -> String {
+    let mut text = String::with_capacity(s.len());
+    for item in Parser::new(s) {
+        match item {
+            Item::Newline | Item::Separator => text.push('\n'),
+            Item::Text(_, s) | Item::Hyperlink(_, s, _) | Item::CodeBlock(_, s) => text.push_str(s),
+            Item::Indentation(indent) => text.push_str(&" ".repeat(indent)),
+            Item::QuoteIndent => {}
+            Item::BulletPoint => text.push_str("• "),
+            Item::NumberedPoint(number) => {
+                text.push_str(number);
+                text.push_str(". ");
+            }
+        }
+    }
+    text
+}
+
+/// The `[text](url)` links and bare `http(s)://` URLs of `text` by byte range, with
+/// their URL. Code stays literal and malformed links are left out.
+pub fn links(text: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut links = Vec::new();
+    let mut in_code = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        if line.starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code || line.starts_with("```") {
+            continue;
+        }
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            let link = if let Some(code) = rest.strip_prefix('`') {
+                // inline code, until the closing backtick
+                i += code.find('`').map_or(rest.len(), |end| end + 2);
+                continue;
+            } else if rest.starts_with('[') {
+                markdown_link(rest)
+            } else if (rest.starts_with("https://") || rest.starts_with("http://"))
+                && !line[..i].ends_with(|c: char| c.is_alphanumeric())
+            {
+                let url = bare_url(rest);
+                Some((url.len(), url))
+            } else {
+                None
+            };
+            if let Some((len, url)) = link {
+                links.push((line_start + i..line_start + i + len, url));
+                i += len;
+            } else {
+                i += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+    links
+}
+
+/// Length and URL of the `[text](url)` link `s` starts with.
+fn markdown_link(s: &str) -> Option<(usize, &str)> {
+    let close = s.find("](")?;
+    if s[1..close].contains(['[', ']']) {
+        return None;
+    }
+    let url_start = close + 2;
+    let url = &s[url_start..url_start + s[url_start..].find(')')?];
+    let is_url = ["https://", "http://", "mailto:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+        && !url.contains(char::is_whitespace);
+    is_url.then_some((url_start + url.len() + 1, url))
+}
+
+/// The URL `s` starts with, without the punctuation of the sentence around it.
+fn bare_url(s: &str) -> &str {
+    let end = s
+        .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+        .unwrap_or(s.len());
+    let mut url = &s[..end];
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '*']);
+        // a closing parenthesis is part of the URL only when it opened one
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(without) if trimmed.matches('(').count() < trimmed.matches(')').count() => without,
+            _ => trimmed,
+        };
+        if trimmed == url {
+            return url;
+        }
+        url = trimmed;
+    }
+}
+
+/// `text` split around its fenced code blocks, `true` for a code block with its fences.
+/// The newlines next to a fence are left out, an unterminated block runs to the end.
+pub fn split_code_blocks<'a>(text: &'a str) -> Vec<(&'a str, bool)> {
+    let mut parts = Vec::new();
+    let mut push = |part: &'a str, code| {
+        let part = part.strip_suffix('\n').unwrap_or(part);
+        if !part.is_empty() {
+            parts.push((part, code));
+        }
+    };
+    // of the current part
+    let mut start = 0;
+    let mut in_code = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        if !line.starts_with("```") {
+            continue;
+        }
+        if in_code {
+            push(&text[start..offset], true);
+            start = offset;
+        } else {
+            push(&text[start..line_start], false);
+            start = line_start;
+        }
+        in_code = !in_code;
+    }
+    push(&text[start..], in_code);
+    parts
+}
+
+/// `s` as HTML, lines starting with `|` outside of code blocks make tables.
+pub fn html(s: &str) -> String {
+    let mut html = String::with_capacity(s.len() * 2);
+    let mut text = String::new();
+    let mut table = Vec::new();
+    let mut in_code = false;
+    for line in s.split_inclusive('\n') {
+        if line.starts_with("```") {
+            in_code = !in_code;
+        }
+        if !in_code && line.trim_start().starts_with('|') {
+            if table.is_empty() {
+                html.push_str(&markup_html(&text));
+                text.clear();
+            }
+            table.push(line.trim());
+            continue;
+        }
+        if !table.is_empty() {
+            html.push_str(&table_html(&table));
+            table.clear();
+        }
+        if let Some((level, title)) = heading(line).filter(|_| !in_code) {
+            html.push_str(&markup_html(&text));
+            text.clear();
+            html.push_str(&format!("<h{level}>{}</h{level}>\n", markup_html(title)));
+            continue;
+        }
+        text.push_str(line);
+    }
+    html.push_str(&markup_html(&text));
+    html.push_str(&table_html(&table));
+    html
+}
+
+/// The level and title of a `#` to `######` heading.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let title = line[level..].strip_prefix(' ')?;
+    (1..=6)
+        .contains(&level)
+        .then_some((level, title.trim_end()))
+}
+
+/// The markdown of the replies as easy mark: `**strong**`, `*italics*`,
+/// `~~strikethrough~~`, `<u>underline</u>` and `* ` bullets. The other characters
+/// easy mark would take as markup are escaped, code and links are left as they are.
+fn markdown_to_easy_mark(s: &str) -> String {
+    let mut easy_mark = String::with_capacity(s.len());
+    let mut in_code = false;
+    for line in s.split_inclusive('\n') {
+        if line.starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code || line.starts_with("```") {
+            easy_mark.push_str(line);
+            continue;
+        }
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let mut i = 0;
+        if line[indent..].starts_with("* ") {
+            easy_mark.push_str(&line[..indent]);
+            easy_mark.push_str("- ");
+            i = indent + 2;
+        }
+        while i < line.len() {
+            let rest = &line[i..];
+            let (markup, len) = if let Some(code) = rest.strip_prefix('`') {
+                let len = code.find('`').map_or(rest.len(), |end| end + 2);
+                (&rest[..len], len)
+            } else if let Some((len, _)) = markdown_link(rest) {
+                (&rest[..len], len)
+            } else if rest.starts_with("**") {
+                ("*", 2)
+            } else if rest.starts_with("~~") {
+                ("~", 2)
+            } else if rest.starts_with("<u>") {
+                ("_", 3)
+            } else if rest.starts_with("</u>") {
+                ("_", 4)
+            } else if rest.starts_with('*') {
+                ("/", 1)
+            } else if rest.starts_with(['/', '_', '~', '$', '^']) {
+                easy_mark.push('\\');
+                (&rest[..1], 1)
+            } else {
+                let len = rest.chars().next().map_or(1, char::len_utf8);
+                (&rest[..len], len)
+            };
+            easy_mark.push_str(markup);
+            i += len;
+        }
+    }
+    easy_mark
+}
+
+fn markup_html(s: &str) -> String {
+    let mut html = String::with_capacity(s.len());
+    // a block ends its own line
+    let mut after_block = false;
+    let easy_mark = markdown_to_easy_mark(s);
+    for item in Parser::new(&easy_mark) {
+        let block = matches!(item, Item::CodeBlock(..) | Item::Separator);
+        match item {
+            Item::Newline if after_block => {}
+            Item::Newline => html.push_str("<br>\n"),
+            Item::Text(style, text) => html.push_str(&styled_html(&style, &escape_html(text))),
+            Item::Hyperlink(style, text, url) => html.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(url),
+                styled_html(&style, &escape_html(text))
+            )),
+            Item::Indentation(indent) => html.push_str(&"&nbsp;".repeat(indent)),
+            Item::QuoteIndent => {}
+            Item::BulletPoint => html.push_str("&bull; "),
+            Item::NumberedPoint(number) => {
+                html.push_str(number);
+                html.push_str(". ");
+            }
+            Item::Separator => html.push_str("<hr>\n"),
+            Item::CodeBlock(language, code) => html.push_str(&format!(
+                "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                escape_html(language.trim()),
+                escape_html(code)
+            )),
+        }
+        after_block = block;
+    }
+    html
+}
+
+fn styled_html(style: &Style, text: &str) -> String {
+    let mut html = text.to_string();
+    let mut wrap = |tag: &str, enabled: bool| {
+        if enabled {
+            html = format!("<{tag}>{html}</{tag}>");
+        }
+    };
+    wrap("code", style.code);
+    wrap("strong", style.strong);
+    wrap("em", style.italics);
+    wrap("u", style.underline);
+    wrap("s", style.strikethrough);
+    wrap("small", style.small);
+    wrap("sup", style.raised);
+    if style.heading != 0 {
+        html = format!("<span class=\"h{}\">{}</span>", style.heading, html);
+    }
+    if style.quoted {
+        html = format!("<span class=\"quote\">{}</span>", html);
+    }
+    html
+}
+
+/// Rows of `| a | b |` cells, the first is the header when followed by `|---|---|`.
+fn table_html(lines: &[&str]) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+    let cells = |line: &str| -> Vec<String> {
+        let line = line.trim().trim_start_matches('|').trim_end_matches('|');
+        line.split('|')
+            .map(|cell| cell.trim().to_string())
+            .collect()
+    };
+    let is_separator = |line: &str| {
+        cells(line)
+            .iter()
+            .all(|cell| !cell.is_empty() && cell.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+    };
+    let has_header = lines.get(1).is_some_and(|line| is_separator(line));
+    let mut html = String::from("<table>\n");
+    for (i, line) in lines.iter().enumerate() {
+        if has_header && i == 1 {
+            continue;
+        }
+        let tag = if has_header && i == 0 { "th" } else { "td" };
+        html.push_str("<tr>");
+        for cell in cells(line) {
+            html.push_str(&format!("<{tag}>{}</{tag}>", markup_html(&cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 #[test]
 fn test_easy_mark_parser() {
     let items: Vec<_> = Parser::new("~strikethrough `code`~").collect();
@@ -354,3 +678,79 @@ fn test_easy_mark_parser() {
         ]
     );
 }
+
+#[test]
+fn test_plain_text() {
+    assert_eq!(
+        plain_text("# Title\n*bold* and `code` with [a link](https://example.com)"),
+        "Title\nbold and code with a link"
+    );
+    assert_eq!(plain_text("- one\n  1. two"), "• one\n  1. two");
+    assert_eq!(
+        plain_text("before\n```rs\nlet a = 1;\n```\nafter"),
+        "before\nlet a = 1;\nafter"
+    );
+}
+
+#[test]
+fn test_links() {
+    let text = "See [the docs](https://example.com/a) or https://example.com/b.\n\
+        `https://example.com/c` [broken](https://example.com/d\n\
+        ```\nhttps://example.com/e\n```\n\
+        (https://en.wikipedia.org/wiki/Rust_(programming_language))";
+    let links: Vec<_> = links(text)
+        .into_iter()
+        .map(|(range, url)| (&text[range], url))
+        .collect();
+    assert_eq!(
+        links,
+        [
+            ("[the docs](https://example.com/a)", "https://example.com/a"),
+            ("https://example.com/b", "https://example.com/b"),
+            ("https://example.com/d", "https://example.com/d"),
+            (
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_split_code_blocks() {
+    assert_eq!(
+        split_code_blocks("before\n```rs\nlet a = 1;\n```\nafter\n```\nopen"),
+        [
+            ("before", false),
+            ("```rs\nlet a = 1;\n```", true),
+            ("after", false),
+            ("```\nopen", true),
+        ]
+    );
+    assert_eq!(split_code_blocks("plain"), [("plain", false)]);
+}
+
+#[test]
+fn test_html() {
+    assert_eq!(
+        html("## Title\n**bold** a < b &"),
+        "<h2>Title</h2>\n<strong>bold</strong> a &lt; b &amp;"
+    );
+    // easy mark markup is literal, paths and identifiers are left as they are
+    assert_eq!(
+        html("*see* src/main.rs, my_var, $5 and ~~old~~"),
+        "<em>see</em> src/main.rs, my_var, $5 and <s>old</s>"
+    );
+    assert_eq!(
+        html("* [a_b](https://x.org/a_b)"),
+        "&bull; <a href=\"https://x.org/a_b\">a_b</a>"
+    );
+    assert_eq!(
+        html("```rs\nlet a = 1;\n```\nafter"),
+        "<pre><code class=\"language-rs\">let a = 1;</code></pre>\nafter"
+    );
+    assert_eq!(
+        html("| a | b |\n|---|---|\n| `1` | 2 |\n"),
+        "<table>\n<tr><th>a</th><th>b</th></tr>\n<tr><td><code>1</code></td><td>2</td></tr>\n</table>\n"
+    );
+}
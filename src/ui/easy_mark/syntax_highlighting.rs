@@ -38,6 +38,104 @@ pub fn highlight(ctx: &egui::Context, theme: &CodeTheme, code: &str, language: &
     })
 }
 
+/// Guess the language of a code block without a fence language, `"text"`
+/// unless one language is clearly more likely than the others.
+pub fn guess_language(code: &str) -> &'static str {
+    let trimmed = code.trim();
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return "json";
+    }
+    if trimmed.starts_with("#!/bin/") || trimmed.starts_with("#!/usr/bin/env bash") {
+        return "sh";
+    }
+    let lines = || code.lines().map(str::trim_start);
+    let candidates: [(&str, usize); 5] = [
+        (
+            "rs",
+            [
+                "fn ", "let mut ", "impl ", "pub ", "use std", "#[derive", "println!", "-> ", "::",
+            ]
+            .into_iter()
+            .filter(|marker| code.contains(marker))
+            .count(),
+        ),
+        (
+            "py",
+            [
+                "def ", "elif ", "self.", "__init__", "print(", "None", "import ",
+            ]
+            .into_iter()
+            .filter(|marker| code.contains(marker))
+            .count()
+                + lines()
+                    .filter(|line| {
+                        ["def ", "class ", "if ", "for ", "while "]
+                            .iter()
+                            .any(|keyword| line.starts_with(keyword))
+                            && line.ends_with(':')
+                    })
+                    .count()
+                    .min(2),
+        ),
+        (
+            "js",
+            [
+                "function ",
+                "const ",
+                "=> ",
+                "console.log",
+                "===",
+                "require(",
+                "document.",
+            ]
+            .into_iter()
+            .filter(|marker| code.contains(marker))
+            .count(),
+        ),
+        (
+            "sql",
+            // only upper case keywords, lower case ones are common in prose
+            [
+                "SELECT ",
+                " FROM ",
+                "WHERE ",
+                "INSERT INTO ",
+                "CREATE TABLE ",
+                "JOIN ",
+                "GROUP BY ",
+            ]
+            .into_iter()
+            .filter(|marker| code.contains(marker))
+            .count(),
+        ),
+        (
+            "sh",
+            lines()
+                .filter(|line| {
+                    [
+                        "$ ", "cargo ", "git ", "npm ", "pip ", "sudo ", "cd ", "export ", "echo ",
+                    ]
+                    .iter()
+                    .any(|command| line.starts_with(command))
+                })
+                .count(),
+        ),
+    ];
+    let best = candidates
+        .iter()
+        .map(|(_, score)| *score)
+        .max()
+        .unwrap_or(0);
+    let mut likely = candidates.iter().filter(|(_, score)| *score == best);
+    match (likely.next(), likely.next()) {
+        // a single keyword is as likely to be prose
+        (Some((language, score)), None) if *score >= 2 => language,
+        _ => "text",
+    }
+}
+
 // ----------------------------------------------------------------------------
 
 #[cfg(not(feature = "syntect"))]
@@ -149,12 +247,12 @@ impl CodeTheme {
     pub fn from_memory(ctx: &egui::Context) -> Self {
         if ctx.style().visuals.dark_mode {
             ctx.data_mut(|d| {
-                d.get_persisted(egui::Id::new("dark"))
+                d.get_temp(egui::Id::new("dark"))
                     .unwrap_or_else(CodeTheme::dark)
             })
         } else {
             ctx.data_mut(|d| {
-                d.get_persisted(egui::Id::new("light"))
+                d.get_temp(egui::Id::new("light"))
                     .unwrap_or_else(CodeTheme::light)
             })
         }
@@ -162,9 +260,9 @@ impl CodeTheme {
 
     pub fn store_in_memory(self, ctx: &egui::Context) {
         if self.dark_mode {
-            ctx.data_mut(|d| d.insert_persisted(egui::Id::new("dark"), self));
+            ctx.data_mut(|d| d.insert_temp(egui::Id::new("dark"), self));
         } else {
-            ctx.data_mut(|d| d.insert_persisted(egui::Id::new("light"), self));
+            ctx.data_mut(|d| d.insert_temp(egui::Id::new("light"), self));
         }
     }
 }
@@ -235,7 +333,7 @@ impl CodeTheme {
         ui.horizontal_top(|ui| {
             let selected_id = egui::Id::null();
             let mut selected_tt: TokenType =
-                ui.data_mut(|d| *d.get_persisted_mut_or(selected_id, TokenType::Comment));
+                ui.data_mut(|d| *d.get_temp_mut_or(selected_id, TokenType::Comment));
 
             ui.vertical(|ui| {
                 ui.set_width(150.0);
@@ -277,7 +375,7 @@ impl CodeTheme {
 
             ui.add_space(16.0);
 
-            ui.data_mut(|d| d.insert_persisted(selected_id, selected_tt));
+            ui.data_mut(|d| d.insert_temp(selected_id, selected_tt));
 
             egui::Frame::group(ui.style())
                 .inner_margin(egui::Vec2::splat(2.0))
@@ -505,3 +603,30 @@ fn is_keyword(word: &str) -> bool {
             | "while"
     )
 }
+
+#[test]
+fn test_guess_language() {
+    assert_eq!(guess_language(r#"{"a": [1, 2]}"#), "json");
+    assert_eq!(
+        guess_language("fn main() {\n    let mut a = 1;\n    println!(\"{a}\");\n}"),
+        "rs"
+    );
+    assert_eq!(
+        guess_language("def add(a, b):\n    return a + b\n\nprint(add(1, 2))"),
+        "py"
+    );
+    assert_eq!(
+        guess_language("const add = (a, b) => a + b;\nconsole.log(add(1, 2));"),
+        "js"
+    );
+    assert_eq!(
+        guess_language("SELECT name FROM users WHERE id = 1;"),
+        "sql"
+    );
+    assert_eq!(guess_language("cd project\ncargo build --release"), "sh");
+    assert_eq!(
+        guess_language("Select the file from the menu, then press OK."),
+        "text"
+    );
+    assert_eq!(guess_language("Hello world"), "text");
+}
@@ -3,22 +3,38 @@ use eframe::egui;
 use std::{
     collections::{BTreeSet, HashMap},
     path::Path,
+    sync::Arc,
+    time::Instant,
 };
 
+use egui_notify::Toasts;
+use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 use tokio::runtime::Handle;
 
-use crate::api::{
-    chat::{Chat, ChatAPI, ChatAPIBuilder},
-    complete::{Complete, CompleteAPI, CompleteAPIBuilder},
+use crate::{
+    api::{
+        chat::{Chat, ChatAPI, ChatAPIBuilder, ChatMessage, Role},
+        complete::{Complete, CompleteAPI, CompleteAPIBuilder},
+    },
+    client::MultiClient,
 };
 
-use super::{chat_window::ChatWindow, complete_window::CompleteWindow, ModelType, TabWindow};
+use super::{
+    chat_window::ChatWindow, complete_window::CompleteWindow, components, settings::SharedSettings,
+    ModelType, TabRequest, TabWindow, WindowEvent, UNDO_TIMEOUT,
+};
 
 pub struct ViewContext {
     pub name: String,
     pub view: Box<dyn TabWindow<Response = ()>>,
     pub api: APIImpl,
+    /// shown in a floating window instead of a tab
+    pub detached: bool,
+    /// listed in the "Pinned" section above the others
+    pub pinned: bool,
+    /// only organizes the list
+    pub folder: Option<String>,
 }
 
 #[derive(Debug, From)]
@@ -27,24 +43,231 @@ pub enum APIImpl {
     Complete(CompleteAPI),
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum SavedData {
+    Chat(Chat),
+    Complete(Complete),
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedConversation {
+    name: String,
+    /// missing in older files, which use the default setting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    markdown: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wrap_code: Option<bool>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder: Option<String>,
+    /// extra request headers, name and value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<(String, String)>,
+    #[serde(flatten)]
+    data: SavedData,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFile {
+    version: u32,
+    conversations: Vec<SavedConversation>,
+}
+
+impl SavedFile {
+    const VERSION: u32 = 2;
+
+    /// The conversations that can be read from a file that cannot be as a whole,
+    /// and how many could not. `None` when it is not JSON or of a newer version.
+    fn partial(bytes: &[u8]) -> Option<(Vec<SavedConversation>, usize)> {
+        let conversations = match serde_json::from_slice(bytes).ok()? {
+            serde_json::Value::Array(conversations) => conversations,
+            serde_json::Value::Object(mut file) => {
+                let version = file.get("version").and_then(serde_json::Value::as_u64);
+                if version.is_some_and(|version| version > Self::VERSION as u64) {
+                    return None;
+                }
+                match file.remove("conversations")? {
+                    serde_json::Value::Array(conversations) => conversations,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        let total = conversations.len();
+        let conversations: Vec<SavedConversation> = conversations
+            .into_iter()
+            .filter_map(|conversation| serde_json::from_value(conversation).ok())
+            .collect();
+        let skipped = total - conversations.len();
+        Some((conversations, skipped))
+    }
+
+    /// Read the current format, or one written by an older version.
+    fn from_reader(reader: impl std::io::Read) -> Result<Self, anyhow::Error> {
+        let file = match serde_json::from_reader(reader)? {
+            AnySavedFile::Versioned(file) => file,
+            AnySavedFile::Unversioned(conversations) => Self {
+                version: Self::VERSION,
+                conversations,
+            },
+            AnySavedFile::Legacy(LegacySavedFile { chat, complete }) => Self {
+                version: 1,
+                conversations: chat
+                    .into_iter()
+                    .map(|(name, chat)| SavedConversation {
+                        name,
+                        markdown: None,
+                        wrap_code: None,
+                        pinned: false,
+                        folder: None,
+                        headers: Vec::new(),
+                        data: SavedData::Chat(chat),
+                    })
+                    .chain(
+                        complete
+                            .into_iter()
+                            .map(|(name, complete)| SavedConversation {
+                                name,
+                                markdown: None,
+                                wrap_code: None,
+                                pinned: false,
+                                folder: None,
+                                headers: Vec::new(),
+                                data: SavedData::Complete(complete),
+                            }),
+                    )
+                    .collect(),
+            },
+        };
+        if file.version > Self::VERSION {
+            anyhow::bail!("unsupported save file version {}", file.version);
+        }
+        Ok(file)
+    }
+}
+
+/// `name` without the characters file systems do not allow.
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    // hidden or special files otherwise
+    match stem.trim_start_matches('.') {
+        "" => "_".to_string(),
+        _ => stem,
+    }
+}
+
+fn is_conversation_file(file_name: &str) -> bool {
+    file_name.ends_with(".chat.json") || file_name.ends_with(".complete.json")
+}
+
+/// Index of the name after `focused` in `names`, or before it when not `forward`,
+/// from the first or the last one when nothing is focused.
+fn next_focus(names: &[String], focused: Option<&str>, forward: bool) -> Option<usize> {
+    let last = names.len().checked_sub(1)?;
+    Some(
+        match focused.and_then(|f| names.iter().position(|n| n == f)) {
+            Some(index) if forward => (index + 1).min(last),
+            Some(index) => index.saturating_sub(1),
+            None if forward => 0,
+            None => last,
+        },
+    )
+}
+
+/// Copy `path` next to it as `<name>.bak.<unix time>`.
+fn backup_file(path: &Path) -> Result<std::path::PathBuf, anyhow::Error> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".bak.{}", timestamp));
+    let backup = std::path::PathBuf::from(backup);
+    std::fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnySavedFile {
+    Versioned(SavedFile),
+    /// ordered, but without a version
+    Unversioned(Vec<SavedConversation>),
+    Legacy(LegacySavedFile),
+}
+
+/// Version 1, conversations keyed by name in no particular order.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacySavedFile {
+    #[serde(default)]
+    chat: HashMap<String, Chat>,
+    #[serde(default)]
+    complete: HashMap<String, Complete>,
+}
+
+/// Whether `name` is still the generated `chat_1` style name.
+pub fn is_untitled(name: &str) -> bool {
+    ModelType::iter().any(|model_type| {
+        name.strip_prefix(&format!("{}_", model_type))
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
 pub enum ResponseEvent {
     Select(String),
-    Remove(String),
+    Remove(Vec<String>),
     /// from, to
     Rename(String, String),
     None,
 }
 
 impl ViewContext {
-    pub fn new(name: String, api: APIImpl) -> Self {
+    fn saved(&self) -> SavedConversation {
+        SavedConversation {
+            name: self.name.clone(),
+            markdown: Some(self.view.markdown()),
+            wrap_code: self.view.wrap_code(),
+            pinned: self.pinned,
+            folder: self.folder.clone(),
+            headers: match &self.api {
+                APIImpl::Chat(chat) => chat.headers(),
+                APIImpl::Complete(complete) => complete.headers(),
+            },
+            data: match &self.api {
+                APIImpl::Chat(chat) => SavedData::Chat(chat.data()),
+                APIImpl::Complete(complete) => SavedData::Complete(complete.data()),
+            },
+        }
+    }
+    pub fn new(name: String, api: APIImpl, settings: SharedSettings) -> Self {
         let view = match &api {
-            APIImpl::Chat(chat) => Box::new(ChatWindow::new(name.clone(), chat.clone()))
+            APIImpl::Chat(chat) => Box::new(ChatWindow::new(name.clone(), chat.clone(), settings))
                 as Box<dyn TabWindow<Response = ()>>,
-            APIImpl::Complete(complete) => {
-                Box::new(CompleteWindow::new(name.clone(), complete.clone()))
-            }
+            APIImpl::Complete(complete) => Box::new(CompleteWindow::new(
+                name.clone(),
+                complete.clone(),
+                settings.borrow().markdown_by_default,
+            )),
         };
-        Self { name, view, api }
+        Self {
+            name,
+            view,
+            api,
+            detached: false,
+            pinned: false,
+            folder: None,
+        }
     }
 }
 
@@ -52,25 +275,78 @@ pub struct ListView {
     text: String,
     select_mode: ModelType,
     selected: BTreeSet<String>,
+    /// picked with Ctrl or Shift click for the bulk actions, unlike the open tabs in `selected`
+    marked: BTreeSet<String>,
+    /// the last clicked conversation, where a Shift click range starts
+    mark_anchor: Option<String>,
     views: Vec<ViewContext>,
     rename: Option<String>,
     rename_buffer: String,
+    /// name of a new folder, typed in the context menu
+    folder_buffer: String,
+    settings: SharedSettings,
+    client: Arc<MultiClient>,
+    /// for the app, waiting to be taken once the dock is drawn
+    events: Vec<WindowEvent>,
+    /// index of the conversation being dragged in the list
+    dragging: Option<usize>,
+    /// waiting for the removal to be confirmed, once for all of them
+    confirm_remove: Option<Vec<String>>,
+    dont_ask_again: bool,
+    /// positions and conversations of the last removal, and its time
+    last_removed: Option<(Vec<(usize, ViewContext)>, Instant)>,
+    /// highlighted by the arrow keys, while the list has focus
+    focused: Option<String>,
+    /// the last click was in the list, so it takes the arrow keys
+    has_focus: bool,
+    /// the names in the order they were listed in the last frame, without the collapsed ones
+    shown: Vec<String>,
+    /// the focus moved with the keyboard, so the list follows it
+    scroll_to_focused: bool,
+    /// the conversations a prompt is typed for, while the broadcast window is open
+    broadcast_to: Option<Vec<String>>,
+    broadcast_text: String,
+    /// files of the save directory that could not be loaded, never saved over or removed
+    unreadable: BTreeSet<String>,
+    toasts: Toasts,
 }
 
-impl Default for ListView {
-    fn default() -> Self {
+impl ListView {
+    const SAVE_FILE: &'static str = "./chats.json";
+    /// one file per conversation
+    const SAVE_DIR: &'static str = "./chats";
+    /// the file names of the save directory, in the order of the list
+    const ORDER_FILE: &'static str = "order.json";
+
+    pub fn new(settings: SharedSettings, client: Arc<MultiClient>) -> Self {
         Self {
             text: String::new(),
             select_mode: ModelType::Chat,
             selected: BTreeSet::new(),
+            marked: BTreeSet::new(),
+            mark_anchor: None,
             rename: None,
             views: Vec::new(),
             rename_buffer: String::new(),
+            folder_buffer: String::new(),
+            settings,
+            client,
+            events: Vec::new(),
+            dragging: None,
+            confirm_remove: None,
+            dont_ask_again: false,
+            last_removed: None,
+            focused: None,
+            has_focus: false,
+            shown: Vec::new(),
+            scroll_to_focused: false,
+            broadcast_to: None,
+            broadcast_text: String::new(),
+            unreadable: BTreeSet::new(),
+            toasts: Toasts::default(),
         }
     }
-}
 
-impl ListView {
     fn generate_new_name(&self) -> String {
         let mut name = String::new();
         let mut i = 1;
@@ -84,9 +360,34 @@ impl ListView {
         name
     }
 
+    /// `OPENAI_API_KEY`, or the key entered in the app when it is not set.
+    pub fn api_key(&self) -> String {
+        std::env::var("OPENAI_API_KEY")
+            .ok()
+            .filter(|api_key| !api_key.trim().is_empty())
+            .unwrap_or_else(|| self.settings.borrow().api_key.clone())
+    }
+    /// Use `api_key` for every open conversation.
+    pub fn set_api_key(&mut self, api_key: String) {
+        tokio::task::block_in_place(|| {
+            Handle::current().block_on(async {
+                for context in self.views.iter() {
+                    match &context.api {
+                        APIImpl::Chat(chat) => chat.set_api_key(api_key.clone()).await,
+                        APIImpl::Complete(complete) => complete.set_api_key(api_key.clone()).await,
+                    }
+                }
+            })
+        });
+    }
     pub fn new_chat(&mut self, name: Option<String>) -> Result<(), anyhow::Error> {
-        let api_key = std::env::var("OPENAI_API_KEY").unwrap_or_default();
-        let chat = ChatAPIBuilder::new(api_key).build();
+        let api_key = self.api_key();
+        let mut builder = ChatAPIBuilder::new(api_key, self.client.clone());
+        let default_model = self.settings.borrow().default_model.trim().to_string();
+        if !default_model.is_empty() {
+            builder = builder.with_model(default_model);
+        }
+        let chat = builder.build();
         if let Ok(system_message) = std::env::var("SYSTEM_MESSAGE") {
             if !system_message.is_empty() {
                 tokio::task::block_in_place(|| {
@@ -99,104 +400,810 @@ impl ListView {
 
         let name = name.unwrap_or_else(|| self.generate_new_name());
 
-        let context = ViewContext::new(name.clone(), APIImpl::Chat(chat));
+        let context = ViewContext::new(name.clone(), APIImpl::Chat(chat), self.settings.clone());
 
         self.views.push(context);
         Ok(())
     }
     pub fn new_complete(&mut self, name: Option<String>) -> Result<(), anyhow::Error> {
-        let api_key = std::env::var("OPENAI_API_KEY").unwrap_or_default();
-        let complete = CompleteAPIBuilder::new(api_key).build();
+        let api_key = self.api_key();
+        let complete = CompleteAPIBuilder::new(api_key, self.client.clone()).build();
         let name = name.unwrap_or_else(|| self.generate_new_name());
-        let context = ViewContext::new(name.clone(), APIImpl::Complete(complete));
+        let context = ViewContext::new(
+            name.clone(),
+            APIImpl::Complete(complete),
+            self.settings.clone(),
+        );
 
         self.views.push(context);
         Ok(())
     }
-    pub fn remove(&mut self, name: &str) -> Option<APIImpl> {
+    /// Create a new conversation of the currently selected mode.
+    pub fn new_view(&mut self, name: Option<String>) -> Result<(), anyhow::Error> {
+        match self.select_mode {
+            ModelType::Chat => self.new_chat(name),
+            ModelType::Complete => self.new_complete(name),
+            ModelType::Edit => {
+                tracing::warn!("edit mode not supported yet.");
+                Ok(())
+            }
+        }
+    }
+    /// What the tabs asked of the app since the last call, in order.
+    pub fn take_events(&mut self) -> Vec<WindowEvent> {
+        std::mem::take(&mut self.events)
+    }
+    /// `tab_suffix`, or `tab_suffix_2` and so on when taken.
+    fn derived_name(&self, tab: &str, suffix: &str) -> String {
+        let mut name = format!("{}_{}", tab, suffix);
+        let mut i = 2;
+        while self.views.iter().any(|v| v.name == name) {
+            name = format!("{}_{}_{}", tab, suffix, i);
+            i += 1;
+        }
+        name
+    }
+    fn handle_request(&mut self, tab: &str, request: TabRequest) {
+        match request {
+            TabRequest::Fork(api) => {
+                let name = self.derived_name(tab, "fork");
+                let markdown = self
+                    .views
+                    .iter()
+                    .find(|v| v.name == tab)
+                    .map(|v| v.view.markdown());
+                let mut context = ViewContext::new(name.clone(), api, self.settings.clone());
+                if let Some(markdown) = markdown {
+                    context.view.set_markdown(markdown);
+                }
+                self.views.push(context);
+                self.selected.insert(name.clone());
+                self.events.push(WindowEvent::Opened(name));
+            }
+            TabRequest::Rename(title) => {
+                // renamed by hand in the meantime
+                if !is_untitled(tab) {
+                    return;
+                }
+                let mut name = title.clone();
+                let mut i = 2;
+                while self.views.iter().any(|v| v.name == name) {
+                    name = format!("{} {}", title, i);
+                    i += 1;
+                }
+                let Some(context) = self.views.iter_mut().find(|v| v.name == tab) else {
+                    return;
+                };
+                context.name = name.clone();
+                context.view.set_name(name.clone());
+                if self.selected.remove(tab) {
+                    self.selected.insert(name.clone());
+                }
+                self.events
+                    .push(WindowEvent::Renamed(tab.to_string(), name));
+            }
+            TabRequest::NewChat(text) => {
+                let name = self.derived_name(tab, "chat");
+                if let Err(e) = self.new_chat(Some(name.clone())) {
+                    tracing::error!("{}", e);
+                    return;
+                }
+                if let Some(APIImpl::Chat(chat)) = self.views.last().map(|v| &v.api) {
+                    let mut chat = chat.clone();
+                    tokio::task::block_in_place(|| {
+                        Handle::current().block_on(chat.add_message(ChatMessage {
+                            role: Role::User,
+                            content: text,
+                        }))
+                    });
+                }
+                self.selected.insert(name.clone());
+                self.events.push(WindowEvent::Opened(name));
+            }
+        }
+    }
+    /// The unsent inputs, by conversation.
+    pub fn drafts(&self) -> HashMap<String, String> {
+        self.views
+            .iter()
+            .filter_map(|v| Some((v.name.clone(), v.view.draft()?.to_string())))
+            .collect()
+    }
+    /// Restore the unsent inputs, those of missing conversations are dropped.
+    pub fn set_drafts(&mut self, mut drafts: HashMap<String, String>) {
+        for context in &mut self.views {
+            if let Some(draft) = drafts.remove(&context.name) {
+                context.view.set_draft(draft);
+            }
+        }
+    }
+    pub fn toggle_presentation(&mut self, name: &str) {
+        if let Some(context) = self.views.iter_mut().find(|v| v.name == name) {
+            context.view.toggle_presentation();
+        }
+    }
+    /// Conversations generating a reply.
+    pub fn generating(&self) -> usize {
+        self.views.iter().filter(|v| v.view.is_generating()).count()
+    }
+    /// Abort every running generation, the partial replies are kept.
+    pub fn stop_all(&self) {
+        for context in self.views.iter().filter(|v| v.view.is_generating()) {
+            context.view.abort();
+        }
+    }
+    /// Ask `question` in each chat of `names` at once, they generate their
+    /// replies independently, in the queue of the client. Returns the chats
+    /// asked, those busy with another reply are skipped.
+    pub fn broadcast(&mut self, names: &[String], question: &str) -> Vec<String> {
+        self.views
+            .iter_mut()
+            .filter(|v| names.contains(&v.name) && matches!(v.api, APIImpl::Chat(_)))
+            .filter_map(|v| v.view.ask(question.to_string()).then(|| v.name.clone()))
+            .collect()
+    }
+    /// Type the prompt broadcast to `names` and send it.
+    fn broadcast_window(&mut self, ctx: &egui::Context) {
+        let Some(names) = self.broadcast_to.clone() else {
+            return;
+        };
+        let mut open = true;
+        let mut send = false;
+        egui::Window::new("Broadcast")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Ask {}:", names.join(", ")));
+                ui.add(
+                    egui::TextEdit::multiline(&mut self.broadcast_text)
+                        .hint_text("The same prompt for each conversation")
+                        .desired_rows(4),
+                );
+                send = ui
+                    .add_enabled(
+                        !self.broadcast_text.trim().is_empty(),
+                        egui::Button::new("Send"),
+                    )
+                    .clicked();
+            });
+        if send {
+            let asked = self.broadcast(&names, &self.broadcast_text.clone());
+            let skipped = names.len() - asked.len();
+            if skipped > 0 {
+                self.toasts.info(format!(
+                    "Asked {} conversations, {} skipped while busy or without a key",
+                    asked.len(),
+                    skipped
+                ));
+            } else {
+                self.toasts
+                    .info(format!("Asked {} conversations", asked.len()));
+            }
+            self.broadcast_text.clear();
+            open = false;
+        }
+        if !open {
+            self.broadcast_to = None;
+        }
+    }
+    /// Names of the conversations, in the order of the list.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.views.iter().map(|v| v.name.as_str())
+    }
+    /// The chats with their names, sharing their data with the tabs.
+    pub fn chats(&self) -> Vec<(String, ChatAPI)> {
+        self.views
+            .iter()
+            .filter_map(|v| match &v.api {
+                APIImpl::Chat(chat) => Some((v.name.clone(), chat.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn is_chat(&self, name: &str) -> bool {
+        self.views
+            .iter()
+            .any(|v| v.name == name && matches!(v.api, APIImpl::Chat(_)))
+    }
+    pub fn set_model(&self, name: &str, model: String) {
+        if let Some(APIImpl::Chat(chat)) =
+            self.views.iter().find(|v| v.name == name).map(|v| &v.api)
+        {
+            let mut chat = chat.clone();
+            tokio::spawn(async move { chat.set_model(model).await });
+        }
+    }
+    /// Mark the tab of `name` as open, `false` when there is no such conversation.
+    pub fn reopen(&mut self, name: &str) -> bool {
+        let exists = self.views.iter().any(|v| v.name == name);
+        if exists {
+            self.selected.insert(name.to_string());
+        }
+        exists
+    }
+    /// Mark the tab of `name` as closed, without removing the conversation.
+    pub fn close(&mut self, name: &str) {
         self.selected.remove(name);
-
-        let context = self
-            .views
-            .remove(self.views.iter().position(|v| v.name == name)?);
-        Some(context.api)
+    }
+    /// Remove the conversations, keeping them until the next removal for [`Self::undo_remove`].
+    pub fn remove(&mut self, names: &[String]) {
+        let mut removed = Vec::new();
+        // by position, so the ones left in front keep theirs
+        for index in (0..self.views.len()).rev() {
+            let name = &self.views[index].name;
+            if names.contains(name) {
+                self.selected.remove(name);
+                self.marked.remove(name);
+                let mut context = self.views.remove(index);
+                context.detached = false;
+                removed.push((index, context));
+            }
+        }
+        removed.reverse();
+        let text = match removed.as_slice() {
+            [] => return,
+            [(_, context)] => format!("Conversation {} removed", context.name),
+            removed => format!("{} conversations removed", removed.len()),
+        };
+        self.last_removed = Some((removed, Instant::now()));
+        self.toasts.info(text).set_duration(Some(UNDO_TIMEOUT));
+    }
+    /// Put back the last removed conversations where they were.
+    pub fn undo_remove(&mut self) {
+        let Some((removed, _)) = self.last_removed.take() else {
+            return;
+        };
+        for (index, mut context) in removed {
+            // the name may have been taken in the meantime
+            while self.views.iter().any(|v| v.name == context.name) {
+                context.name = format!("{}_restored", context.name);
+                context.view.set_name(context.name.clone());
+            }
+            let index = index.min(self.views.len());
+            self.views.insert(index, context);
+        }
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
-        let mut save_value: HashMap<String, HashMap<String, serde_json::Value>> = HashMap::new();
+        self.save_views(path, |_| true)
+    }
+    /// Save only the conversations of `names`, the file loads like a full save.
+    pub fn export<P: AsRef<Path>>(
+        &self,
+        path: P,
+        names: &BTreeSet<String>,
+    ) -> Result<(), anyhow::Error> {
+        self.save_views(path, |context| names.contains(&context.name))
+    }
+    fn save_views<P: AsRef<Path>>(
+        &self,
+        path: P,
+        keep: impl Fn(&ViewContext) -> bool,
+    ) -> Result<(), anyhow::Error> {
         let full_path = if path.as_ref().is_dir() {
             anyhow::bail!("path is directory");
         } else {
             path.as_ref().to_path_buf()
         };
-        for context in self.views.iter() {
-            let name = context.name.clone();
-            match &context.api {
-                APIImpl::Chat(chat) => {
-                    let value = serde_json::to_value(chat.data())?;
-                    save_value
-                        .entry("chat".to_string())
-                        .and_modify(|v| {
-                            v.insert(name.clone(), value.clone());
-                        })
-                        .or_insert_with(|| {
-                            let mut map = HashMap::new();
-                            map.insert(name, value);
-                            map
-                        });
-                }
-                APIImpl::Complete(complete) => {
-                    let value = serde_json::to_value(complete.data())?;
-                    save_value
-                        .entry("complete".to_string())
-                        .and_modify(|v| {
-                            v.insert(name.clone(), value.clone());
-                        })
-                        .or_insert_with(|| {
-                            let mut map = HashMap::new();
-                            map.insert(name, value);
-                            map
-                        });
-                }
-            }
-        }
+        let conversations = self
+            .views
+            .iter()
+            .filter(|context| keep(context))
+            .map(ViewContext::saved)
+            .collect::<Vec<_>>();
 
         let mut file = std::fs::File::create(full_path)?;
-        serde_json::to_writer(&mut file, &save_value)?;
+        serde_json::to_writer(
+            &mut file,
+            &SavedFile {
+                version: SavedFile::VERSION,
+                conversations,
+            },
+        )?;
 
         Ok(())
     }
+    /// Save each conversation to its own file in `dir`, removing the files of
+    /// conversations that no longer exist but not the ones that could not be loaded.
+    pub fn save_dir<P: AsRef<Path>>(&self, dir: P) -> Result<(), anyhow::Error> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let mut written = BTreeSet::new();
+        let mut order = Vec::new();
+        for context in &self.views {
+            let saved = context.saved();
+            let kind = match saved.data {
+                SavedData::Chat(_) => "chat",
+                SavedData::Complete(_) => "complete",
+            };
+            // names that only differ by characters left out of file names
+            let stem = file_stem(&context.name);
+            let mut file_name = format!("{}.{}.json", stem, kind);
+            let mut n = 1;
+            while written.contains(&file_name) || self.unreadable.contains(&file_name) {
+                n += 1;
+                file_name = format!("{} ({}).{}.json", stem, n, kind);
+            }
+            std::fs::write(dir.join(&file_name), serde_json::to_string_pretty(&saved)?)?;
+            written.insert(file_name.clone());
+            order.push(file_name);
+        }
+        std::fs::write(dir.join(Self::ORDER_FILE), serde_json::to_string(&order)?)?;
+        for entry in std::fs::read_dir(dir)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            if is_conversation_file(&file_name)
+                && !written.contains(&file_name)
+                && !self.unreadable.contains(&file_name)
+            {
+                std::fs::remove_file(dir.join(&file_name))?;
+            }
+        }
+        Ok(())
+    }
+    /// Where [`Settings::save_to_directory`](super::settings::Settings::save_to_directory) says.
+    pub fn save_default(&self) -> Result<(), anyhow::Error> {
+        if self.settings.borrow().save_to_directory {
+            self.save_dir(Self::SAVE_DIR)
+        } else {
+            self.save(Self::SAVE_FILE)
+        }
+    }
+    pub fn load_default(&mut self) -> Result<(), anyhow::Error> {
+        if self.settings.borrow().save_to_directory {
+            self.load_dir(Self::SAVE_DIR)
+        } else {
+            self.load(Self::SAVE_FILE)
+        }
+    }
 
     pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), anyhow::Error> {
-        let mut file = std::fs::File::open(path.as_ref())?;
+        if path.as_ref().is_dir() {
+            return self.load_dir(path);
+        }
+        let bytes = std::fs::read(path.as_ref())?;
 
-        let api_key = std::env::var("OPENAI_API_KEY").unwrap_or_default();
-        let value: HashMap<String, serde_json::Value> = serde_json::from_reader(&mut file)?;
-        let chats = if let Some(value) = value.get("chat") {
-            serde_json::from_value::<HashMap<String, Chat>>(value.clone())?
-        } else {
-            HashMap::new()
-        };
-        let completes = if let Some(value) = value.get("complete") {
-            serde_json::from_value::<HashMap<String, Complete>>(value.clone())?
-        } else {
-            HashMap::new()
+        let conversations = match SavedFile::from_reader(bytes.as_slice()) {
+            Ok(file) => file.conversations,
+            Err(e) => {
+                // kept before anything is loaded, or saved over it
+                let backup = backup_file(path.as_ref())?;
+                let Some((conversations, skipped)) = SavedFile::partial(&bytes) else {
+                    anyhow::bail!("{}, the file was backed up to {}", e, backup.display());
+                };
+                tracing::warn!("{}, skipped {} conversations", e, skipped);
+                self.toasts.warning(format!(
+                    "Skipped {} unreadable conversations, the file was backed up to {}",
+                    skipped,
+                    backup.display()
+                ));
+                conversations
+            }
         };
+        self.restore(conversations);
+        Ok(())
+    }
+    /// Load the conversation files of `dir` in the saved order, the ones missing
+    /// from it sorted by name after the others. The unreadable ones are skipped.
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), anyhow::Error> {
+        let order: Vec<String> = std::fs::read(dir.as_ref().join(Self::ORDER_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .is_some_and(|name| is_conversation_file(&name.to_string_lossy()))
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort_by_cached_key(|path| {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            let position = order
+                .iter()
+                .position(|saved| Some(saved) == file_name.as_ref());
+            (position.unwrap_or(usize::MAX), path.clone())
+        });
+        let mut conversations = Vec::new();
+        let mut skipped = 0;
+        self.unreadable.clear();
+        for path in paths {
+            let conversation = std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?));
+            match conversation {
+                Ok(conversation) => conversations.push(conversation),
+                Err(e) => {
+                    tracing::warn!("Skipped {}: {}", path.display(), e);
+                    if let Some(file_name) = path.file_name() {
+                        self.unreadable
+                            .insert(file_name.to_string_lossy().into_owned());
+                    }
+                    skipped += 1;
+                }
+            }
+        }
+        if skipped > 0 {
+            self.toasts.warning(format!(
+                "Skipped {} unreadable conversations, their files are kept",
+                skipped
+            ));
+        }
+        self.restore(conversations);
+        Ok(())
+    }
+    /// Replace the conversations with the saved ones.
+    fn restore(&mut self, conversations: Vec<SavedConversation>) {
+        let api_key = self.api_key();
         self.views.clear();
         self.selected.clear();
-        for (name, chat) in chats {
-            let chat = ChatAPIBuilder::new(api_key.clone()).with_data(chat).build();
-            self.views.push(ViewContext::new(name, APIImpl::Chat(chat)));
-        }
-        for (name, complete) in completes {
-            let complete = CompleteAPIBuilder::new(api_key.clone())
-                .with_data(complete)
-                .build();
-            self.views
-                .push(ViewContext::new(name, APIImpl::Complete(complete)));
+        self.marked.clear();
+        for SavedConversation {
+            name,
+            markdown,
+            wrap_code,
+            pinned,
+            folder,
+            headers,
+            data,
+        } in conversations
+        {
+            let api = match data {
+                SavedData::Chat(chat) => APIImpl::Chat(
+                    ChatAPIBuilder::new(api_key.clone(), self.client.clone())
+                        .with_data(chat)
+                        .with_headers(headers)
+                        .build(),
+                ),
+                SavedData::Complete(complete) => APIImpl::Complete(
+                    CompleteAPIBuilder::new(api_key.clone(), self.client.clone())
+                        .with_data(complete)
+                        .with_headers(headers)
+                        .build(),
+                ),
+            };
+            let mut context = ViewContext::new(name, api, self.settings.clone());
+            if let Some(markdown) = markdown {
+                context.view.set_markdown(markdown);
+            }
+            if let Some(wrap_code) = wrap_code {
+                context.view.set_wrap_code(wrap_code);
+            }
+            context.pinned = pinned;
+            context.folder = folder;
+            self.views.push(context);
         }
+    }
+    /// The conversations at `indices`, in the order of the list.
+    fn conversations_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        indices: &[usize],
+        event: &mut ResponseEvent,
+        will_remove: &mut Option<Vec<String>>,
+        will_export: &mut bool,
+    ) {
+        let folders = self.folders();
+        let chat_names: BTreeSet<_> = self
+            .views
+            .iter()
+            .filter(|v| matches!(v.api, APIImpl::Chat(_)))
+            .map(|v| v.name.clone())
+            .collect();
+        let names: Vec<_> = indices
+            .iter()
+            .map(|&i| self.views[i].name.clone())
+            .collect();
+        ui.with_layout(ui.layout().with_cross_justify(true), |ui| {
+            let mut rects = Vec::new();
+            for (position, &i) in indices.iter().enumerate() {
+                let ViewContext {
+                    name,
+                    view,
+                    pinned,
+                    folder,
+                    ..
+                } = &mut self.views[i];
+                if let Some(rename) = self.rename.clone() {
+                    if &rename == name {
+                        let resp = ui.text_edit_singleline(&mut self.rename_buffer);
+                        rects.push((i, resp.rect));
+                        if (!self.rename_buffer.is_empty()
+                            && resp.has_focus()
+                            && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                            || (!self.rename_buffer.is_empty() && resp.lost_focus())
+                        {
+                            // the pinned flag stays with the context
+                            self.selected.remove(name);
+                            self.selected.insert(self.rename_buffer.clone());
+                            if self.marked.remove(name) {
+                                self.marked.insert(self.rename_buffer.clone());
+                            }
+                            if self.focused.as_ref() == Some(name) {
+                                self.focused = Some(self.rename_buffer.clone());
+                            }
+                            view.set_name(self.rename_buffer.clone());
+                            *event =
+                                ResponseEvent::Rename(name.clone(), self.rename_buffer.clone());
+                            *name = self.rename_buffer.clone();
+                            self.rename_buffer.clear();
+                            self.rename = None;
+                        } else {
+                            resp.request_focus();
+                        }
+                        self.shown.push(name.clone());
+                        continue;
+                    }
+                }
 
-        Ok(())
+                // while picking, the highlight shows the picked ones instead of the open tabs
+                let highlighted = if self.marked.is_empty() {
+                    self.selected.contains(name)
+                } else {
+                    self.marked.contains(name)
+                };
+                let response = ui
+                    .selectable_label(highlighted, name.clone())
+                    .interact(egui::Sense::drag());
+                rects.push((i, response.rect));
+                self.shown.push(name.clone());
+                if self.has_focus && self.focused.as_ref() == Some(name) {
+                    ui.painter().rect_stroke(
+                        response.rect,
+                        ui.visuals().widgets.hovered.rounding,
+                        ui.visuals().selection.stroke,
+                    );
+                    if std::mem::take(&mut self.scroll_to_focused) {
+                        response.scroll_to_me(None);
+                    }
+                }
+                if response.drag_started() {
+                    self.dragging = Some(i);
+                }
+                response
+                    .context_menu(|ui| {
+                        if self.rename.is_none() && ui.button("rename").clicked() {
+                            self.rename = Some(name.clone());
+                            self.rename_buffer = name.clone();
+                            ui.close_menu();
+                        }
+                        if ui.button(if *pinned { "unpin" } else { "pin" }).clicked() {
+                            *pinned = !*pinned;
+                            ui.close_menu();
+                        }
+                        ui.menu_button("move to folder", |ui| {
+                            for f in &folders {
+                                if ui.selectable_label(folder.as_ref() == Some(f), f).clicked() {
+                                    *folder = Some(f.clone());
+                                    ui.close_menu();
+                                }
+                            }
+                            ui.horizontal(|ui| {
+                                let resp = ui.add(
+                                    egui::TextEdit::singleline(&mut self.folder_buffer)
+                                        .hint_text("New folder")
+                                        .desired_width(100.),
+                                );
+                                let new_folder = self.folder_buffer.trim();
+                                if ui
+                                    .add_enabled(!new_folder.is_empty(), egui::Button::new("new"))
+                                    .clicked()
+                                    || (!new_folder.is_empty()
+                                        && resp.lost_focus()
+                                        && ui.input(|i| i.key_pressed(egui::Key::Enter)))
+                                {
+                                    *folder = Some(new_folder.to_string());
+                                    self.folder_buffer.clear();
+                                    ui.close_menu();
+                                }
+                            });
+                            if folder.is_some() && ui.button("ungroup").clicked() {
+                                *folder = None;
+                                ui.close_menu();
+                            }
+                        });
+                        if ui.button("remove").clicked() {
+                            *will_remove = Some(vec![name.clone()]);
+                            ui.close_menu();
+                        };
+                        if self.marked.len() > 1 && self.marked.contains(name) {
+                            ui.separator();
+                            if ui
+                                .button(format!("remove selected ({})", self.marked.len()))
+                                .clicked()
+                            {
+                                *will_remove = Some(self.marked.iter().cloned().collect());
+                                ui.close_menu();
+                            }
+                            if ui
+                                .button(format!("export selected ({})", self.marked.len()))
+                                .clicked()
+                            {
+                                *will_export = true;
+                                ui.close_menu();
+                            }
+                            let chats: Vec<_> = self
+                                .marked
+                                .iter()
+                                .filter(|name| chat_names.contains(*name))
+                                .cloned()
+                                .collect();
+                            if chats.len() > 1
+                                && ui
+                                    .button(format!("broadcast to selected ({})", chats.len()))
+                                    .on_hover_text("Send the same prompt to each of these chats")
+                                    .clicked()
+                            {
+                                self.broadcast_to = Some(chats);
+                                ui.close_menu();
+                            } else if let [left, right] = chats.as_slice() {
+                                if ui
+                                    .button("compare selected")
+                                    .on_hover_text("Show both chats side by side")
+                                    .clicked()
+                                {
+                                    self.events
+                                        .push(WindowEvent::Compare(left.clone(), right.clone()));
+                                    ui.close_menu();
+                                }
+                            }
+                        }
+                        if ui.button("select").clicked() {
+                            self.selected.insert(name.clone());
+                            *event = ResponseEvent::Select(name.clone());
+                            ui.close_menu();
+                        }
+                    })
+                    .clicked()
+                    .then(|| {
+                        self.focused = Some(name.clone());
+                        let modifiers = ui.input(|i| i.modifiers);
+                        if modifiers.shift {
+                            // a range within this section
+                            let anchor = self
+                                .mark_anchor
+                                .as_ref()
+                                .and_then(|anchor| names.iter().position(|n| n == anchor))
+                                .unwrap_or(position);
+                            self.marked.extend(
+                                names[anchor.min(position)..=anchor.max(position)]
+                                    .iter()
+                                    .cloned(),
+                            );
+                        } else if modifiers.command {
+                            if !self.marked.remove(name) {
+                                self.marked.insert(name.clone());
+                            }
+                            self.mark_anchor = Some(name.clone());
+                        } else {
+                            self.marked.clear();
+                            self.mark_anchor = Some(name.clone());
+                            self.selected.insert(name.clone());
+                            *event = ResponseEvent::Select(name.clone())
+                        }
+                    });
+            }
+            // only reordered within its own section
+            if let Some(from) = self.dragging.filter(|from| indices.contains(from)) {
+                self.drop_target(ui, &rects, from);
+            }
+        });
+    }
+    /// Up and Down move the highlight through the listed conversations, Enter
+    /// opens, F2 renames and Delete removes the highlighted one.
+    fn keyboard(
+        &mut self,
+        ui: &mut egui::Ui,
+        event: &mut ResponseEvent,
+        will_remove: &mut Option<Vec<String>>,
+    ) {
+        // the rename box and the other inputs keep their keys
+        if !self.has_focus || self.rename.is_some() || ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        let (up, down, enter, f2, delete) = ui.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::F2),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Delete),
+            )
+        });
+        if up || down {
+            let next = next_focus(&self.shown, self.focused.as_deref(), down);
+            self.focused = next.map(|index| self.shown[index].clone());
+            self.scroll_to_focused = true;
+        }
+        let Some(name) = self
+            .focused
+            .clone()
+            .filter(|name| self.shown.contains(name))
+        else {
+            return;
+        };
+        if enter {
+            self.marked.clear();
+            self.mark_anchor = Some(name.clone());
+            self.selected.insert(name.clone());
+            *event = ResponseEvent::Select(name);
+        } else if f2 {
+            self.rename_buffer = name.clone();
+            self.rename = Some(name);
+        } else if delete {
+            *will_remove = Some(vec![name]);
+        }
+    }
+    /// Names of the folders in use, sorted.
+    fn folders(&self) -> BTreeSet<String> {
+        self.views.iter().filter_map(|v| v.folder.clone()).collect()
+    }
+    /// Show where the dragged conversation would land, and move it there on release.
+    /// `rects` are the listed conversations, by index into the views.
+    fn drop_target(&mut self, ui: &mut egui::Ui, rects: &[(usize, egui::Rect)], from: usize) {
+        let Some(pointer) = ui.ctx().pointer_interact_pos() else {
+            self.dragging = None;
+            return;
+        };
+        let (to, y) = match rects.iter().find(|(_, rect)| pointer.y < rect.center().y) {
+            Some((to, rect)) => (*to, rect.top()),
+            None => rects
+                .last()
+                .map_or((from, pointer.y), |(last, rect)| (last + 1, rect.bottom())),
+        };
+        let rect = ui.max_rect();
+        ui.painter()
+            .hline(rect.left()..=rect.right(), y, ui.visuals().selection.stroke);
+        if !ui.input(|i| i.pointer.any_down()) {
+            self.move_view(from, to);
+            self.dragging = None;
+        }
+    }
+    /// Move the conversation at `from` in front of the one at `to`.
+    fn move_view(&mut self, from: usize, to: usize) {
+        let to = if to > from { to - 1 } else { to };
+        let context = self.views.remove(from);
+        self.views.insert(to, context);
+    }
+    pub fn is_detached(&self, name: &str) -> bool {
+        self.views.iter().any(|v| v.name == name && v.detached)
+    }
+    /// Show the conversation of `name` in a floating window instead of a tab.
+    pub fn detach(&mut self, name: &str) {
+        if let Some(context) = self.views.iter_mut().find(|v| v.name == name) {
+            context.detached = true;
+        }
+    }
+    /// Show the detached conversations, returning the ones closed to be docked again.
+    pub fn show_detached(&mut self, ctx: &egui::Context) -> Vec<String> {
+        let mut reattached = Vec::new();
+        let mut requests = Vec::new();
+        for context in self.views.iter_mut().filter(|v| v.detached) {
+            context.view.show(ctx, &mut context.detached);
+            if !context.detached {
+                reattached.push(context.name.clone());
+            }
+            if let Some(request) = context.view.take_request() {
+                requests.push((context.name.clone(), request));
+            }
+        }
+        for (name, request) in requests {
+            self.handle_request(&name, request);
+        }
+        reattached
+    }
+    /// Insert the text of the file at `path` into the input of the conversation `name`.
+    pub fn attach_file(&mut self, name: &str, path: &Path) {
+        let Some(context) = self.views.iter_mut().find(|c| c.name == name) else {
+            return;
+        };
+        if !context.view.attach_file(path) {
+            self.toasts.warning("Files can only be attached to chats");
+        }
     }
     pub fn action(&mut self, name: &String, ui: &mut egui::Ui) {
         if let Some(context) = self.views.iter_mut().find(|c| &c.name == name) {
@@ -211,6 +1218,16 @@ impl super::View for ListView {
     fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response {
         let mut event = ResponseEvent::None;
         let mut will_remove = None;
+        let mut will_export = false;
+        if self
+            .last_removed
+            .as_ref()
+            .is_some_and(|(_, removed_at)| removed_at.elapsed() > UNDO_TIMEOUT)
+        {
+            self.last_removed = None;
+        }
+        self.keyboard(ui, &mut event, &mut will_remove);
+        self.shown.clear();
 
         ui.horizontal(|ui| {
             ui.add_sized(
@@ -225,16 +1242,8 @@ impl super::View for ListView {
                     self.text.clear();
                     name
                 };
-                match self.select_mode {
-                    ModelType::Chat => {
-                        self.new_chat(name).unwrap();
-                    }
-                    ModelType::Complete => {
-                        self.new_complete(name).unwrap();
-                    }
-                    ModelType::Edit => {
-                        tracing::warn!("edit mode not supported yet.")
-                    }
+                if let Err(e) = self.new_view(name) {
+                    tracing::error!("{}", e);
                 }
             });
             ui.menu_button("mode", |ui| {
@@ -247,72 +1256,86 @@ impl super::View for ListView {
                     };
                 }
             });
+            if let Some((_, removed_at)) = &self.last_removed {
+                ui.ctx()
+                    .request_repaint_after(UNDO_TIMEOUT.saturating_sub(removed_at.elapsed()));
+                ui.button("undo")
+                    .on_hover_text("Restore the removed conversations")
+                    .clicked()
+                    .then(|| self.undo_remove());
+            }
         });
-        egui::CentralPanel::default()
+        let list = egui::CentralPanel::default()
             .show_inside(ui, |ui| {
-                if !self.views.is_empty() {
+                let (pinned, others): (Vec<_>, Vec<_>) =
+                    (0..self.views.len()).partition(|&i| self.views[i].pinned);
+                if !pinned.is_empty() {
+                    egui::CollapsingHeader::new("Pinned")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            self.conversations_ui(
+                                ui,
+                                &pinned,
+                                &mut event,
+                                &mut will_remove,
+                                &mut will_export,
+                            );
+                        });
+                }
+                if !others.is_empty() {
                     egui::CollapsingHeader::new("Chat")
                         .default_open(true)
                         .show(ui, |ui| {
-                            ui.with_layout(ui.layout().with_cross_justify(true), |ui| {
-                                for ViewContext { name, view, .. } in self.views.iter_mut() {
-                                    if let Some(rename) = self.rename.clone() {
-                                        if &rename == name {
-                                            let resp =
-                                                ui.text_edit_singleline(&mut self.rename_buffer);
-                                            if (!self.rename_buffer.is_empty()
-                                                && resp.has_focus()
-                                                && ui.input(|i| i.key_pressed(egui::Key::Enter)))
-                                                || (!self.rename_buffer.is_empty()
-                                                    && resp.lost_focus())
-                                            {
-                                                self.selected.remove(name);
-                                                self.selected.insert(self.rename_buffer.clone());
-                                                view.set_name(self.rename_buffer.clone());
-                                                event = ResponseEvent::Rename(
-                                                    name.clone(),
-                                                    self.rename_buffer.clone(),
-                                                );
-                                                *name = self.rename_buffer.clone();
-                                                self.rename_buffer.clear();
-                                                self.rename = None;
-                                            } else {
-                                                resp.request_focus();
-                                            }
-
-                                            continue;
-                                        }
-                                    }
-
-                                    ui.selectable_label(
-                                        self.selected.iter().find(|s| *s == name).is_some(),
-                                        name.clone(),
-                                    )
-                                    .context_menu(|ui| {
-                                        if self.rename.is_none() {
-                                            if ui.button("rename").clicked() {
-                                                self.rename = Some(name.clone());
-                                                self.rename_buffer = name.clone();
-                                                ui.close_menu();
-                                            };
-                                        }
-                                        if ui.button("remove").clicked() {
-                                            will_remove = Some(name.clone());
-                                            ui.close_menu();
-                                        };
-                                        if ui.button("select").clicked() {
-                                            self.selected.insert(name.clone());
-                                            event = ResponseEvent::Select(name.clone());
-                                            ui.close_menu();
-                                        }
-                                    })
-                                    .clicked()
-                                    .then(|| {
-                                        self.selected.insert(name.clone());
-                                        event = ResponseEvent::Select(name.clone())
-                                    });
+                            let folders = self.folders();
+                            for folder in &folders {
+                                let indices: Vec<_> = others
+                                    .iter()
+                                    .copied()
+                                    .filter(|&i| self.views[i].folder.as_ref() == Some(folder))
+                                    .collect();
+                                if indices.is_empty() {
+                                    continue;
                                 }
-                            });
+                                egui::CollapsingHeader::new(folder)
+                                    .id_source(("folder", folder))
+                                    .default_open(true)
+                                    .show(ui, |ui| {
+                                        self.conversations_ui(
+                                            ui,
+                                            &indices,
+                                            &mut event,
+                                            &mut will_remove,
+                                            &mut will_export,
+                                        );
+                                    });
+                            }
+                            let ungrouped: Vec<_> = others
+                                .iter()
+                                .copied()
+                                .filter(|&i| self.views[i].folder.is_none())
+                                .collect();
+                            if folders.is_empty() {
+                                self.conversations_ui(
+                                    ui,
+                                    &ungrouped,
+                                    &mut event,
+                                    &mut will_remove,
+                                    &mut will_export,
+                                );
+                            } else if !ungrouped.is_empty() {
+                                egui::CollapsingHeader::new("Ungrouped")
+                                    .id_source("ungrouped")
+                                    .default_open(true)
+                                    .show(ui, |ui| {
+                                        self.conversations_ui(
+                                            ui,
+                                            &ungrouped,
+                                            &mut event,
+                                            &mut will_remove,
+                                            &mut will_export,
+                                        );
+                                    });
+                            }
                         });
                 }
             })
@@ -320,10 +1343,64 @@ impl super::View for ListView {
             .context_menu(|ui| {
                 ui.label("Actions");
             });
-        if let Some(name) = will_remove {
-            self.remove(&name);
-            event = ResponseEvent::Remove(name)
+        if ui.input(|i| i.pointer.any_pressed()) {
+            self.has_focus = ui
+                .input(|i| i.pointer.interact_pos())
+                .is_some_and(|pos| list.rect.contains(pos));
         }
+        if will_export {
+            if let Some(path) = rfd::FileDialog::new()
+                .set_file_name("chats.json")
+                .add_filter("JSON", &["json"])
+                .save_file()
+            {
+                match self.export(&path, &self.marked) {
+                    Ok(()) => {
+                        self.toasts.info(format!(
+                            "{} conversations exported to {}",
+                            self.marked.len(),
+                            path.display()
+                        ));
+                    }
+                    Err(e) => {
+                        self.toasts.error(format!("Failed to export: {}", e));
+                    }
+                }
+            }
+        }
+        if let Some(names) = will_remove {
+            if self.settings.borrow().confirm_destructive {
+                self.confirm_remove = Some(names);
+            } else {
+                self.remove(&names);
+                event = ResponseEvent::Remove(names)
+            }
+        }
+        if let Some(names) = self.confirm_remove.clone() {
+            let text = match names.as_slice() {
+                [name] => format!("Remove {}?", name),
+                names => format!("Remove {} conversations?", names.len()),
+            };
+            match components::confirm(
+                ui.ctx(),
+                "Remove conversation",
+                &text,
+                &mut self.dont_ask_again,
+            ) {
+                Some(true) => {
+                    if self.dont_ask_again {
+                        self.settings.borrow_mut().confirm_destructive = false;
+                    }
+                    self.confirm_remove = None;
+                    self.remove(&names);
+                    event = ResponseEvent::Remove(names)
+                }
+                Some(false) => self.confirm_remove = None,
+                None => {}
+            }
+        }
+        self.broadcast_window(ui.ctx());
+        self.toasts.show(ui.ctx());
         event
     }
 }
@@ -335,6 +1412,9 @@ impl egui_dock::TabViewer for ListView {
         let context = self.views.iter_mut().find(|v| &v.name == tab);
         if let Some(context) = context {
             context.view.ui(ui);
+            if let Some(request) = context.view.take_request() {
+                self.handle_request(tab, request);
+            }
         }
     }
 
@@ -350,3 +1430,177 @@ impl egui_dock::TabViewer for ListView {
         !self.selected.contains(_tab)
     }
 }
+
+#[test]
+fn test_load_legacy_save_file() {
+    let legacy = r#"{"chat":{"a":{"model":"gpt-3.5-turbo","messages":[]}},"complete":{"b":{"model":"text-davinci-003","prompt":""}}}"#;
+    let file = SavedFile::from_reader(legacy.as_bytes()).unwrap();
+    assert_eq!(file.version, 1);
+    let names = file
+        .conversations
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a", "b"]);
+    assert!(file.conversations.iter().all(|c| c.markdown.is_none()));
+
+    let saved = serde_json::to_string(&SavedFile {
+        version: SavedFile::VERSION,
+        conversations: file.conversations.into_iter().rev().collect(),
+    })
+    .unwrap();
+    let file = SavedFile::from_reader(saved.as_bytes()).unwrap();
+    assert_eq!(file.version, SavedFile::VERSION);
+    assert!(matches!(file.conversations[0].data, SavedData::Complete(_)));
+    assert_eq!(file.conversations[1].name, "a");
+}
+
+#[test]
+fn test_is_untitled() {
+    assert!(is_untitled("chat_1"));
+    assert!(is_untitled("complete_12"));
+    assert!(!is_untitled("chat_"));
+    assert!(!is_untitled("chat_1_fork"));
+    assert!(!is_untitled("Rust lifetimes explained"));
+}
+
+#[test]
+fn test_round_trip() {
+    let saved = r#"{"version":2,"conversations":[
+        {"name":"a","pinned":true,"folder":"work","headers":[["X-Api-Version","2"]],"type":"chat",
+            "data":{"model":"gpt-3.5-turbo","messages":[],"user":"user-1","seed":42}},
+        {"name":"b","type":"complete","data":{"model":"text-davinci-003","prompt":""}}]}"#;
+    let file = SavedFile::from_reader(saved.as_bytes()).unwrap();
+    let saved = serde_json::to_string(&file).unwrap();
+    // the fields left unset keep the format of older files
+    for field in ["pinned", "folder", "headers", "user", "seed"] {
+        let key = format!("\"{}\":", field);
+        assert_eq!(saved.matches(&key).count(), 1, "{}", field);
+    }
+    let file = SavedFile::from_reader(saved.as_bytes()).unwrap();
+    let [a, b] = &file.conversations[..] else {
+        panic!("not two conversations");
+    };
+    assert!(a.pinned);
+    assert_eq!(a.folder.as_deref(), Some("work"));
+    assert_eq!(a.headers, [("X-Api-Version".to_string(), "2".to_string())]);
+    let SavedData::Chat(chat) = &a.data else {
+        panic!("not a chat");
+    };
+    assert_eq!(chat.user.as_deref(), Some("user-1"));
+    assert_eq!(chat.seed, Some(42));
+    assert!(!b.pinned);
+    assert_eq!(b.folder, None);
+    assert!(b.headers.is_empty());
+}
+
+#[test]
+fn test_bulk_remove() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let settings = std::rc::Rc::new(std::cell::RefCell::new(Default::default()));
+    let mut list = ListView::new(settings, Arc::new(MultiClient::new()));
+    for name in ["a", "b", "c", "d"] {
+        list.new_chat(Some(name.to_string())).unwrap();
+    }
+    let names = |list: &ListView| list.names().map(str::to_string).collect::<Vec<_>>();
+    list.remove(&["b".to_string(), "d".to_string()]);
+    assert_eq!(names(&list), ["a", "c"]);
+    // both come back in their places with a single undo
+    list.undo_remove();
+    assert_eq!(names(&list), ["a", "b", "c", "d"]);
+}
+
+#[test]
+fn test_load_corrupt_file() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let dir = std::env::temp_dir().join(format!("chatgpt-rs-corrupt-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("chats.json");
+    // the second conversation lost its messages
+    let saved = r#"{"version":2,"conversations":[{"name":"a","type":"chat","data":{"model":"gpt-3.5-turbo","messages":[]}},{"name":"b","type":"chat","data":{"model":"gpt-3.5-turbo"}}]}"#;
+    std::fs::write(&path, saved).unwrap();
+    let settings = std::rc::Rc::new(std::cell::RefCell::new(Default::default()));
+    let mut list = ListView::new(settings, Arc::new(MultiClient::new()));
+    list.load(&path).unwrap();
+    assert_eq!(list.names().collect::<Vec<_>>(), ["a"]);
+    let backups: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("chats.json.bak.")
+        })
+        .collect();
+    assert_eq!(backups.len(), 1);
+    assert_eq!(std::fs::read_to_string(backups[0].path()).unwrap(), saved);
+    // not JSON at all
+    std::fs::write(&path, &saved[..40]).unwrap();
+    assert!(list.load(&path).is_err());
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_save_dir() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+    let dir = std::env::temp_dir().join(format!("chatgpt-rs-dir-{}", std::process::id()));
+    let settings = std::rc::Rc::new(std::cell::RefCell::new(Default::default()));
+    let mut list = ListView::new(settings, Arc::new(MultiClient::new()));
+    for name in ["a/b", "a_b", "c"] {
+        list.new_chat(Some(name.to_string())).unwrap();
+    }
+    list.new_complete(Some("d".to_string())).unwrap();
+    list.save_dir(&dir).unwrap();
+    let files = |dir: &Path| {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    };
+    assert_eq!(
+        files(&dir),
+        [
+            "a_b (2).chat.json",
+            "a_b.chat.json",
+            "c.chat.json",
+            "d.complete.json",
+            "order.json"
+        ]
+    );
+    // removed conversations lose their file
+    list.remove(&["c".to_string()]);
+    list.save_dir(&dir).unwrap();
+    assert!(!files(&dir).contains(&"c.chat.json".to_string()));
+    // the order of the list is kept, not the one of the file names
+    list.move_view(2, 0);
+    list.save_dir(&dir).unwrap();
+    list.load(&dir).unwrap();
+    assert_eq!(list.names().collect::<Vec<_>>(), ["d", "a/b", "a_b"]);
+    // an unreadable file is neither removed nor saved over
+    std::fs::write(dir.join("e.chat.json"), "{").unwrap();
+    list.load(&dir).unwrap();
+    list.new_chat(Some("e".to_string())).unwrap();
+    list.save_dir(&dir).unwrap();
+    assert_eq!(std::fs::read(dir.join("e.chat.json")).unwrap(), b"{");
+    assert!(files(&dir).contains(&"e (2).chat.json".to_string()));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_next_focus() {
+    let names = ["a", "b", "c"].map(String::from);
+    assert_eq!(next_focus(&names, None, true), Some(0));
+    assert_eq!(next_focus(&names, None, false), Some(2));
+    assert_eq!(next_focus(&names, Some("b"), true), Some(2));
+    assert_eq!(next_focus(&names, Some("c"), true), Some(2));
+    assert_eq!(next_focus(&names, Some("a"), false), Some(0));
+    // removed in the meantime
+    assert_eq!(next_focus(&names, Some("d"), true), Some(0));
+    assert_eq!(next_focus(&[], None, true), None);
+}
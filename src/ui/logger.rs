@@ -1,6 +1,8 @@
 use std::{
     collections::{BTreeMap, VecDeque},
-    sync::RwLock,
+    io::Write,
+    path::Path,
+    sync::{atomic, Arc, RwLock, TryLockError},
 };
 
 use eframe::{
@@ -9,8 +11,8 @@ use eframe::{
 };
 use regex::RegexBuilder;
 use serde::{Deserialize, Serialize};
-use strum::{Display, EnumCount, EnumIter, IntoEnumIterator};
-use tracing::metadata;
+use strum::{Display, EnumCount, EnumIter, FromRepr, IntoEnumIterator};
+use tracing::{metadata, subscriber::Interest};
 use tracing_subscriber::{
     registry::{self, LookupSpan},
     Layer,
@@ -21,10 +23,16 @@ use super::View;
 pub static LOG: RwLock<VecDeque<LogOutput>> = RwLock::new(VecDeque::new());
 
 pub struct Logger {
-    max_level: metadata::Level,
+    /// the least severe [`Level`] captured, shared with [`LoggerUi`]
+    max_level: Arc<atomic::AtomicU8>,
+    /// maximum number of entries kept in [`LOG`]
+    capacity: usize,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, EnumIter, EnumCount, Display)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Serialize, Deserialize, EnumIter, EnumCount, Display, FromRepr,
+)]
+#[repr(u8)]
 pub enum Level {
     Trace = 0,
     Debug = 1,
@@ -124,12 +132,16 @@ where
     S: tracing::Subscriber,
     S: for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
 {
+    fn register_callsite(&self, _metadata: &'static tracing::Metadata<'static>) -> Interest {
+        // the level can change at runtime, so `enabled` must be asked every time
+        Interest::sometimes()
+    }
     fn enabled(
         &self,
         metadata: &tracing::Metadata<'_>,
         _ctx: tracing_subscriber::layer::Context<'_, S>,
     ) -> bool {
-        metadata.level() <= &self.max_level
+        self.is_captured(metadata.level())
     }
     fn on_new_span(
         &self,
@@ -164,6 +176,9 @@ where
         values.record(&mut visitor);
     }
     fn on_event(&self, event: &tracing::Event<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if !self.is_captured(event.metadata().level()) {
+            return;
+        }
         let spans = ctx
             .event_scope(event)
             .map(|scope| scope.map(LogOutput::from).collect());
@@ -179,12 +194,30 @@ where
             fields,
             spans,
         };
-        LOG.write().unwrap().push_front(output);
+        let mut log = LOG.write().unwrap();
+        log.push_front(output);
+        log.truncate(self.capacity);
     }
 }
 impl Logger {
+    pub const DEFAULT_CAPACITY: usize = 5000;
+
     pub fn new(max_level: metadata::Level) -> Self {
-        Self { max_level }
+        Self {
+            max_level: Arc::new(atomic::AtomicU8::new(Level::from(&max_level) as u8)),
+            capacity: Self::DEFAULT_CAPACITY,
+        }
+    }
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+    /// Handle to change the captured level at runtime.
+    pub fn max_level(&self) -> Arc<atomic::AtomicU8> {
+        self.max_level.clone()
+    }
+    fn is_captured(&self, level: &metadata::Level) -> bool {
+        Level::from(level) as u8 >= self.max_level.load(atomic::Ordering::Relaxed)
     }
 }
 pub struct LoggerUi {
@@ -198,10 +231,12 @@ pub struct LoggerUi {
     copy_text: String,
     max_log_length: usize,
     logs_displayed: usize,
+    max_level: Arc<atomic::AtomicU8>,
+    export_pretty: bool,
 }
 
-impl Default for LoggerUi {
-    fn default() -> Self {
+impl LoggerUi {
+    pub fn new(max_level: Arc<atomic::AtomicU8>) -> Self {
         Self {
             log_levels: [false, true, true, true, true],
             search_term: String::new(),
@@ -213,11 +248,56 @@ impl Default for LoggerUi {
             copy_text: String::new(),
             max_log_length: 20,
             logs_displayed: 0,
+            max_level,
+            export_pretty: false,
         }
     }
 }
 
 impl LoggerUi {
+    /// Level, span and target filters, the search term is matched separately
+    /// against the serialized output.
+    fn filter(&self, log: &LogOutput) -> bool {
+        if !self.log_levels[log.level as usize] {
+            return false;
+        }
+        let span_matched = if let Some(spans) = &log.spans {
+            spans
+                .iter()
+                .any(|span| span.name.contains(&self.span_filter))
+        } else {
+            self.span_filter.is_empty()
+        };
+        span_matched && log.target.contains(&self.target_filter)
+    }
+    /// Write every filtered log, oldest first. `.jsonl` files get one
+    /// [`LogOutput`] per line, other files are prefixed with the level.
+    fn export(&self, logs: &VecDeque<LogOutput>, path: &Path) -> Result<(), anyhow::Error> {
+        let json_lines = path.extension().is_some_and(|ext| ext == "jsonl");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for log in logs.iter().rev().filter(|log| self.filter(log)) {
+            let content = if self.export_pretty {
+                serde_json::to_string_pretty(log)?
+            } else {
+                serde_json::to_string(log)?
+            };
+            if !self.search_term.is_empty() && !self.match_string(&content) {
+                continue;
+            }
+            if json_lines {
+                writeln!(file, "{}", content)?;
+            } else {
+                writeln!(
+                    file,
+                    "[{}] {}",
+                    log.level.to_string().to_uppercase(),
+                    content
+                )?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
     fn match_string(&self, string: &str) -> bool {
         if self.search_use_regex {
             if let Some(matcher) = &self.regex {
@@ -305,6 +385,17 @@ impl super::View for LoggerUi {
                         .clamp_range(1..=1000),
                 );
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
+                    let max_level = self.max_level.load(atomic::Ordering::Relaxed);
+                    let mut selected = Level::from_repr(max_level).unwrap_or(Level::Trace);
+                    egui::ComboBox::from_id_source("capture_level")
+                        .selected_text(format!("Capture: {}", selected))
+                        .show_ui(ui, |ui| {
+                            for level in Level::iter() {
+                                ui.selectable_value(&mut selected, level, level.to_string());
+                            }
+                        });
+                    self.max_level
+                        .store(selected as u8, atomic::Ordering::Relaxed);
                     ui.menu_button("Log Levels", |ui| {
                         for level in Level::iter() {
                             if ui
@@ -326,6 +417,7 @@ impl super::View for LoggerUi {
         });
         let logs = LOG.read().unwrap();
         let logs_len = logs.len();
+        let mut exported = None;
         egui::TopBottomPanel::bottom("log_bottom").show_inside(ui, |ui| {
             ui.horizontal(|ui| {
                 ui.label(format!("Displayed: {}", self.logs_displayed));
@@ -334,24 +426,28 @@ impl super::View for LoggerUi {
                     if ui.button("Copy").clicked() {
                         ui.output_mut(|o| o.copied_text = self.copy_text.to_string());
                     }
+                    if ui
+                        .button("Export")
+                        .on_hover_text("Export the filtered logs to a file")
+                        .clicked()
+                    {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .set_file_name("log.jsonl")
+                            .add_filter("JSON Lines", &["jsonl"])
+                            .add_filter("Text", &["txt"])
+                            .save_file()
+                        {
+                            exported = Some((self.export(&logs, &path), path));
+                        }
+                    }
+                    ui.checkbox(&mut self.export_pretty, "Pretty");
                 });
             });
         });
         egui::CentralPanel::default().show_inside(ui, |ui| {
-            let log_levels = self.log_levels;
             let logs_iter = logs
                 .iter()
-                .filter(|log| log_levels[log.level as usize])
-                .filter(|log| {
-                    if let Some(spans) = &log.spans {
-                        spans
-                            .iter()
-                            .any(|span| span.name.contains(&self.span_filter))
-                    } else {
-                        self.span_filter.is_empty()
-                    }
-                })
-                .filter(|log| log.target.contains(&self.target_filter))
+                .filter(|log| self.filter(log))
                 .take(self.max_log_length);
 
             let mut logs_displayed_content = logs_iter.collect::<Vec<_>>();
@@ -400,5 +496,48 @@ impl super::View for LoggerUi {
 
         // has to be cleared after every frame
         self.copy_text.clear();
+
+        // logging while `LOG` is still read locked would deadlock
+        drop(logs);
+        match exported {
+            Some((Ok(()), path)) => tracing::info!("logs exported to {}", path.display()),
+            Some((Err(err), _)) => tracing::error!("failed to export logs: {}", err),
+            None => {}
+        }
     }
 }
+
+/// The latest `count` logs of [`LOG`] as lines, oldest first, for crash reports.
+/// Never blocks, and still reads the logs when a panic poisoned the lock.
+pub fn recent_lines(count: usize) -> Vec<String> {
+    let log = match LOG.try_read() {
+        Ok(log) => log,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        // held by the thread that panicked
+        Err(TryLockError::WouldBlock) => return vec!["(the logs are locked)".to_string()],
+    };
+    log.iter()
+        .take(count)
+        .rev()
+        .map(|log| {
+            let content = serde_json::to_string(log).unwrap_or_default();
+            format!("[{}] {}", log.level.to_string().to_uppercase(), content)
+        })
+        .collect()
+}
+
+#[test]
+fn test_log_capacity() {
+    use tracing_subscriber::prelude::*;
+
+    let subscriber =
+        tracing_subscriber::registry().with(Logger::new(metadata::Level::TRACE).with_capacity(10));
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..100 {
+            tracing::info!("message {}", i);
+        }
+    });
+    let log = LOG.read().unwrap();
+    assert_eq!(log.len(), 10);
+    assert_eq!(log[0].fields["message"], "message 99");
+}
@@ -1,4 +1,6 @@
 mod chat_window;
+mod command_palette;
+mod compare_window;
 mod complete_window;
 mod components;
 mod easy_mark;
@@ -6,8 +8,18 @@ mod list_view;
 pub mod logger;
 mod model_table;
 mod parameter_control;
+mod settings;
+mod template;
 
-use self::{list_view::ListView, logger::LoggerUi};
+use self::{
+    command_palette::{Command, CommandPalette},
+    compare_window::CompareWindow,
+    list_view::ListView,
+    logger::LoggerUi,
+    model_table::ModelTable,
+    settings::{Settings, SettingsWindow, ThemeMode},
+};
+use crate::client::{MultiClientBuilder, ProxyConfig};
 use eframe::{
     egui,
     epaint::{FontFamily, FontId},
@@ -18,8 +30,16 @@ use font_kit::{
     properties::{Properties, Weight},
     source::SystemSource,
 };
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{atomic, Arc},
+};
 use strum::{Display, EnumIter};
 
+/// How long a removed conversation or cleared chat can be restored.
+const UNDO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, Display)]
 #[strum(serialize_all = "snake_case")]
 pub enum ModelType {
@@ -32,6 +52,16 @@ pub struct ChatApp {
     list_view: ListView,
     widgets: Vec<(Box<dyn Window<Response = ()>>, bool)>,
     tree: egui_dock::Tree<String>,
+    settings: settings::SharedSettings,
+    system_theme: Option<eframe::Theme>,
+    /// asks for an API key until one is entered or the prompt is dismissed
+    show_api_key_prompt: bool,
+    api_key_input: String,
+    command_palette: CommandPalette,
+    compare: CompareWindow,
+    show_compare: bool,
+    /// when the conversations were last saved automatically
+    last_auto_save: std::time::Instant,
 
     expand_list: bool,
 }
@@ -46,27 +76,340 @@ impl ChatApp {
             false
         }
     };
-    pub fn new(cc: &eframe::CreationContext) -> Self {
-        setup_fonts(&cc.egui_ctx);
+    pub fn new(cc: &eframe::CreationContext, log_level: Arc<atomic::AtomicU8>) -> Self {
+        let settings = Settings::load(cc.storage);
+        let system_theme = cc.integration_info.system_theme;
+        cc.egui_ctx.set_visuals(settings.visuals(system_theme));
+        setup_text_styles(&cc.egui_ctx, settings.font_scale);
+        setup_fonts(
+            &cc.egui_ctx,
+            &settings.proportional_font,
+            &settings.monospace_font,
+        );
+        // one client for every conversation, so they share the connection pool
+        let client = Arc::new(
+            MultiClientBuilder::new()
+                .pool_max_idle_per_host(settings.pool_max_idle_per_host)
+                .keep_alive(
+                    (settings.keep_alive > 0)
+                        .then(|| std::time::Duration::from_secs(settings.keep_alive)),
+                )
+                .http2_only(settings.http2_only)
+                .build(),
+        );
+        client.set_concurrency(settings.max_concurrent_requests);
+        client.cache().set_enabled(settings.cache_responses);
+        client.set_stall_timeout(settings.stall_timeout);
+        client.set_idempotency_keys(settings.idempotency_keys);
+        let mut proxy_error = None;
+        if settings.proxy != ProxyConfig::System {
+            if let Err(e) = client.set_proxy(&settings.proxy) {
+                tracing::error!("{}", e);
+                proxy_error = Some(e.to_string());
+            }
+        }
         let mut widgets = Vec::new();
-        let mut list_view = ListView::default();
+        let settings = Rc::new(RefCell::new(settings));
+        let mut list_view = ListView::new(settings.clone(), client.clone());
 
-        list_view.load("./chats.json").ok();
+        list_view.load_default().ok();
+        if let Some(drafts) = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, Self::DRAFTS_KEY))
+        {
+            list_view.set_drafts(drafts);
+        }
+        let tree = Self::load_tree(cc.storage, &mut list_view);
+        let show_api_key_prompt = list_view.api_key().trim().is_empty();
         widgets.push((
-            Box::new(LoggerUi::default()) as Box<dyn Window<Response = ()>>,
+            Box::new(LoggerUi::new(log_level)) as Box<dyn Window<Response = ()>>,
             Self::DEBUG,
         ));
+        widgets.push((
+            Box::new(SettingsWindow::new(
+                settings.clone(),
+                client.clone(),
+                system_theme,
+                proxy_error,
+            )),
+            false,
+        ));
+        let compare = CompareWindow::new(settings.clone());
         Self {
             list_view,
             widgets,
             expand_list: true,
-            tree: egui_dock::Tree::default(),
+            tree,
+            settings,
+            system_theme,
+            show_api_key_prompt,
+            api_key_input: String::new(),
+            command_palette: CommandPalette::default(),
+            compare,
+            show_compare: false,
+            last_auto_save: std::time::Instant::now(),
+        }
+    }
+}
+
+impl ChatApp {
+    const TREE_KEY: &'static str = "dock_tree";
+    const DRAFTS_KEY: &'static str = "drafts";
+
+    /// Save the conversations every [`Settings::auto_save_interval`] seconds.
+    fn auto_save(&mut self, ctx: &egui::Context) {
+        let interval = self.settings.borrow().auto_save_interval;
+        if interval == 0 {
+            return;
+        }
+        let interval = std::time::Duration::from_secs(interval);
+        let elapsed = self.last_auto_save.elapsed();
+        if elapsed < interval {
+            ctx.request_repaint_after(interval - elapsed);
+            return;
+        }
+        self.last_auto_save = std::time::Instant::now();
+        if let Err(e) = self.list_view.save_default() {
+            tracing::error!("auto-save failed: {}", e);
+        }
+        ctx.request_repaint_after(interval);
+    }
+    /// The saved dock layout, without the tabs of conversations that no longer exist.
+    fn load_tree(
+        storage: Option<&dyn eframe::Storage>,
+        list_view: &mut ListView,
+    ) -> egui_dock::Tree<String> {
+        let mut tree: egui_dock::Tree<String> = storage
+            .and_then(|storage| eframe::get_value(storage, Self::TREE_KEY))
+            .unwrap_or_default();
+        let missing: Vec<String> = tree
+            .tabs()
+            .filter(|tab| !list_view.reopen(tab))
+            .cloned()
+            .collect();
+        for tab in missing {
+            if let Some(index) = tree.find_tab(&tab) {
+                tree.remove_tab(index);
+            }
+        }
+        tree
+    }
+}
+
+impl ChatApp {
+    const NEW_CHAT: egui::KeyboardShortcut =
+        egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::N);
+    const PRESENT: egui::KeyboardShortcut =
+        egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::F11);
+    const CLOSE_TAB: egui::KeyboardShortcut =
+        egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::W);
+    const NEXT_TAB: egui::KeyboardShortcut =
+        egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Tab);
+    const PREV_TAB: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+        egui::Modifiers {
+            shift: true,
+            ..egui::Modifiers::COMMAND
+        },
+        egui::Key::Tab,
+    );
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.input_mut(|i| i.consume_shortcut(&CommandPalette::SHORTCUT)) {
+            self.command_palette.toggle();
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&Self::PRESENT)) {
+            if let Some((_, tab)) = self.tree.find_active_focused() {
+                let tab = tab.clone();
+                self.list_view.toggle_presentation(&tab);
+            }
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&Self::NEW_CHAT)) {
+            if let Err(e) = self.list_view.new_view(None) {
+                tracing::error!("{}", e);
+            }
+        }
+        // the remaining shortcuts would steal keys from a focused text edit
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&Self::CLOSE_TAB)) {
+            if let Some((_, tab)) = self.tree.find_active_focused() {
+                let tab = tab.clone();
+                if let Some(index) = self.tree.find_tab(&tab) {
+                    self.tree.remove_tab(index);
+                }
+                self.list_view.close(&tab);
+            }
+        }
+        let step = if ctx.input_mut(|i| i.consume_shortcut(&Self::PREV_TAB)) {
+            -1
+        } else if ctx.input_mut(|i| i.consume_shortcut(&Self::NEXT_TAB)) {
+            1
+        } else {
+            return;
+        };
+        let Some(node) = self.tree.focused_leaf() else {
+            return;
+        };
+        if let egui_dock::Node::Leaf { tabs, active, .. } = &self.tree[node] {
+            if tabs.is_empty() {
+                return;
+            }
+            let len = tabs.len() as isize;
+            let next = (active.0 as isize + step).rem_euclid(len) as usize;
+            self.tree.set_active_tab(node, next.into());
+        }
+    }
+}
+
+impl ChatApp {
+    /// Every command of the palette, in the order shown for an empty query.
+    fn commands(&mut self) -> Vec<(String, Command)> {
+        let mut commands = vec![
+            ("New Chat".to_string(), Command::NewChat),
+            ("New Complete".to_string(), Command::NewComplete),
+            ("Save".to_string(), Command::Save),
+            ("Load".to_string(), Command::Load),
+            ("Toggle List".to_string(), Command::ToggleList),
+        ];
+        for (index, (view, _)) in self.widgets.iter().enumerate() {
+            commands.push((
+                format!("Toggle {}", view.name()),
+                Command::ToggleWidget(index),
+            ));
+        }
+        if let Some((_, tab)) = self.tree.find_active_focused() {
+            if self.list_view.is_chat(tab) {
+                for model in ModelTable::CHAT_MODELS {
+                    commands.push((
+                        format!("Set model {}", model),
+                        Command::SetModel(tab.clone(), model.to_string()),
+                    ));
+                }
+            }
+        }
+        for name in self.list_view.names() {
+            commands.push((
+                format!("Switch to {}", name),
+                Command::SwitchTo(name.to_string()),
+            ));
+        }
+        commands
+    }
+    fn run_command(&mut self, command: Command) {
+        let res = match command {
+            Command::NewChat => self.list_view.new_chat(None),
+            Command::NewComplete => self.list_view.new_complete(None),
+            Command::Save => self.list_view.save_default(),
+            Command::Load => self.list_view.load_default(),
+            Command::ToggleList => {
+                self.expand_list = !self.expand_list;
+                Ok(())
+            }
+            Command::ToggleWidget(index) => {
+                if let Some((_, show)) = self.widgets.get_mut(index) {
+                    *show = !*show;
+                }
+                Ok(())
+            }
+            Command::SwitchTo(name) => {
+                if self.list_view.is_detached(&name) {
+                    // already open in its own window
+                } else if let Some(index) = self.tree.find_tab(&name) {
+                    self.tree.set_active_tab(index.0, index.1);
+                    self.tree.set_focused_node(index.0);
+                } else if self.list_view.reopen(&name) {
+                    self.tree.push_to_focused_leaf(name);
+                }
+                Ok(())
+            }
+            Command::SetModel(name, model) => {
+                self.list_view.set_model(&name, model);
+                Ok(())
+            }
+        };
+        if let Err(e) = res {
+            tracing::error!("{}", e);
+        }
+    }
+    fn handle_event(&mut self, event: WindowEvent) {
+        match event {
+            WindowEvent::Opened(name) => self.tree.push_to_focused_leaf(name),
+            WindowEvent::Renamed(from, to) => {
+                if let Some((node, tab)) = self.tree.find_tab(&from) {
+                    if let egui_dock::Node::Leaf { tabs, .. } = &mut self.tree[node] {
+                        tabs[tab.0] = to.clone();
+                    }
+                }
+                self.compare.rename(&from, &to);
+            }
+            WindowEvent::Compare(left, right) => {
+                self.compare.compare(left, right);
+                self.show_compare = true;
+            }
+        }
+    }
+}
+
+impl ChatApp {
+    /// Modal shown on first run when neither `OPENAI_API_KEY` nor a saved key exists.
+    fn api_key_prompt(&mut self, ctx: &egui::Context) {
+        if !self.show_api_key_prompt {
+            return;
+        }
+        components::backdrop(ctx, "api_key_backdrop");
+        let response = egui::Window::new("OpenAI API key")
+            .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("No API key was found. Paste one to start chatting.");
+                ui.hyperlink("https://platform.openai.com/account/api-keys");
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut self.api_key_input)
+                        .password(true)
+                        .hint_text("sk-..."),
+                );
+                let api_key = self.api_key_input.trim().to_string();
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                ui.horizontal(|ui| {
+                    let save = ui.add_enabled(!api_key.is_empty(), egui::Button::new("Save"));
+                    if save.clicked() || (submitted && !api_key.is_empty()) {
+                        self.settings.borrow_mut().api_key = api_key.clone();
+                        self.list_view.set_api_key(api_key);
+                        self.api_key_input.clear();
+                        self.show_api_key_prompt = false;
+                    }
+                    if ui.button("Later").clicked() {
+                        self.show_api_key_prompt = false;
+                    }
+                });
+            });
+        if let Some(response) = response {
+            ctx.move_to_top(response.response.layer_id);
         }
     }
 }
 
 impl eframe::App for ChatApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.settings.borrow().save(storage);
+        eframe::set_value(storage, Self::TREE_KEY, &self.tree);
+        eframe::set_value(storage, Self::DRAFTS_KEY, &self.list_view.drafts());
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_shortcuts(ctx);
+        self.auto_save(ctx);
+        self.api_key_prompt(ctx);
+        if self.command_palette.is_open() {
+            let commands = self.commands();
+            if let Some(command) = self.command_palette.show(ctx, commands) {
+                self.run_command(command);
+            }
+        }
+
         let open = ctx.input(|i| i.raw.hovered_files.first().map(|f| f.clone()));
 
         select_popup(ctx, &open.is_some(), "Select");
@@ -81,22 +424,30 @@ impl eframe::App for ChatApp {
                 None
             });
             if let Some(path) = path {
-                if let Err(e) = self.list_view.load(&path) {
-                    tracing::error!("{}", e);
+                let focused = self.tree.find_active_focused().map(|(_, tab)| tab.clone());
+                match focused {
+                    // other files are attached to the conversation
+                    Some(tab) if path.extension() != Some("json".as_ref()) => {
+                        self.list_view.attach_file(&tab, &path)
+                    }
+                    _ => {
+                        if let Err(e) = self.list_view.load(&path) {
+                            tracing::error!("{}", e);
+                        }
+                    }
                 }
-            } else {
             }
 
             ui.horizontal(|ui| {
                 ui.menu_button("File", |ui| {
                     ui.button("Load").clicked().then(|| {
-                        if let Err(e) = self.list_view.load("./chats.json") {
+                        if let Err(e) = self.list_view.load_default() {
                             tracing::error!("{}", e);
                         }
                         ui.close_menu();
                     });
                     ui.button("Save").clicked().then(|| {
-                        if let Err(e) = self.list_view.save("./chats.json") {
+                        if let Err(e) = self.list_view.save_default() {
                             tracing::error!("{}", e);
                         }
                         ui.close_menu();
@@ -108,18 +459,54 @@ impl eframe::App for ChatApp {
 
                 ui.separator();
 
+                let mut detach = None;
                 if let Some((_, tab)) = self.tree.find_active_focused() {
                     self.list_view.action(tab, ui);
+                    ui.button("Detach")
+                        .on_hover_text("Open in its own window, close the window to dock it again")
+                        .clicked()
+                        .then(|| detach = Some(tab.clone()));
+                }
+                if let Some(tab) = detach {
+                    if let Some(index) = self.tree.find_tab(&tab) {
+                        self.tree.remove_tab(index);
+                    }
+                    self.list_view.detach(&tab);
                 }
 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let dark_mode = ui.ctx().style().visuals.dark_mode;
                     egui::global_dark_light_mode_switch(ui);
+                    if ui.ctx().style().visuals.dark_mode != dark_mode {
+                        let mut settings = self.settings.borrow_mut();
+                        settings.theme = if dark_mode {
+                            ThemeMode::Light
+                        } else {
+                            ThemeMode::Dark
+                        };
+                        ui.ctx().set_visuals(settings.visuals(self.system_theme));
+                    }
                     ui.separator();
+                    let generating = self.list_view.generating();
+                    if generating > 0
+                        && ui
+                            .button(format!("Stop all ({})", generating))
+                            .on_hover_text("Abort every running generation")
+                            .clicked()
+                    {
+                        self.list_view.stop_all();
+                    }
                     for (view, show) in self.widgets.iter_mut() {
                         ui.selectable_label(*show, view.name()).clicked().then(|| {
                             *show = !*show;
                         });
                     }
+                    if ui
+                        .selectable_label(self.show_compare, self.compare.name())
+                        .clicked()
+                    {
+                        self.show_compare = !self.show_compare;
+                    }
                 });
             });
         });
@@ -127,22 +514,34 @@ impl eframe::App for ChatApp {
         self.widgets
             .iter_mut()
             .for_each(|(view, show)| view.show(ctx, show));
+        if self.show_compare {
+            self.compare.set_chats(self.list_view.chats());
+            self.compare.show(ctx, &mut self.show_compare);
+        }
+        for name in self.list_view.show_detached(ctx) {
+            self.tree.push_to_focused_leaf(name);
+        }
 
         egui::SidePanel::left("left_chat_panel").show_animated(ctx, self.expand_list, |ui| {
             match self.list_view.ui(ui) {
                 list_view::ResponseEvent::Select(label) => {
-                    if let Some(index) = self.tree.find_tab(&label) {
+                    if self.list_view.is_detached(&label) {
+                        // already open in its own window
+                    } else if let Some(index) = self.tree.find_tab(&label) {
                         self.tree.set_active_tab(index.0, index.1)
                     } else {
                         self.tree.push_to_focused_leaf(label)
                     }
                 }
-                list_view::ResponseEvent::Remove(label) => {
-                    if let Some(index) = self.tree.find_tab(&label) {
-                        self.tree.remove_tab(index);
+                list_view::ResponseEvent::Remove(labels) => {
+                    for label in labels {
+                        if let Some(index) = self.tree.find_tab(&label) {
+                            self.tree.remove_tab(index);
+                        }
                     }
                 }
                 list_view::ResponseEvent::Rename(from, to) => {
+                    self.compare.rename(&from, &to);
                     if let Some(index) = self.tree.find_tab(&from) {
                         self.tree.remove_tab(index);
                         self.tree.push_to_first_leaf(to.clone());
@@ -163,6 +562,9 @@ impl eframe::App for ChatApp {
             egui_dock::DockArea::new(&mut self.tree)
                 .style(style)
                 .show_inside(ui, &mut self.list_view);
+            for event in self.list_view.take_events() {
+                self.handle_event(event);
+            }
         });
     }
 }
@@ -194,9 +596,59 @@ fn select_popup(ctx: &egui::Context, open: &bool, text: impl Into<egui::WidgetTe
         });
 }
 
+/// Raised by the [`ListView`] for what only the app can do, such as changing the dock.
+pub enum WindowEvent {
+    /// A conversation opened by a tab, to be pushed to the dock
+    Opened(String),
+    /// A conversation renamed by its tab, from and to
+    Renamed(String, String),
+    /// Two chats to show side by side, left and right
+    Compare(String, String),
+}
+
+/// Asked of the [`ListView`] by a tab, handled once the tab is drawn.
+pub enum TabRequest {
+    /// Open a new conversation named after the requesting tab
+    Fork(list_view::APIImpl),
+    /// Rename the requesting tab, if it still has a generated name
+    Rename(String),
+    /// Open a new chat starting with this user message
+    NewChat(String),
+}
+
 pub trait TabWindow: Window {
     fn set_name(&mut self, name: String);
+    fn markdown(&self) -> bool;
+    fn set_markdown(&mut self, enabled: bool);
+    /// Whether long lines of code blocks wrap, `None` when there are none to show.
+    fn wrap_code(&self) -> Option<bool> {
+        None
+    }
+    fn set_wrap_code(&mut self, _wrap: bool) {}
+    /// Whether a reply is being generated or waits in the queue.
+    fn is_generating(&self) -> bool;
+    /// Stop the generation, keeping what was streamed so far.
+    fn abort(&self);
+    /// Show only the messages, without any control.
+    fn toggle_presentation(&mut self) {}
+    /// The input typed but not sent yet.
+    fn draft(&self) -> Option<&str> {
+        None
+    }
+    fn set_draft(&mut self, _draft: String) {}
     fn actions(&mut self, _ui: &mut egui::Ui) {}
+    fn take_request(&mut self) -> Option<TabRequest> {
+        None
+    }
+    /// Insert the text of a dropped file, `false` when files are not supported.
+    fn attach_file(&mut self, _path: &std::path::Path) -> bool {
+        false
+    }
+    /// Send `question` as if it was typed, `false` when it cannot be asked now,
+    /// e.g. while a reply is generated.
+    fn ask(&mut self, _question: String) -> bool {
+        false
+    }
 }
 
 pub trait Window: View {
@@ -209,102 +661,110 @@ pub trait View {
     fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response;
 }
 
-fn setup_fonts(ctx: &egui::Context) {
+/// Set up the text styles, scaling every size by `scale` while keeping the
+/// ratios between body text and headings.
+fn setup_text_styles(ctx: &egui::Context, scale: f32) {
     let mut style = (*ctx.style()).clone();
-    style.text_styles.insert(
+    let mut text_styles = egui::Style::default().text_styles;
+    text_styles.insert(
         egui::TextStyle::Name("Heading1".into()),
         FontId::new(36.0, FontFamily::Proportional),
     );
-    style.text_styles.insert(
+    text_styles.insert(
         egui::TextStyle::Name("Heading2".into()),
         FontId::new(24.0, FontFamily::Proportional),
     );
-    style.text_styles.insert(
+    text_styles.insert(
         egui::TextStyle::Name("Heading3".into()),
         FontId::new(21.0, FontFamily::Proportional),
     );
-    style.text_styles.insert(
+    text_styles.insert(
         egui::TextStyle::Name("Heading4".into()),
         FontId::new(18.0, FontFamily::Proportional),
     );
-    style.text_styles.insert(
+    text_styles.insert(
         egui::TextStyle::Name("Heading5".into()),
         FontId::new(16.0, FontFamily::Proportional),
     );
-    style.text_styles.insert(
+    text_styles.insert(
         egui::TextStyle::Name("Heading6".into()),
         FontId::new(14.0, FontFamily::Proportional),
     );
+    for font_id in text_styles.values_mut() {
+        font_id.size *= scale;
+    }
+    style.text_styles = text_styles;
     ctx.set_style(style);
+}
 
+/// Load the system fonts, preferring the user configured families and falling back
+/// to the platform defaults when they are empty or can't be loaded.
+fn setup_fonts(ctx: &egui::Context, proportional: &str, monospace: &str) {
     let mut fonts = egui::FontDefinitions::default();
     let source = SystemSource::new();
-    let prop = if let Ok(font) = source.select_best_match(
+
+    if let Some(prop) = select_font(
+        &source,
+        proportional,
         &[
             FamilyName::Title("微软雅黑".to_owned()),
             FamilyName::SansSerif,
         ],
-        Properties::new().weight(Weight::NORMAL),
     ) {
-        let font = match font.load() {
-            Ok(font) => font,
-            Err(err) => {
-                tracing::error!("Failed to load font: {}", err);
-                return;
-            }
-        };
-        tracing::info!("Using font: {:?}", font);
-        let Some(font_data) = font.copy_font_data() else {
-            return;
-        };
-        let data = Box::leak((*font_data).clone().into_boxed_slice());
-        data
-    } else {
-        return;
-    };
-
-    fonts
-        .font_data
-        .insert("prop".to_owned(), egui::FontData::from_static(prop));
-    fonts
-        .families
-        .entry(FontFamily::Proportional)
-        .or_default()
-        .insert(0, "prop".to_owned());
+        fonts
+            .font_data
+            .insert("prop".to_owned(), egui::FontData::from_owned(prop));
+        fonts
+            .families
+            .entry(FontFamily::Proportional)
+            .or_default()
+            .insert(0, "prop".to_owned());
+    }
 
-    let mono = if let Ok(font) = source.select_best_match(
+    if let Some(mono) = select_font(
+        &source,
+        monospace,
         &[
             FamilyName::Title("YaHei Consolas Hybrid".to_owned()),
             FamilyName::Title("Consolas".to_owned()),
             FamilyName::Monospace,
         ],
-        Properties::new().weight(Weight::NORMAL),
     ) {
-        let font = match font.load() {
-            Ok(font) => font,
-            Err(err) => {
-                tracing::error!("Failed to load font: {}", err);
-                return;
-            }
-        };
-        tracing::info!("Using font: {:?}", font);
-        let Some(font_data) = font.copy_font_data() else {
-            return;
-        };
-        let data = Box::leak((*font_data).clone().into_boxed_slice());
-        data
-    } else {
-        return;
-    };
+        fonts
+            .font_data
+            .insert("mono".to_owned(), egui::FontData::from_owned(mono));
+        fonts
+            .families
+            .entry(FontFamily::Monospace)
+            .or_default()
+            .insert(0, "mono".to_owned());
+    }
+    ctx.set_fonts(fonts);
+}
 
-    fonts
-        .font_data
-        .insert("mono".to_owned(), egui::FontData::from_static(mono));
+fn select_font(source: &SystemSource, preferred: &str, fallback: &[FamilyName]) -> Option<Vec<u8>> {
+    if !preferred.is_empty() {
+        match load_font(source, &[FamilyName::Title(preferred.to_owned())]) {
+            Ok(data) => return Some(data),
+            Err(err) => tracing::warn!("Failed to load font {}: {}", preferred, err),
+        }
+    }
+    match load_font(source, fallback) {
+        Ok(data) => Some(data),
+        Err(err) => {
+            tracing::warn!("Failed to load font: {}", err);
+            None
+        }
+    }
+}
 
-    fonts
-        .families
-        .entry(FontFamily::Monospace)
-        .or_default()
-        .insert(0, "mono".to_owned());
-    ctx.set_fonts(fonts);
+fn load_font(source: &SystemSource, families: &[FamilyName]) -> Result<Vec<u8>, anyhow::Error> {
+    let font = source
+        .select_best_match(families, Properties::new().weight(Weight::NORMAL))?
+        .load()?;
+    tracing::info!("Using font: {:?}", font);
+    let font_data = font
+        .copy_font_data()
+        .ok_or_else(|| anyhow::anyhow!("font data is unavailable"))?;
+    Ok((*font_data).clone())
 }
@@ -1,14 +1,18 @@
-use std::sync::atomic;
+use std::sync::{atomic, Arc};
 
 use eframe::egui;
+use tokio::{sync::RwLock, task::block_in_place};
 
-use crate::api::models::ModelsAPI;
+use crate::{api::models::ModelsAPI, client::MultiClient};
 
 use super::ModelType;
 
 pub struct ModelTable {
     pub models: ModelsAPI,
     pub model_type: ModelType,
+    /// the model of the conversation, highlighted in the table
+    pub selected: Option<String>,
+    filter: String,
 }
 
 pub enum ResponseEvent {
@@ -17,11 +21,17 @@ pub enum ResponseEvent {
 }
 
 impl ModelTable {
-    const CHAT_MODELS: [&str; 2] = ["gpt-3.5-turbo", "gpt-3.5-turbo-0301"];
-    pub fn new(model_type: ModelType) -> Self {
+    pub const CHAT_MODELS: [&str; 2] = ["gpt-3.5-turbo", "gpt-3.5-turbo-0301"];
+    pub fn new(
+        model_type: ModelType,
+        api_key: Arc<RwLock<String>>,
+        client: Arc<MultiClient>,
+    ) -> Self {
         Self {
-            models: ModelsAPI::new(std::env::var("OPENAI_API_KEY").unwrap_or_default()),
+            models: ModelsAPI::new(api_key, client),
             model_type,
+            selected: None,
+            filter: String::new(),
         }
     }
 }
@@ -31,7 +41,7 @@ impl super::View for ModelTable {
 
     fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response {
         let mut event = ResponseEvent::None;
-        // let models = block_in_place(|| self.models.models.blocking_read().clone());
+        let models = block_in_place(|| self.models.models.blocking_read().clone());
         let is_ready = self.models.is_ready.load(atomic::Ordering::Relaxed);
         ui.vertical(|ui| {
             ui.heading("Model");
@@ -52,79 +62,68 @@ impl super::View for ModelTable {
                     }
                 });
             }
-            egui::Grid::new("models").striped(true).show(ui, |ui| {
-                ui.label("ID");
-                ui.label("Action");
-                ui.end_row();
-                match self.model_type {
-                    ModelType::Chat => {
-                        for id in Self::CHAT_MODELS {
-                            ui.label(id);
-                            if ui.button("Select").clicked() {
-                                event = ResponseEvent::SelectModel(id.to_string());
-                            }
-                            ui.end_row();
+            let filter = self.filter.trim().to_string();
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.filter)
+                        .hint_text("Filter or model id")
+                        .desired_width(160.),
+                );
+                ui.add_enabled(!filter.is_empty(), egui::Button::new("Use"))
+                    .on_hover_text("Select the typed model id, e.g. a fine-tuned model")
+                    .clicked()
+                    .then(|| event = ResponseEvent::SelectModel(filter.clone()));
+            });
+            let mut rows: Vec<(String, Option<String>)> = match self.model_type {
+                ModelType::Chat => Self::CHAT_MODELS
+                    .iter()
+                    .map(|id| (id.to_string(), None))
+                    .collect(),
+                ModelType::Complete => todo!(),
+                ModelType::Edit => todo!(),
+            };
+            for model in models.iter().flat_map(|models| &models.data) {
+                if !rows.iter().any(|(id, _)| id == &model.id) {
+                    rows.push((model.id.clone(), Some(model.owned_by.clone())));
+                }
+            }
+            // a saved conversation may use a model that is not listed
+            if let Some(selected) = &self.selected {
+                if !rows.iter().any(|(id, _)| id == selected) {
+                    rows.push((selected.clone(), None));
+                }
+            }
+            let filter = filter.to_lowercase();
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("models").striped(true).show(ui, |ui| {
+                    ui.strong("ID");
+                    ui.strong("Owned By");
+                    ui.strong("Action");
+                    ui.end_row();
+                    for (id, owned_by) in rows {
+                        if !id.to_lowercase().contains(&filter) {
+                            continue;
+                        }
+                        let is_selected = self.selected.as_ref() == Some(&id);
+                        let clicked = ui.selectable_label(is_selected, &id).clicked();
+                        ui.label(owned_by.unwrap_or_default());
+                        let clicked = ui
+                            .add_enabled(!is_selected, egui::Button::new("Select"))
+                            .clicked()
+                            || clicked;
+                        if clicked && !is_selected {
+                            event = ResponseEvent::SelectModel(id);
                         }
+                        ui.end_row();
                     }
-
-                    ModelType::Complete => todo!(),
-                    ModelType::Edit => todo!(),
-                }
+                });
             });
-            // if let Some(models) = models {
-            //     let table = egui_extras::TableBuilder::new(ui)
-            //         .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-            //         .striped(true)
-            //         .column(
-            //             egui_extras::Column::auto()
-            //                 .at_least(10.0)
-            //                 .resizable(true)
-            //                 .clip(true),
-            //         )
-            //         .column(
-            //             egui_extras::Column::auto()
-            //                 .at_least(10.0)
-            //                 .resizable(true)
-            //                 .clip(true),
-            //         )
-            //         .column(
-            //             egui_extras::Column::auto()
-            //                 .at_least(10.0)
-            //                 .resizable(true)
-            //                 .clip(true),
-            //         )
-            //         .column(egui_extras::Column::auto());
-            //     table
-            //         .header(20., |mut header| {
-            //             header.col(|ui| {
-            //                 ui.strong("ID");
-            //             });
-            //             header.col(|ui| {
-            //                 ui.strong("Owned By");
-            //             });
-            //             header.col(|ui| {
-            //                 ui.strong("Actions");
-            //             });
-            //         })
-            //         .body(|mut body| {
-            //             for model in models.data {
-            //                 body.row(20., |mut row| {
-            //                     row.col(|ui| {
-            //                         ui.label(&model.id);
-            //                     });
-            //                     row.col(|ui| {
-            //                         ui.label(&model.owned_by);
-            //                     });
-            //                     row.col(|ui| {
-            //                         if ui.button("Select").clicked() {
-            //                             event = ResponseEvent::SelectModel(model);
-            //                         }
-            //                     });
-            //                 })
-            //             }
-            //         });
-            // }
         });
+        if let ResponseEvent::SelectModel(id) = &event {
+            if self.selected.as_ref() == Some(id) {
+                return ResponseEvent::None;
+            }
+        }
         event
     }
 }
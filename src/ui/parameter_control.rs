@@ -1,19 +1,343 @@
+use std::collections::BTreeMap;
+
 use eframe::egui;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::api::{
+    capabilities::capabilities, looks_like_api_key, Parameter, ParameterRange, ParameterValue,
+};
+use crate::client::custom_header;
 
-use crate::api::{Parameter, ParameterRange, ParameterValue};
+const TOP_P_DOCS: &str =
+    "https://platform.openai.com/docs/api-reference/chat/create#chat/create-top_p";
 
 pub enum ResponseEvent {
     None,
 }
 
+/// Sampling settings set at once. Which one is active follows from the values,
+/// so it is kept with the conversation and any other value is "custom".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter)]
+pub enum Preset {
+    Precise,
+    Balanced,
+    Creative,
+}
+
+impl Preset {
+    fn values(self) -> [(&'static str, f32); 4] {
+        let (temperature, presence_penalty, frequency_penalty) = match self {
+            Self::Precise => (0.2, 0., 0.),
+            Self::Balanced => (0.7, 0., 0.),
+            Self::Creative => (1.2, 0.6, 0.3),
+        };
+        [
+            ("temperature", temperature),
+            ("top_p", 1.),
+            ("presence_penalty", presence_penalty),
+            ("frequency_penalty", frequency_penalty),
+        ]
+    }
+    fn apply(self, params: &[Box<dyn Parameter>]) {
+        for (name, value) in self.values() {
+            if let Some(param) = params.iter().find(|p| p.name() == name) {
+                param.set(ParameterValue::Number(value));
+            }
+        }
+    }
+    /// The preset all of `params` are set to, the missing ones are not compared.
+    pub fn active(params: &[Box<dyn Parameter>]) -> Option<Self> {
+        Self::iter().find(|preset| {
+            preset.values().iter().all(|(name, value)| {
+                match params.iter().find(|p| p.name() == *name) {
+                    Some(param) => matches!(
+                        param.get(),
+                        ParameterValue::Number(n) if (n - value).abs() < 1e-3
+                    ),
+                    None => true,
+                }
+            })
+        })
+    }
+}
+
+/// Adds and removes the entries of `logit_bias`.
+#[derive(Default)]
+struct LogitBiasEditor {
+    /// the entry added next
+    token: u32,
+    bias: f32,
+    /// banned by its tokens
+    #[cfg(feature = "tokenizer")]
+    word: String,
+}
+
+impl LogitBiasEditor {
+    #[cfg_attr(not(feature = "tokenizer"), allow(unused_variables))]
+    fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        param: &dyn Parameter,
+        mut logit_bias: BTreeMap<u32, f32>,
+        model: &str,
+    ) {
+        let mut changed = false;
+        ui.label("logit_bias").on_hover_text(
+            "Bias of token ids, from -100 to never generate them to 100 to only generate them",
+        );
+        egui::Grid::new("logit_bias").num_columns(3).show(ui, |ui| {
+            let mut removed = None;
+            for (token, bias) in logit_bias.iter_mut() {
+                ui.monospace(token.to_string());
+                changed |= ui.add(egui::Slider::new(bias, -100.0..=100.0)).changed();
+                if ui.button("ｘ").clicked() {
+                    removed = Some(*token);
+                }
+                ui.end_row();
+            }
+            if let Some(token) = removed {
+                logit_bias.remove(&token);
+                changed = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.token).prefix("token "));
+            ui.add(
+                egui::DragValue::new(&mut self.bias)
+                    .clamp_range(-100.0..=100.0)
+                    .prefix("bias "),
+            );
+            if ui.button("Add").clicked() {
+                logit_bias.insert(self.token, self.bias);
+                changed = true;
+            }
+        });
+        #[cfg(feature = "tokenizer")]
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.word)
+                    .hint_text("word")
+                    .desired_width(100.),
+            );
+            if ui
+                .add_enabled(!self.word.trim().is_empty(), egui::Button::new("Ban"))
+                .on_hover_text(
+                    "Set the tokens of the word to -100, which also bans the other words made of them",
+                )
+                .clicked()
+            {
+                let tokens = crate::api::tokenizer::word_token_ids(model, &self.word);
+                logit_bias.extend(tokens.into_iter().map(|token| (token, -100.)));
+                self.word.clear();
+                changed = true;
+            }
+        });
+        if changed {
+            param.set(ParameterValue::LogitBias(logit_bias));
+        }
+    }
+}
+
+/// Edits the extra request headers, set once every row can be sent.
+#[derive(Default)]
+struct HeadersEditor {
+    /// the rows as typed, taken from the parameter when first shown
+    rows: Option<Vec<(String, String)>>,
+}
+
+impl HeadersEditor {
+    fn ui(&mut self, ui: &mut egui::Ui, param: &dyn Parameter, headers: Vec<(String, String)>) {
+        let rows = self.rows.get_or_insert(headers);
+        let mut changed = false;
+        ui.label("headers")
+            .on_hover_text("Sent with every request of this conversation, e.g. for a gateway");
+        egui::Grid::new("headers").num_columns(3).show(ui, |ui| {
+            let mut removed = None;
+            for (index, (name, value)) in rows.iter_mut().enumerate() {
+                for (text, hint) in [(name, "name"), (value, "value")] {
+                    changed |= ui
+                        .add(
+                            egui::TextEdit::singleline(text)
+                                .hint_text(hint)
+                                .desired_width(100.),
+                        )
+                        .changed();
+                }
+                if ui.button("ｘ").clicked() {
+                    removed = Some(index);
+                }
+                ui.end_row();
+            }
+            if let Some(index) = removed {
+                rows.remove(index);
+                changed = true;
+            }
+        });
+        if ui.button("Add").clicked() {
+            rows.push(Default::default());
+        }
+        // the empty rows are left out, not rejected
+        let rows: Vec<_> = rows
+            .iter()
+            .filter(|(name, value)| !name.trim().is_empty() || !value.trim().is_empty())
+            .cloned()
+            .collect();
+        let errors: Vec<_> = rows
+            .iter()
+            .filter_map(|(name, value)| custom_header(name, value).err())
+            .collect();
+        for error in &errors {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        if changed && errors.is_empty() {
+            param.set(ParameterValue::Headers(rows));
+        }
+    }
+}
+
+/// The API takes no more stop sequences than this.
+const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The stop sequences to send, kept exactly as typed but for the empty ones.
+fn stop_sequences(rows: &[String]) -> Vec<String> {
+    rows.iter()
+        .filter(|stop| !stop.is_empty())
+        .take(MAX_STOP_SEQUENCES)
+        .cloned()
+        .collect()
+}
+
+/// Edits the sequences the completion stops before, newlines included.
+#[derive(Default)]
+struct StopEditor {
+    /// the rows as typed, taken from the parameter when first shown
+    rows: Option<Vec<String>>,
+}
+
+impl StopEditor {
+    fn ui(&mut self, ui: &mut egui::Ui, param: &dyn Parameter, stop: Vec<String>) {
+        let rows = self.rows.get_or_insert(stop);
+        let mut changed = false;
+        ui.label("stop").on_hover_text(format!(
+            "Up to {} sequences the completion stops before, they are not part of it",
+            MAX_STOP_SEQUENCES
+        ));
+        let mut removed = None;
+        for (index, stop) in rows.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                changed |= ui
+                    .add(
+                        egui::TextEdit::multiline(stop)
+                            .hint_text("sequence")
+                            .desired_rows(1)
+                            .desired_width(200.),
+                    )
+                    .changed();
+                if ui.button("ｘ").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            rows.remove(index);
+            changed = true;
+        }
+        if ui
+            .add_enabled(rows.len() < MAX_STOP_SEQUENCES, egui::Button::new("Add"))
+            .clicked()
+        {
+            rows.push(String::new());
+        }
+        if changed {
+            param.set(ParameterValue::StringArray(stop_sequences(rows)));
+        }
+    }
+}
+
+/// The size of the system message, counted again only when it or the model changes.
+#[derive(Default)]
+struct TokenCount {
+    model: String,
+    text: String,
+    tokens: usize,
+    /// counted by the rough estimate, without the tokenizer
+    estimated: bool,
+}
+
+impl TokenCount {
+    fn update(&mut self, model: &str, text: &str) {
+        if self.model == model && self.text == text {
+            return;
+        }
+        self.model = model.to_string();
+        self.text = text.to_string();
+        #[cfg(feature = "tokenizer")]
+        let (tokens, estimated) = (crate::api::tokenizer::count_tokens(model, text), false);
+        #[cfg(not(feature = "tokenizer"))]
+        let (tokens, estimated) = (crate::api::chat::estimate_text_tokens(text), true);
+        self.tokens = tokens;
+        self.estimated = estimated;
+    }
+    fn ui(&mut self, ui: &mut egui::Ui, model: &str, text: &str) {
+        self.update(model, text);
+        let context_length = capabilities(model).context_length;
+        let tokens = if self.estimated {
+            format!("~{}", self.tokens)
+        } else {
+            self.tokens.to_string()
+        };
+        ui.horizontal(|ui| {
+            ui.weak(format!(
+                "{} characters, {} tokens",
+                text.chars().count(),
+                tokens
+            ));
+            // a quarter of the context leaves little room for the conversation
+            if self.tokens * 4 > context_length {
+                ui.colored_label(ui.visuals().warn_fg_color, "⚠")
+                    .on_hover_text(format!(
+                        "{}% of the {} tokens context of {}, \
+                        older messages are dropped sooner",
+                        self.tokens * 100 / context_length,
+                        context_length,
+                        model
+                    ));
+            }
+        });
+    }
+}
+
 #[derive(Default)]
 pub struct ParameterControler {
     params: Vec<Box<dyn Parameter>>,
+    /// hidden, not supported by the current model
+    unsupported: &'static [&'static str],
+    model: String,
+    logit_bias: LogitBiasEditor,
+    headers: HeadersEditor,
+    stop: StopEditor,
+    system_tokens: TokenCount,
 }
 
 impl ParameterControler {
     pub fn new(params: Vec<Box<dyn Parameter>>) -> Self {
-        Self { params }
+        Self {
+            params,
+            ..Default::default()
+        }
+    }
+    /// Hide the parameters `model` does not support, `true` when it changed.
+    pub fn set_model(&mut self, model: &str) -> bool {
+        if self.model == model {
+            return false;
+        }
+        self.model = model.to_string();
+        self.unsupported = capabilities(model).unsupported;
+        true
+    }
+    /// Show `params` instead, with their ranges and last values as they are now.
+    pub fn refresh(&mut self, params: Vec<Box<dyn Parameter>>) {
+        self.params = params;
     }
 }
 
@@ -21,7 +345,33 @@ impl super::View for ParameterControler {
     type Response = ResponseEvent;
     fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response {
         let event = ResponseEvent::None;
-        let params: Vec<_> = self.params.iter().map(|a| (a, a.get())).collect();
+        if self.params.iter().any(|p| p.name() == "temperature") {
+            let active = Preset::active(&self.params);
+            ui.horizontal(|ui| {
+                for preset in Preset::iter() {
+                    let temperature = preset.values()[0].1;
+                    if ui
+                        .selectable_label(active == Some(preset), preset.to_string())
+                        .on_hover_text(format!("temperature {}", temperature))
+                        .clicked()
+                    {
+                        preset.apply(&self.params);
+                    }
+                }
+                // set by moving any of the sliders
+                ui.add_enabled(
+                    false,
+                    egui::SelectableLabel::new(active.is_none(), "Custom"),
+                );
+            });
+            ui.separator();
+        }
+        let params: Vec<_> = self
+            .params
+            .iter()
+            .filter(|a| !self.unsupported.contains(&a.name()))
+            .map(|a| (a, a.get()))
+            .collect();
         egui::Grid::new("grid")
             .num_columns(2)
             .striped(true)
@@ -53,14 +403,47 @@ impl super::View for ParameterControler {
                                     };
                                 } else {
                                     if ui.add(egui::DragValue::new(&mut res).speed(1)).changed() {
-                                        param.set(ParameterValue::OptionalInteger(n.clone()));
+                                        param.set(ParameterValue::OptionalInteger(Some(res)));
+                                    }
+                                }
+                            });
+                            ui.end_row();
+                        }
+                        ParameterValue::OptionalLong(n) => {
+                            let mut res = match n {
+                                Some(n) => {
+                                    if ui.checkbox(&mut true, param.name()).changed() {
+                                        param.set(ParameterValue::OptionalLong(None))
+                                    }
+                                    n.to_string()
+                                }
+                                None => {
+                                    let ParameterValue::Long(d) = param.store() else {
+                                        continue;
+                                    };
+                                    if ui.checkbox(&mut false, param.name()).changed() {
+                                        param.set(ParameterValue::OptionalLong(Some(d)))
+                                    }
+                                    d.to_string()
+                                }
+                            };
+                            // a DragValue goes through f64 and rounds large values
+                            ui.add_enabled_ui(n.is_some(), |ui| {
+                                if ui.text_edit_singleline(&mut res).changed() {
+                                    if let Ok(res) = res.trim().parse() {
+                                        param.set(ParameterValue::OptionalLong(Some(res)));
                                     }
                                 }
                             });
                             ui.end_row();
                         }
                         ParameterValue::Number(mut n) => {
-                            ui.label(param.name());
+                            if param.name() == "top_p" {
+                                // easily mistaken for a temperature
+                                ui.add(doc_link_label("top_p", "top_p", TOP_P_DOCS));
+                            } else {
+                                ui.label(param.name());
+                            }
                             if let Some(ParameterRange::Number(st, ed)) = param.range() {
                                 if ui.add(egui::Slider::new(&mut n, st..=ed)).changed() {
                                     param.set(ParameterValue::Number(n));
@@ -72,6 +455,13 @@ impl super::View for ParameterControler {
                             }
                             ui.end_row();
                         }
+                        ParameterValue::Bool(mut b) => {
+                            ui.label(param.name());
+                            if ui.checkbox(&mut b, "").changed() {
+                                param.set(ParameterValue::Bool(b));
+                            }
+                            ui.end_row();
+                        }
                         ParameterValue::Integer(mut n) => {
                             ui.label(param.name());
                             if let Some(ParameterRange::Integer(st, ed)) = param.range() {
@@ -144,21 +534,57 @@ impl super::View for ParameterControler {
                         if res.is_empty() {
                             param.set(ParameterValue::OptionalString(None));
                         } else {
-                            param.set(ParameterValue::OptionalString(Some(res)));
+                            param.set(ParameterValue::OptionalString(Some(res.clone())));
                         }
                     });
+                    if param.name() == "system_message" {
+                        self.system_tokens.ui(ui, &self.model, &res);
+                    }
                 }
                 ParameterValue::String(s) => {
                     ui.separator();
-                    ui.label(param.name());
+                    ui.horizontal(|ui| {
+                        ui.label(param.name());
+                        if param.name() == "api_key" && !looks_like_api_key(&s) {
+                            ui.colored_label(ui.visuals().warn_fg_color, "⚠")
+                                .on_hover_text(
+                                    "Does not look like an OpenAI key (sk-...), \
+                                    it is still sent for other gateways",
+                                );
+                        }
+                    });
                     let mut res = s;
                     ui.text_edit_singleline(&mut res).changed().then(|| {
                         param.set(ParameterValue::String(res));
                     });
                 }
+                ParameterValue::StringArray(stop) if param.name() == "stop" => {
+                    ui.separator();
+                    self.stop.ui(ui, param.as_ref(), stop);
+                }
+                ParameterValue::LogitBias(logit_bias) => {
+                    ui.separator();
+                    egui::CollapsingHeader::new("Advanced").show(ui, |ui| {
+                        self.logit_bias
+                            .ui(ui, param.as_ref(), logit_bias, &self.model)
+                    });
+                }
+                ParameterValue::Headers(headers) => {
+                    ui.separator();
+                    egui::CollapsingHeader::new("Headers")
+                        .show(ui, |ui| self.headers.ui(ui, param.as_ref(), headers));
+                }
                 _ => {}
             }
         }
+        if !self.unsupported.is_empty() {
+            ui.separator();
+            ui.weak(format!(
+                "Not supported by {}: {}",
+                self.model,
+                self.unsupported.join(", ")
+            ));
+        }
         event
     }
 }
@@ -174,3 +600,47 @@ fn doc_link_label<'a>(title: &'a str, name: &'a str, url: &'a str) -> impl egui:
         })
     }
 }
+
+#[test]
+fn test_preset() {
+    use std::cell::Cell;
+    struct Number(&'static str, Cell<f32>);
+    impl Parameter for Number {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn range(&self) -> Option<ParameterRange> {
+            None
+        }
+        fn default(&self) -> ParameterValue {
+            ParameterValue::Number(0.)
+        }
+        fn store(&self) -> ParameterValue {
+            self.get()
+        }
+        fn set(&self, value: ParameterValue) {
+            if let ParameterValue::Number(n) = value {
+                self.1.set(n);
+            }
+        }
+        fn get(&self) -> ParameterValue {
+            ParameterValue::Number(self.1.get())
+        }
+    }
+    let params: Vec<Box<dyn Parameter>> = vec![
+        Box::new(Number("temperature", Cell::new(0.3))),
+        Box::new(Number("presence_penalty", Cell::new(0.))),
+    ];
+    assert_eq!(Preset::active(&params), None);
+    Preset::Creative.apply(&params);
+    assert_eq!(Preset::active(&params), Some(Preset::Creative));
+    // a slider moved by hand
+    params[0].set(ParameterValue::Number(1.1));
+    assert_eq!(Preset::active(&params), None);
+}
+
+#[test]
+fn test_stop_sequences() {
+    let rows = ["\n\n", "", " END", "\r\n---\r\n", "Q:", "A:"].map(String::from);
+    assert_eq!(stop_sequences(&rows), ["\n\n", " END", "\r\n---\r\n", "Q:"]);
+}
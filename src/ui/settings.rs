@@ -0,0 +1,473 @@
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+
+use eframe::{egui, epaint::Color32};
+use serde::{Deserialize, Serialize};
+
+use super::View;
+use crate::{
+    api::chat::ChatAPI,
+    client::{ConnectionOptions, MultiClient, ProxyConfig},
+};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+pub type SharedSettings = Rc<RefCell<Settings>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, EnumIter, Display)]
+pub enum SendMode {
+    /// Enter sends, Shift+Enter inserts a newline
+    #[default]
+    #[strum(serialize = "Enter to send")]
+    Enter,
+    /// Ctrl+Enter sends, Enter inserts a newline
+    #[strum(serialize = "Ctrl+Enter to send")]
+    CtrlEnter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, EnumIter, Display)]
+pub enum Density {
+    /// Each message in a bordered group under its role
+    #[default]
+    Comfortable,
+    /// The role as a small prefix, without borders and with tighter spacing
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, EnumIter, Display)]
+pub enum ThemeMode {
+    /// Follow the theme of the operating system
+    #[default]
+    #[strum(serialize = "Follow system")]
+    System,
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, EnumIter, Display)]
+pub enum Accent {
+    #[default]
+    Blue,
+    Green,
+    Purple,
+    Orange,
+    Rose,
+}
+
+impl Accent {
+    /// selection background, hyperlink
+    fn colors(&self, dark_mode: bool) -> (Color32, Color32) {
+        match (self, dark_mode) {
+            (Self::Blue, true) => (
+                Color32::from_rgb(0, 92, 128),
+                Color32::from_rgb(90, 170, 255),
+            ),
+            (Self::Blue, false) => (
+                Color32::from_rgb(144, 209, 255),
+                Color32::from_rgb(0, 155, 255),
+            ),
+            (Self::Green, true) => (
+                Color32::from_rgb(30, 110, 60),
+                Color32::from_rgb(110, 210, 130),
+            ),
+            (Self::Green, false) => (
+                Color32::from_rgb(160, 230, 170),
+                Color32::from_rgb(20, 140, 60),
+            ),
+            (Self::Purple, true) => (
+                Color32::from_rgb(95, 60, 140),
+                Color32::from_rgb(180, 140, 255),
+            ),
+            (Self::Purple, false) => (
+                Color32::from_rgb(210, 180, 255),
+                Color32::from_rgb(110, 60, 200),
+            ),
+            (Self::Orange, true) => (
+                Color32::from_rgb(150, 80, 20),
+                Color32::from_rgb(255, 170, 80),
+            ),
+            (Self::Orange, false) => (
+                Color32::from_rgb(255, 200, 140),
+                Color32::from_rgb(200, 100, 0),
+            ),
+            (Self::Rose, true) => (
+                Color32::from_rgb(140, 40, 70),
+                Color32::from_rgb(255, 120, 160),
+            ),
+            (Self::Rose, false) => (
+                Color32::from_rgb(255, 175, 200),
+                Color32::from_rgb(190, 40, 90),
+            ),
+        }
+    }
+}
+
+/// Application settings, persisted through the eframe storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub send_mode: SendMode,
+    /// Multiplier applied to every text style size
+    pub font_scale: f32,
+    /// Preferred proportional font family, empty for the platform default
+    pub proportional_font: String,
+    /// Preferred monospace font family, empty for the platform default
+    pub monospace_font: String,
+    pub theme: ThemeMode,
+    pub accent: Accent,
+    /// Coalesce streamed tokens and show a typing cursor while generating
+    pub smooth_streaming: bool,
+    pub proxy: ProxyConfig,
+    /// Used when `OPENAI_API_KEY` is not set
+    pub api_key: String,
+    /// Ask before clearing or removing a conversation
+    pub confirm_destructive: bool,
+    /// Whether new conversations render markdown
+    pub markdown_by_default: bool,
+    /// How new chat windows show the messages, the last one picked in a window
+    pub density: Density,
+    /// Messages longer than this many lines show a preview, 0 to always show them whole
+    pub collapse_lines: usize,
+    /// Ask the model for a title after the first reply of an untitled conversation
+    pub auto_title: bool,
+    /// Generations running at once, the others wait in a queue
+    pub max_concurrent_requests: usize,
+    /// Replay identical requests at temperature 0 from the disk cache
+    pub cache_responses: bool,
+    /// Seconds without a streamed token before the reply is given up
+    pub stall_timeout: u64,
+    /// Idle connections kept open to the API, applied at startup
+    pub pool_max_idle_per_host: usize,
+    /// Seconds between keep-alive probes, 0 to go without, applied at startup
+    pub keep_alive: u64,
+    /// Talk HTTP/2 to the API without negotiating it, applied at startup
+    pub http2_only: bool,
+    /// Send an `Idempotency-Key` with each request, kept for its retries
+    pub idempotency_keys: bool,
+    /// Drop the oldest messages that do not fit in the context window before sending
+    pub auto_trim: bool,
+    /// Model of new chats, empty for [`ChatAPI::DEFAULT_MODEL`]
+    pub default_model: String,
+    /// Seconds between saves of the conversations, 0 to only save by hand
+    pub auto_save_interval: u64,
+    /// Save each conversation to its own file in `./chats/` instead of `./chats.json`
+    pub save_to_directory: bool,
+    /// Replace `{{date}}` and the other [`super::template::VARIABLES`] when sending
+    pub expand_templates: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            send_mode: SendMode::default(),
+            font_scale: 1.0,
+            proportional_font: String::new(),
+            monospace_font: String::new(),
+            theme: ThemeMode::default(),
+            accent: Accent::default(),
+            smooth_streaming: false,
+            proxy: ProxyConfig::default(),
+            api_key: String::new(),
+            confirm_destructive: true,
+            markdown_by_default: true,
+            density: Density::default(),
+            collapse_lines: 0,
+            auto_title: false,
+            max_concurrent_requests: MultiClient::DEFAULT_CONCURRENCY,
+            cache_responses: false,
+            stall_timeout: MultiClient::DEFAULT_STALL_TIMEOUT,
+            pool_max_idle_per_host: ConnectionOptions::default().pool_max_idle_per_host,
+            keep_alive: ConnectionOptions::default()
+                .keep_alive
+                .map_or(0, |interval| interval.as_secs()),
+            http2_only: false,
+            idempotency_keys: true,
+            auto_trim: false,
+            default_model: String::new(),
+            auto_save_interval: 0,
+            save_to_directory: false,
+            expand_templates: false,
+        }
+    }
+}
+
+impl Settings {
+    const KEY: &'static str = "settings";
+
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        storage
+            .and_then(|storage| eframe::get_value(storage, Self::KEY))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, Self::KEY, self);
+    }
+
+    /// The visuals for the selected theme and accent, `system_theme` is used
+    /// when following the system.
+    pub fn visuals(&self, system_theme: Option<eframe::Theme>) -> egui::Visuals {
+        let dark_mode = match self.theme {
+            ThemeMode::System => system_theme != Some(eframe::Theme::Light),
+            ThemeMode::Dark => true,
+            ThemeMode::Light => false,
+        };
+        let mut visuals = if dark_mode {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        };
+        let (selection, hyperlink) = self.accent.colors(dark_mode);
+        visuals.selection.bg_fill = selection;
+        visuals.hyperlink_color = hyperlink;
+        visuals
+    }
+}
+
+/// Edits the [`Settings`], applying them as they change.
+pub struct SettingsWindow {
+    settings: SharedSettings,
+    client: Arc<MultiClient>,
+    system_theme: Option<eframe::Theme>,
+    /// uri typed for [`ProxyConfig::Custom`], not applied until confirmed
+    proxy_uri: String,
+    proxy_error: Option<String>,
+}
+
+impl SettingsWindow {
+    /// `proxy_error` is why the saved proxy could not be used at startup.
+    pub fn new(
+        settings: SharedSettings,
+        client: Arc<MultiClient>,
+        system_theme: Option<eframe::Theme>,
+        proxy_error: Option<String>,
+    ) -> Self {
+        let proxy_uri = match &settings.borrow().proxy {
+            ProxyConfig::Custom(uri) => uri.clone(),
+            _ => String::new(),
+        };
+        Self {
+            settings,
+            client,
+            system_theme,
+            proxy_uri,
+            proxy_error,
+        }
+    }
+}
+
+impl super::Window for SettingsWindow {
+    fn name(&self) -> &'static str {
+        "Settings"
+    }
+    fn show(&mut self, ctx: &egui::Context, open: &mut bool) {
+        egui::Window::new(self.name()).open(open).show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| self.ui(ui))
+        });
+    }
+}
+
+impl View for SettingsWindow {
+    type Response = ();
+    fn ui(&mut self, ui: &mut egui::Ui) -> Self::Response {
+        let mut settings = self.settings.borrow_mut();
+        ui.label("Send message");
+        for mode in SendMode::iter() {
+            ui.selectable_value(&mut settings.send_mode, mode, mode.to_string());
+        }
+        ui.checkbox(
+            &mut settings.confirm_destructive,
+            "Confirm before clearing or removing",
+        );
+        ui.checkbox(&mut settings.markdown_by_default, "Markdown by default")
+            .on_hover_text("Render markdown in new conversations");
+        ui.horizontal(|ui| {
+            ui.label("Messages");
+            for density in Density::iter() {
+                ui.selectable_value(&mut settings.density, density, density.to_string())
+                    .on_hover_text("How new chat windows show the messages");
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut settings.collapse_lines, 0..=200).text("Collapse after (lines)"),
+        )
+        .on_hover_text("Show the start of longer messages with a toggle, 0 to show them whole");
+        ui.horizontal(|ui| {
+            ui.label("Default model");
+            ui.add(
+                egui::TextEdit::singleline(&mut settings.default_model)
+                    .hint_text(ChatAPI::DEFAULT_MODEL)
+                    .desired_width(150.),
+            )
+            .on_hover_text("Model of new chats");
+        });
+        ui.add(egui::Slider::new(&mut settings.auto_save_interval, 0..=600).text("Auto-save (s)"))
+            .on_hover_text("Save the conversations this often, 0 to only save from the File menu");
+        ui.checkbox(&mut settings.save_to_directory, "One file per conversation")
+            .on_hover_text(
+                "Save and load ./chats/ with a file for each conversation, instead of ./chats.json",
+            );
+        ui.checkbox(&mut settings.auto_title, "Auto-title conversations")
+            .on_hover_text(
+                "Name new conversations after their first reply, with one extra request",
+            );
+        ui.checkbox(&mut settings.expand_templates, "Template variables")
+            .on_hover_text(super::template::VARIABLES.iter().fold(
+                "Replace when sending:".to_string(),
+                |text, (name, value)| format!("{}\n{{{{{}}}}}: {}", text, name, value),
+            ));
+        ui.checkbox(&mut settings.auto_trim, "Trim to the context window")
+            .on_hover_text(
+                "Drop the oldest messages before sending when the conversation is too long for the model",
+            );
+        ui.checkbox(&mut settings.smooth_streaming, "Smooth streaming")
+            .on_hover_text("Show a typing cursor and redraw the reply at most 30 times per second");
+        if ui
+            .add(
+                egui::Slider::new(&mut settings.max_concurrent_requests, 1..=10)
+                    .text("Concurrent requests"),
+            )
+            .on_hover_text("More generations wait in a queue, to stay under rate limits")
+            .changed()
+        {
+            self.client
+                .set_concurrency(settings.max_concurrent_requests);
+        }
+        if ui
+            .add(egui::Slider::new(&mut settings.stall_timeout, 5..=300).text("Stall timeout (s)"))
+            .on_hover_text("Give up a reply when no token arrives for this long")
+            .changed()
+        {
+            self.client.set_stall_timeout(settings.stall_timeout);
+        }
+        if ui
+            .checkbox(&mut settings.idempotency_keys, "Idempotency keys")
+            .on_hover_text(
+                "Send a key with each request, so its retries are not generated and billed twice",
+            )
+            .changed()
+        {
+            self.client.set_idempotency_keys(settings.idempotency_keys);
+        }
+        ui.add(
+            egui::Slider::new(&mut settings.pool_max_idle_per_host, 0..=32)
+                .text("Idle connections"),
+        )
+        .on_hover_text("Connections kept open between requests, so the next ones start sooner");
+        ui.add(egui::Slider::new(&mut settings.keep_alive, 0..=300).text("Keep-alive (s)"))
+            .on_hover_text("How often the open connections are checked, 0 to never");
+        ui.checkbox(&mut settings.http2_only, "HTTP/2 only")
+            .on_hover_text(
+                "Skip HTTP/1.1, fails with the servers and proxies that do not speak HTTP/2",
+            );
+        let options = self.client.connection_options();
+        if options.pool_max_idle_per_host != settings.pool_max_idle_per_host
+            || options.keep_alive.map_or(0, |interval| interval.as_secs()) != settings.keep_alive
+            || options.http2_only != settings.http2_only
+        {
+            ui.weak("The connection settings apply after a restart");
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut settings.cache_responses, "Cache replies")
+                .on_hover_text(
+                    "Replay identical requests sent with a temperature of 0 instead of paying for them again",
+                )
+                .changed()
+            {
+                self.client.cache().set_enabled(settings.cache_responses);
+            }
+            let cache = self.client.cache();
+            if ui.button(format!("Clear ({})", cache.count())).clicked() {
+                if let Err(e) = cache.clear() {
+                    tracing::error!("failed to clear the cache: {}", e);
+                }
+            }
+        });
+        ui.separator();
+        if ui
+            .add(egui::Slider::new(&mut settings.font_scale, 0.5..=3.0).text("Font size"))
+            .changed()
+        {
+            super::setup_text_styles(ui.ctx(), settings.font_scale);
+        }
+        ui.separator();
+        ui.label("Proportional font");
+        ui.text_edit_singleline(&mut settings.proportional_font);
+        ui.label("Monospace font");
+        ui.text_edit_singleline(&mut settings.monospace_font);
+        if ui.button("Apply fonts").clicked() {
+            super::setup_fonts(
+                ui.ctx(),
+                &settings.proportional_font,
+                &settings.monospace_font,
+            );
+        }
+        ui.separator();
+        let mut visuals_changed = false;
+        ui.label("Theme");
+        for theme in ThemeMode::iter() {
+            visuals_changed |= ui
+                .selectable_value(&mut settings.theme, theme, theme.to_string())
+                .clicked();
+        }
+        ui.label("Accent");
+        ui.horizontal(|ui| {
+            for accent in Accent::iter() {
+                visuals_changed |= ui
+                    .selectable_value(&mut settings.accent, accent, accent.to_string())
+                    .clicked();
+            }
+        });
+        if visuals_changed {
+            ui.ctx().set_visuals(settings.visuals(self.system_theme));
+        }
+        ui.separator();
+        ui.label("Proxy");
+        let mut proxy = None;
+        ui.horizontal(|ui| {
+            if ui
+                .selectable_label(settings.proxy == ProxyConfig::System, "System")
+                .on_hover_text("HTTP_PROXY, or the system settings on Windows")
+                .clicked()
+            {
+                proxy = Some(ProxyConfig::System);
+            }
+            if ui
+                .selectable_label(settings.proxy == ProxyConfig::None, "No proxy")
+                .clicked()
+            {
+                proxy = Some(ProxyConfig::None);
+            }
+            if ui
+                .selectable_label(matches!(settings.proxy, ProxyConfig::Custom(_)), "Custom")
+                .clicked()
+            {
+                proxy = Some(ProxyConfig::Custom(self.proxy_uri.clone()));
+            }
+        });
+        if matches!(settings.proxy, ProxyConfig::Custom(_)) {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.proxy_uri)
+                        .hint_text("socks5://127.0.0.1:1080"),
+                );
+                if ui.button("Apply").clicked() {
+                    proxy = Some(ProxyConfig::Custom(self.proxy_uri.clone()));
+                }
+            });
+        }
+        if let Some(proxy) = proxy {
+            // the uri stays editable even when it is rejected
+            self.proxy_error = match self.client.set_proxy(&proxy) {
+                Ok(()) => None,
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    Some(e.to_string())
+                }
+            };
+            settings.proxy = proxy;
+        }
+        if let Some(error) = &self.proxy_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+/// The variables of [`variable`], with what they are replaced by.
+pub const VARIABLES: &[(&str, &str)] = &[
+    ("date", "today, e.g. 2024-01-31"),
+    ("time", "the current time, e.g. 13:45"),
+    ("datetime", "both, e.g. 2024-01-31 13:45"),
+    ("clipboard", "the text in the clipboard"),
+    ("selection", "the text last selected in the conversation"),
+];
+
+/// `text` with each `{{name}}` replaced by `lookup(name)`, the names it does
+/// not know are left as written.
+pub fn expand(text: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(close) = rest.find("}}") {
+        // the innermost braces, `{{{{date}}}}` keeps the outer ones
+        let Some(open) = rest[..close].rfind("{{") else {
+            expanded.push_str(&rest[..close + 2]);
+            rest = &rest[close + 2..];
+            continue;
+        };
+        expanded.push_str(&rest[..open]);
+        match lookup(rest[open + 2..close].trim()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[open..close + 2]),
+        }
+        rest = &rest[close + 2..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The value of one of the [`VARIABLES`] now.
+pub fn variable(name: &str, selection: Option<&str>) -> Option<String> {
+    let now = chrono::Local::now();
+    match name {
+        "date" => Some(now.format("%Y-%m-%d").to_string()),
+        "time" => Some(now.format("%H:%M").to_string()),
+        "datetime" => Some(now.format("%Y-%m-%d %H:%M").to_string()),
+        "clipboard" => match arboard::Clipboard::new().and_then(|mut c| c.get_text()) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                tracing::warn!("Could not read the clipboard: {}", e);
+                None
+            }
+        },
+        "selection" => selection.map(str::to_string),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_expand() {
+    let lookup = |name: &str| (name == "date").then(|| "2024-01-31".to_string());
+    assert_eq!(expand("Today is {{date}}.", lookup), "Today is 2024-01-31.");
+    assert_eq!(expand("{{ date }}{{date}}", lookup), "2024-01-312024-01-31");
+    assert_eq!(expand("{{unknown}} {{date", lookup), "{{unknown}} {{date");
+    assert_eq!(
+        expand("fn f() {{{{date}}}}", lookup),
+        "fn f() {{2024-01-31}}"
+    );
+    assert_eq!(expand("{}", lookup), "{}");
+}